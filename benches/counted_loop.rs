@@ -0,0 +1,57 @@
+//! Compares the specialized counted `for` loop with the general form on the same body.
+//!
+//! Run with `cargo bench --bench counted_loop`. Throughput is reported per loop iteration. Both
+//! scripts differ only in how the condition is written: `n > i` keeps the second loop off the
+//! specialized path, so it tests and updates the counter with the general instructions.
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use holo::gc::GC;
+use std::io;
+
+const ITERATIONS: u64 = 100000;
+
+const COUNTED: &str = "
+fun sum(n) {
+  var total = 0;
+  for (var i = 0; i < n; i = i + 1) {
+    total = total + i;
+  }
+  return total;
+}
+
+sum(100000);
+";
+
+const GENERAL: &str = "
+fun sum(n) {
+  var total = 0;
+  for (var i = 0; n > i; i = i + 1) {
+    total = total + i;
+  }
+  return total;
+}
+
+sum(100000);
+";
+
+fn run(source: &str) {
+    let mut output_stream = io::sink();
+    let mut err_stream = io::sink();
+
+    let mut vm = holo::compile(source, GC::new(), &mut output_stream, &mut err_stream)
+        .expect("benchmark script should compile");
+    vm.run().expect("benchmark script should run");
+}
+
+fn counted_loop(c: &mut Criterion) {
+    let mut group = c.benchmark_group("counted_loop");
+    group.throughput(Throughput::Elements(ITERATIONS));
+
+    group.bench_function("counted", |b| b.iter(|| run(COUNTED)));
+    group.bench_function("general", |b| b.iter(|| run(GENERAL)));
+
+    group.finish();
+}
+
+criterion_group!(benches, counted_loop);
+criterion_main!(benches);
@@ -0,0 +1,66 @@
+//! Measures how fast the VM dispatches instructions on compute-heavy scripts.
+//!
+//! Run with `cargo bench --bench dispatch`. Throughput is reported per call for `fib` and per
+//! loop iteration for `arithmetic_loop`, multiply it by the instructions each one executes (see
+//! `holo disasm`) to get instructions per second.
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use holo::gc::GC;
+use std::io;
+
+const FIB: &str = "
+fun fib(n) {
+  if (n < 2) return n;
+  return fib(n - 2) + fib(n - 1);
+}
+
+fib(20);
+";
+
+// The loop condition is written as `n > i` to keep it off the specialized counted loop path,
+// so every iteration goes through the general instructions
+const ARITHMETIC_LOOP: &str = "
+fun sum(n) {
+  var total = 0;
+  for (var i = 0; n > i; i = i + 1) {
+    total = total + i * 3 - i / 2;
+  }
+  return total;
+}
+
+sum(100000);
+";
+
+/// Number of calls `fib(n)` makes, including itself
+fn fib_calls(n: u64) -> u64 {
+    let (mut a, mut b) = (0, 1);
+    for _ in 0..=n {
+        (a, b) = (b, a + b);
+    }
+
+    2 * a - 1
+}
+
+fn run(source: &str) {
+    let mut output_stream = io::sink();
+    let mut err_stream = io::sink();
+
+    let mut vm = holo::compile(source, GC::new(), &mut output_stream, &mut err_stream)
+        .expect("benchmark script should compile");
+    vm.run().expect("benchmark script should run");
+}
+
+fn dispatch(c: &mut Criterion) {
+    let mut group = c.benchmark_group("dispatch");
+
+    group.throughput(Throughput::Elements(fib_calls(20)));
+    group.bench_function("fib", |b| b.iter(|| run(FIB)));
+
+    group.throughput(Throughput::Elements(100000));
+    group.bench_function("arithmetic_loop", |b| b.iter(|| run(ARITHMETIC_LOOP)));
+
+    group.finish();
+}
+
+criterion_group!(benches, dispatch);
+criterion_main!(benches);
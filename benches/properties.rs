@@ -0,0 +1,54 @@
+//! Measures property reads and writes on instances of one class, the case the property caches
+//! speed up.
+//!
+//! Run with `cargo bench --bench properties`. Throughput is reported per loop iteration, each of
+//! which reads three fields and writes one.
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use holo::gc::GC;
+use std::io;
+
+const ITERATIONS: u64 = 100000;
+
+const FIELDS: &str = "
+class Point {
+  init(x, y) {
+    this.x = x;
+    this.y = y;
+    this.z = 0;
+  }
+}
+
+fun sum(n) {
+  var point = Point(1, 2);
+  var total = 0;
+  for (var i = 0; n > i; i = i + 1) {
+    total = total + point.x + point.y + point.z;
+    point.z = i;
+  }
+  return total;
+}
+
+sum(100000);
+";
+
+fn run(source: &str) {
+    let mut output_stream = io::sink();
+    let mut err_stream = io::sink();
+
+    let mut vm = holo::compile(source, GC::new(), &mut output_stream, &mut err_stream)
+        .expect("benchmark script should compile");
+    vm.run().expect("benchmark script should run");
+}
+
+fn properties(c: &mut Criterion) {
+    let mut group = c.benchmark_group("properties");
+
+    group.throughput(Throughput::Elements(ITERATIONS));
+    group.bench_function("fields", |b| b.iter(|| run(FIELDS)));
+
+    group.finish();
+}
+
+criterion_group!(benches, properties);
+criterion_main!(benches);
@@ -0,0 +1,52 @@
+//! Measures how fast the scanner turns ASCII source into tokens, the common case.
+//!
+//! Run with `cargo bench --bench scanner`. Throughput is reported in bytes of source scanned.
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use holo::scanner::tokenize;
+
+// A mix of identifiers, keywords, numbers, strings, operators and comments, repeated to make a
+// source of a few hundred kilobytes
+const SNIPPET: &str = r#"
+// Sums the squares of the even numbers below `n`
+fun sum_even_squares(n) {
+  var total = 0;
+  for (var i = 0; i < n; i = i + 1) {
+    if (i & 1 == 0 and i >= 0) {
+      total = total + i * i;
+    }
+  }
+  return total;
+}
+
+/* Points with a label */
+class Point {
+  init(x, y) {
+    this.x = x;
+    this.y = y;
+    this.label = "point at {x}, {y}";
+  }
+
+  scale(factor) {
+    return Point(this.x * factor, this.y * 0.5);
+  }
+}
+
+print sum_even_squares(1000) >> 2 | 255;
+"#;
+
+const REPETITIONS: usize = 1000;
+
+fn scanner(c: &mut Criterion) {
+    let source = SNIPPET.repeat(REPETITIONS);
+
+    let mut group = c.benchmark_group("scanner");
+
+    group.throughput(Throughput::Bytes(source.len() as u64));
+    group.bench_function("ascii", |b| b.iter(|| tokenize(&source).len()));
+
+    group.finish();
+}
+
+criterion_group!(benches, scanner);
+criterion_main!(benches);
@@ -1,4 +1,4 @@
-use crate::value::Value;
+use crate::value::{Class, Value};
 
 /// Represents an opcode in `Holo`'s instruction set
 #[repr(u8)]
@@ -23,9 +23,7 @@ pub enum OpCode {
     Sub,
     Mult,
     Divide,
-    /// Evaluates a ternary expression (`condition ? true_value : false_value`)
-    Ternary,
-    /// Logical negation
+    /// Logical negation of the truthiness of any value, see [`Value::is_truthy`]
     Not,
     Equal,
     NotEqual,
@@ -44,12 +42,16 @@ pub enum OpCode {
     /// Define a global variable
     DefineGlobalLong,
     /// GET_GLOBAL <index: u8>
+    /// Rewrites itself into `GET_DEFINED_GLOBAL` once it finds the global defined
     GetGlobal,
     /// GET_GLOBAL <index: u24>
+    /// Rewrites itself into `GET_DEFINED_GLOBAL_LONG` once it finds the global defined
     GetGlobalLong,
     /// SET_GLOBAL <index: u8>
+    /// Rewrites itself into `SET_DEFINED_GLOBAL` once it finds the global defined
     SetGlobal,
     /// SET_GLOBAL <index: u24>
+    /// Rewrites itself into `SET_DEFINED_GLOBAL_LONG` once it finds the global defined
     SetGlobalLong,
     /// GET_LOCAL <index: u8>
     GetLocal,
@@ -64,10 +66,12 @@ pub enum OpCode {
     /// POP_N <count: u24>
     PopNLong,
     /// JUMP_IF_FALSE <offset: u16>
-    /// Jumps forward by the given offset if the top value on the stack is false
+    /// Jumps forward by the given offset if the top value on the stack is falsey (`nil` or
+    /// `false`)
     JumpIfFalse,
     /// JUMP_IF_TRUE <offset: u16>
-    /// Jumps forward by the given offset if the top value on the stack is true
+    /// Jumps forward by the given offset if the top value on the stack is truthy, anything but
+    /// `nil` and `false`
     JumpIfTrue,
     /// JUMP <offset: u16>
     /// Jumps forward by the given offset
@@ -107,13 +111,13 @@ pub enum OpCode {
     /// Produces a new class object. The class's name is stored at `index` in the chunk's constant
     /// table
     Class,
-    /// GET_PROPERTY <index: u8>
+    /// GET_PROPERTY <index: u8> <cache: u8>
     /// Gets a property from the object at the top of the stack. The property's name is stored at
-    /// `index` in the chunk's constant table
+    /// `index` in the chunk's constant table, `cache` indexes the chunk's property caches
     GetProperty,
-    /// SET_PROPERTY <index: u8>
+    /// SET_PROPERTY <index: u8> <cache: u8>
     /// Sets a property on the object at the top of the stack. The property's name is stored at
-    /// `index` in the chunk's constant table
+    /// `index` in the chunk's constant table, `cache` indexes the chunk's property caches
     SetProperty,
     /// METHOD <index: u8>
     /// Defines a method of a class. The method's name is stored at `index` in the chunk's constant
@@ -139,15 +143,117 @@ pub enum OpCode {
     /// right below it. Accesses using `this` in the superclass method will resolve to the
     /// subclass instance
     SuperInvoke,
-    /// STRINGIFY
-    /// Replaces the top value on the stack with its string representation. Used to coerce
-    /// interpolated expressions to strings before concatenation
-    Stringify,
+    /// CONCAT <count: u8>
+    /// Pops the top `count` values off the stack and pushes one string joining their string
+    /// representations in the order they were pushed. Used to build interpolated strings
+    Concat,
+    /// FOR_NUM <counter: u8> <bound: u8> <offset: u16>
+    /// Tests the condition of a counted `for` loop. Jumps forward by the given offset unless the
+    /// local at `counter` is less than the local at `bound`
+    ForNum,
+    /// INCREMENT_LOCAL <index: u8>
+    /// Adds one to the local at the given index. Used for the update of a counted `for` loop
+    IncrementLocal,
+    /// PRINT_N <count: u8> <newline: u8>
+    /// Pops the top `count` values off the stack and prints them to standard output, separated by
+    /// spaces in the order they were pushed. Ends the line unless `newline` is 0
+    PrintN,
+    /// TAIL_CALL <arg_count: u8>
+    /// Like `CALL`, but a closure callee replaces the current function and reuses its call frame.
+    /// Always followed by a `RETURN`, which returns the result of any other callee
+    TailCall,
+    /// FOR_IN <iterable: u8> <index: u8> <offset: u16>
+    /// Advances a `for (item in iterable)` loop. Pushes the item of the list at `iterable` at the
+    /// position held by the local at `index` and increments it, or jumps forward by the given
+    /// offset once the list is exhausted
+    ForIn,
+    /// GET_DEFINED_GLOBAL <index: u8>
+    /// Like `GET_GLOBAL`, for a global the compiler proved to be defined or that
+    /// a `GET_GLOBAL` found defined. Globals are never undefined again
+    GetDefinedGlobal,
+    /// GET_DEFINED_GLOBAL_LONG <index: u24>
+    /// Like `GET_GLOBAL_LONG`, for a global the compiler proved to be defined or that
+    /// a `GET_GLOBAL_LONG` found defined. Globals are never undefined again
+    GetDefinedGlobalLong,
+    /// SET_DEFINED_GLOBAL <index: u8>
+    /// Like `SET_GLOBAL`, for a global the compiler proved to be defined or that
+    /// a `SET_GLOBAL` found defined. Globals are never undefined again
+    SetDefinedGlobal,
+    /// SET_DEFINED_GLOBAL_LONG <index: u24>
+    /// Like `SET_GLOBAL_LONG`, for a global the compiler proved to be defined or that
+    /// a `SET_GLOBAL_LONG` found defined. Globals are never undefined again
+    SetDefinedGlobalLong,
+    /// Bitwise complement of the integer at the top of the stack
+    BitNot,
+    /// Shifts the integer below the top of the stack left by the integer at the top. Only the
+    /// low 6 bits of the shift count are used
+    ShiftLeft,
+    /// Arithmetic right shift, the counterpart of `SHIFT_LEFT`
+    ShiftRight,
+    /// Bitwise and of the top two integers on the stack
+    BitAnd,
+    /// Bitwise or of the top two integers on the stack
+    BitOr,
+    /// Bitwise exclusive or of the top two integers on the stack
+    BitXor,
+    /// POOL_CONSTANT <index: u8>
+    /// Produces the number stored at `index` in the program's constant pool
+    PoolConstant,
+    /// POOL_CONSTANT_LONG <index: u24>
+    /// Produces the number stored at `index` in the program's constant pool
+    PoolConstantLong,
+    /// LEN
+    /// Compiled from `len(x)`. Replaces the callee and its argument with the number of characters
+    /// of a string or items of a list if the callee is the `len` native, otherwise calls it
+    Len,
+    /// GET_PROPERTY_OR_NIL <index: u8> <cache: u8>
+    /// Like `GET_PROPERTY`, but produces `nil` instead of an error if the object has neither a
+    /// field nor a method of that name. Compiled from `?.`
+    GetPropertyOrNil,
+    /// JUMP_IF_NIL <offset: u16>
+    /// Jumps forward by `offset` if the value at the top of the stack is `nil`, leaving it there.
+    /// Skips the rest of a chain after a `?.` on `nil`
+    JumpIfNil,
+    /// BUILD_LIST <count: u8>
+    /// Replaces the top `count` values on the stack with a list of them, in stack order. Compiled
+    /// from `return a, b;`
+    BuildList,
+    /// UNPACK <count: u8>
+    /// Replaces the list at the top of the stack with its first `count` items, padded with `nil`.
+    /// Fails if the list has more items. Any other value counts as a list of itself. Compiled
+    /// from `var (a, b) = ...;`
+    Unpack,
+    /// COLLECT_REST <count: u8>
+    /// Replaces the arguments of the current call after the first `count` with a list of them.
+    /// Starts the body of a function with a `...rest` parameter
+    CollectRest,
+    /// GETTER <index: u8>
+    /// Like `METHOD`, but defines a getter, a method without parameters that runs when the
+    /// property of its name is read
+    Getter,
+    /// STATIC_METHOD <index: u8>
+    /// Like `METHOD`, but defines a static method, which is called on the class rather than on
+    /// its instances
+    StaticMethod,
 }
 
-impl From<u8> for OpCode {
-    fn from(value: u8) -> Self {
-        match value {
+/// A byte that does not encode any [`OpCode`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidOpcode(pub u8);
+
+impl std::fmt::Display for InvalidOpcode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid opcode {}", self.0)
+    }
+}
+
+impl TryFrom<u8> for OpCode {
+    type Error = InvalidOpcode;
+
+    // Decoding happens once per instruction in the dispatch loop, which it must not call out of
+    #[inline(always)]
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        let opcode = match value {
             0 => Self::Constant,
             1 => Self::ConstantLong,
             2 => Self::Nil,
@@ -159,51 +265,77 @@ impl From<u8> for OpCode {
             8 => Self::Sub,
             9 => Self::Mult,
             10 => Self::Divide,
-            11 => Self::Ternary,
-            12 => Self::Not,
-            13 => Self::Equal,
-            14 => Self::NotEqual,
-            15 => Self::Greater,
-            16 => Self::GreaterEqual,
-            17 => Self::Less,
-            18 => Self::LessEqual,
-            19 => Self::Print,
-            20 => Self::Pop,
-            21 => Self::DefineGlobal,
-            22 => Self::DefineGlobalLong,
-            23 => Self::GetGlobal,
-            24 => Self::GetGlobalLong,
-            25 => Self::SetGlobal,
-            26 => Self::SetGlobalLong,
-            27 => Self::GetLocal,
-            28 => Self::GetLocalLong,
-            29 => Self::SetLocal,
-            30 => Self::SetLocalLong,
-            31 => Self::PopN,
-            32 => Self::PopNLong,
-            33 => Self::JumpIfFalse,
-            34 => Self::JumpIfTrue,
-            35 => Self::Jump,
-            36 => Self::Loop,
-            37 => Self::Call,
-            38 => Self::Closure,
-            39 => Self::ClosureLong,
-            40 => Self::GetUpvalue,
-            41 => Self::GetUpvalueLong,
-            42 => Self::SetUpvalue,
-            43 => Self::SetUpvalueLong,
-            44 => Self::CloseUpvalue,
-            45 => Self::Class,
-            46 => Self::GetProperty,
-            47 => Self::SetProperty,
-            48 => Self::Method,
-            49 => Self::Invoke,
-            50 => Self::Inherit,
-            51 => Self::GetSuper,
-            52 => Self::SuperInvoke,
-            53 => Self::Stringify,
-            _ => unreachable!("invalid opcode!"),
-        }
+            11 => Self::Not,
+            12 => Self::Equal,
+            13 => Self::NotEqual,
+            14 => Self::Greater,
+            15 => Self::GreaterEqual,
+            16 => Self::Less,
+            17 => Self::LessEqual,
+            18 => Self::Print,
+            19 => Self::Pop,
+            20 => Self::DefineGlobal,
+            21 => Self::DefineGlobalLong,
+            22 => Self::GetGlobal,
+            23 => Self::GetGlobalLong,
+            24 => Self::SetGlobal,
+            25 => Self::SetGlobalLong,
+            26 => Self::GetLocal,
+            27 => Self::GetLocalLong,
+            28 => Self::SetLocal,
+            29 => Self::SetLocalLong,
+            30 => Self::PopN,
+            31 => Self::PopNLong,
+            32 => Self::JumpIfFalse,
+            33 => Self::JumpIfTrue,
+            34 => Self::Jump,
+            35 => Self::Loop,
+            36 => Self::Call,
+            37 => Self::Closure,
+            38 => Self::ClosureLong,
+            39 => Self::GetUpvalue,
+            40 => Self::GetUpvalueLong,
+            41 => Self::SetUpvalue,
+            42 => Self::SetUpvalueLong,
+            43 => Self::CloseUpvalue,
+            44 => Self::Class,
+            45 => Self::GetProperty,
+            46 => Self::SetProperty,
+            47 => Self::Method,
+            48 => Self::Invoke,
+            49 => Self::Inherit,
+            50 => Self::GetSuper,
+            51 => Self::SuperInvoke,
+            52 => Self::Concat,
+            53 => Self::ForNum,
+            54 => Self::IncrementLocal,
+            55 => Self::PrintN,
+            56 => Self::TailCall,
+            57 => Self::ForIn,
+            58 => Self::GetDefinedGlobal,
+            59 => Self::GetDefinedGlobalLong,
+            60 => Self::SetDefinedGlobal,
+            61 => Self::SetDefinedGlobalLong,
+            62 => Self::BitNot,
+            63 => Self::ShiftLeft,
+            64 => Self::ShiftRight,
+            65 => Self::BitAnd,
+            66 => Self::BitOr,
+            67 => Self::BitXor,
+            68 => Self::PoolConstant,
+            69 => Self::PoolConstantLong,
+            70 => Self::Len,
+            71 => Self::GetPropertyOrNil,
+            72 => Self::JumpIfNil,
+            73 => Self::BuildList,
+            74 => Self::Unpack,
+            75 => Self::CollectRest,
+            76 => Self::Getter,
+            77 => Self::StaticMethod,
+            _ => return Err(InvalidOpcode(value)),
+        };
+
+        Ok(opcode)
     }
 }
 
@@ -220,12 +352,32 @@ pub struct LineInfo {
     line: usize,
 }
 
+/// The class of the last instance a property instruction found the field on and the field's slot
+/// in it. Instances of the same class keep the field in the same slot, so the next one can skip
+/// looking it up by name
+#[derive(Debug, Clone, Copy)]
+pub struct PropertyCache {
+    pub class: *const Class,
+    pub slot: usize,
+}
+
+impl Default for PropertyCache {
+    fn default() -> Self {
+        Self {
+            class: std::ptr::null(),
+            slot: 0,
+        }
+    }
+}
+
 /// Represents a piece of compiled bytecode, associated constants and line information.
 #[derive(Debug)]
 pub struct Chunk {
     pub code: Vec<u8>,
     pub constants: Vec<Value>,
     pub line_info: Vec<LineInfo>,
+    /// One cache per property instruction, see [`PropertyCache`]
+    pub property_caches: Vec<PropertyCache>,
 }
 
 impl Chunk {
@@ -234,6 +386,7 @@ impl Chunk {
             code: vec![],
             constants: vec![],
             line_info: vec![],
+            property_caches: vec![],
         }
     }
 
@@ -262,6 +415,17 @@ impl Chunk {
         }
     }
 
+    /// Removes the bytes from `at` on and returns them with the line of each, ready to be written
+    /// again elsewhere by `write_bytes`
+    pub fn split_off(&mut self, at: usize) -> (Vec<u8>, Vec<usize>) {
+        let lines = (at..self.code.len())
+            .map(|byte_idx| self.get_line_of(byte_idx))
+            .collect();
+
+        self.line_info.retain(|info| info.byte_idx < at);
+        (self.code.split_off(at), lines)
+    }
+
     pub fn write_opcode(&mut self, opcode: OpCode, line: usize) {
         self.write_byte(opcode.into(), line);
     }
@@ -282,13 +446,19 @@ impl Chunk {
     }
 
     pub fn write_int16(&mut self, value: usize, line: usize) {
-        const MASK: usize = (1usize << 8) - 1;
-        let mut bytes: [u8; 2] = [0; 2];
+        self.write_bytes(&Self::int16_bytes(value), &[line; 2]);
+    }
 
-        bytes[1] = (value & MASK) as u8;
-        bytes[0] = ((value >> 8) & MASK) as u8;
+    /// Overwrites the two bytes at `offset`, written by `write_int16`, with `value`
+    pub fn patch_int16(&mut self, offset: usize, value: usize) {
+        self.code[offset..offset + 2].copy_from_slice(&Self::int16_bytes(value));
+    }
+
+    /// Splits the low 16 bits of `value` into big-endian bytes, the order `read_int16` expects
+    fn int16_bytes(value: usize) -> [u8; 2] {
+        const MASK: usize = (1usize << 8) - 1;
 
-        self.write_bytes(&bytes, &[line; 2]);
+        [((value >> 8) & MASK) as u8, (value & MASK) as u8]
     }
 
     pub fn add_constant(&mut self, value: Value) -> usize {
@@ -296,6 +466,12 @@ impl Chunk {
         self.constants.len() - 1
     }
 
+    /// Adds an empty property cache and returns its index
+    pub fn add_property_cache(&mut self) -> usize {
+        self.property_caches.push(PropertyCache::default());
+        self.property_caches.len() - 1
+    }
+
     pub fn read_int24(bytes: &[u8]) -> usize {
         let a = bytes[0] as usize;
         let b = bytes[1] as usize;
@@ -311,10 +487,15 @@ impl Chunk {
         (a << 8) | b
     }
 
+    /// Returns the source line of the byte at `byte_idx`, or 0 if the chunk is empty.
+    ///
+    /// The first entry of `line_info` always starts at byte 0, so every offset of a non-empty
+    /// chunk falls in some entry
     pub fn get_line_of(&self, byte_idx: usize) -> usize {
         let high = self.line_info.partition_point(|x| x.byte_idx <= byte_idx);
 
-        self.line_info[high - 1].line
+        high.checked_sub(1)
+            .map_or(0, |entry| self.line_info[entry].line)
     }
 }
 
@@ -353,6 +534,36 @@ mod tests {
         assert_eq!(result, value);
     }
 
+    #[test]
+    fn test_patch_int16() {
+        let mut chunk = Chunk::new();
+        chunk.write_opcode(OpCode::Jump, 1);
+        chunk.write_int16(0, 1);
+
+        chunk.patch_int16(1, 0xABCD);
+
+        assert_eq!(Chunk::read_int16(&chunk.code[1..]), 0xABCD);
+        assert_eq!(chunk.code.len(), 3);
+    }
+
+    #[test]
+    fn test_opcode_roundtrip() {
+        let mut valid = 0;
+
+        for byte in 0..=u8::MAX {
+            match OpCode::try_from(byte) {
+                Ok(opcode) => {
+                    assert_eq!(u8::from(opcode), byte);
+                    valid += 1;
+                }
+                Err(err) => assert_eq!(err, InvalidOpcode(byte)),
+            }
+        }
+
+        // Opcodes are numbered contiguously from 0
+        assert_eq!(valid, u8::from(OpCode::StaticMethod) as usize + 1);
+    }
+
     #[test]
     fn test_line_info() {
         let mut chunk = Chunk::new();
@@ -366,4 +577,19 @@ mod tests {
         assert_eq!(chunk.get_line_of(2), 2);
         assert_eq!(chunk.get_line_of(3), 3);
     }
+
+    #[test]
+    fn test_line_info_boundaries() {
+        let mut chunk = Chunk::new();
+        assert_eq!(chunk.get_line_of(0), 0);
+
+        // The first byte is found even when the code starts past line 1
+        chunk.write_byte(0, 5);
+        chunk.write_byte(1, 7);
+
+        assert_eq!(chunk.get_line_of(0), 5);
+        assert_eq!(chunk.get_line_of(1), 7);
+        // Past the end, the last line still applies
+        assert_eq!(chunk.get_line_of(10), 7);
+    }
 }
@@ -1,6 +1,7 @@
 use super::{
     chunk::{Chunk, OpCode},
-    error::{CompileError, CompileErrorKind, Expected},
+    const_pool::ConstantPool,
+    error::{CompileError, CompileErrorKind, CompileWarning, CompileWarningKind, Expected},
     gc::GC,
     scanner::Scanner,
     sym_table::SymbolTable,
@@ -8,19 +9,33 @@ use super::{
     token::{Token, TokenKind},
     value::{Function, Value},
 };
+use std::fs;
 use std::io::Write;
+use std::path::{Path, PathBuf};
 
 type Result<'a, T> = std::result::Result<T, CompileError<'a>>;
 
+/// Number of errors written to the error stream in full before the rest are only counted
+pub const DEFAULT_MAX_ERRORS: usize = 20;
+
+/// Binding strength of the operators, from loosest to tightest.
+///
+/// Unlike C, the bitwise operators bind tighter than `==` and `!=` so that
+/// `a & mask == 0` tests `(a & mask) == 0`. They still bind looser than the
+/// comparisons, with `&` above `^` above `|`.
 #[repr(u8)]
-#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
 enum Precedence {
     None,
     Assignment,
     Or,
     And,
     Equality,
+    BitOr,
+    BitXor,
+    BitAnd,
     Comparison,
+    Shift,
     Term,
     Factor,
     Unary,
@@ -42,11 +57,15 @@ impl From<usize> for Precedence {
             2 => Precedence::Or,
             3 => Precedence::And,
             4 => Precedence::Equality,
-            5 => Precedence::Comparison,
-            6 => Precedence::Term,
-            7 => Precedence::Factor,
-            8 => Precedence::Unary,
-            9 => Precedence::Call,
+            5 => Precedence::BitOr,
+            6 => Precedence::BitXor,
+            7 => Precedence::BitAnd,
+            8 => Precedence::Comparison,
+            9 => Precedence::Shift,
+            10 => Precedence::Term,
+            11 => Precedence::Factor,
+            12 => Precedence::Unary,
+            13 => Precedence::Call,
             _ => Precedence::Primary,
         }
     }
@@ -62,22 +81,115 @@ impl std::ops::Add<usize> for Precedence {
 
 struct Local<'a> {
     name: &'a str,
+    token: Token<'a>, // Token the local was declared at
     depth: usize,
     initialized: bool,
     captured: bool,
+    used: bool,     // Whether the local is ever read
+    constant: bool, // Whether the local is declared by `const`
+    // Parameters of the function the local is declared with by `fun`, unless it is ever assigned
+    function: Option<Signature>,
+    // Direct calls of the local, with their argument counts. Checked once the scope ends
+    calls: Vec<(Token<'a>, u8)>,
 }
 
 impl<'a> Local<'a> {
-    fn new(name: &'a str, depth: usize, initialized: bool, captured: bool) -> Self {
+    fn new(
+        name: &'a str,
+        token: Token<'a>,
+        depth: usize,
+        initialized: bool,
+        captured: bool,
+    ) -> Self {
         Local {
             name,
+            token,
             depth,
             initialized,
             captured,
+            used: false,
+            constant: false,
+            function: None,
+            calls: Vec::new(),
+        }
+    }
+}
+
+/// The parameters of a function declared with `fun`, to check direct calls of it against
+#[derive(Clone, Copy)]
+struct Signature {
+    min: u8,
+    max: u8,
+    variadic: bool,
+}
+
+impl Signature {
+    fn of(function: &Function) -> Self {
+        Signature {
+            min: function.required_arity(),
+            max: function.arity,
+            variadic: function.variadic,
+        }
+    }
+
+    fn accepts(&self, arg_count: u8) -> bool {
+        arg_count >= self.min && (arg_count <= self.max || self.variadic)
+    }
+}
+
+/// What the compiler knows about the value of a global
+#[derive(Clone, Copy, Default)]
+enum GlobalBinding {
+    /// Never defined or assigned so far
+    #[default]
+    Unbound,
+    /// Defined once by `fun` and never assigned
+    Function(Signature),
+    /// Anything else, e.g. defined twice, assigned or a native
+    Rebound,
+}
+
+/// The files of a program, shared in turn by the compilers of the main file and of the files it
+/// imports
+#[derive(Default)]
+struct Imports {
+    paths: Vec<PathBuf>,        // Canonical path of each file, the main file first
+    names: Vec<String>,         // Path of each file as named by its importer, for messages
+    in_progress: Vec<usize>,    // Files whose compilation has started but not finished
+    owners: Vec<Option<usize>>, // File that declares each global, `None` for natives
+}
+
+impl Imports {
+    fn new(source_name: &str) -> Self {
+        let name = if source_name.is_empty() {
+            "<main>"
+        } else {
+            source_name
+        };
+
+        Imports {
+            paths: vec![fs::canonicalize(source_name).unwrap_or_default()],
+            names: vec![name.to_owned()],
+            in_progress: vec![0],
+            owners: Vec::new(),
         }
     }
 }
 
+/// A callee whose value is known when the call is compiled
+enum Callee {
+    Local(usize),
+    Global(usize),
+}
+
+/// The bound of a counted `for` loop
+enum CountedBound {
+    /// The slot of a local variable
+    Local(usize),
+    /// A number literal
+    Literal(Value),
+}
+
 type ParseFn<'a, 'b, W> = fn(&mut Compiler<'a, 'b, W>, bool) -> Result<'a, ()>;
 
 struct ParseRule<'a, 'b, W: Write> {
@@ -86,10 +198,11 @@ struct ParseRule<'a, 'b, W: Write> {
     precedence: Precedence,
 }
 
-struct LoopContext {
+struct LoopContext<'a> {
     loop_start: usize, // Start offset of the loop bytecode (condition or the update expression)
     scope_depth: usize, // Scope depth at the start of the loop
     break_jumps: Vec<usize>, // Jump statements to patch to the end of the loop
+    label: Option<&'a str>, // Label naming the loop, if any
 }
 
 struct Upvalue {
@@ -99,15 +212,21 @@ struct Upvalue {
 
 struct CompilerContext<'a> {
     function: Function,
-    loop_contexts: Vec<LoopContext>,
+    loop_contexts: Vec<LoopContext<'a>>,
     curr_depth: usize,
     locals: Vec<Local<'a>>,
     upvalues: Vec<Upvalue>,
     is_initializer: bool,
+    last_call: Option<usize>,
+    len_callee: Option<usize>,
 }
 
-struct ClassContext {
+struct ClassContext<'a> {
     has_superclass: bool,
+    /// Whether a static method of the class is being compiled, where there is no `this`
+    in_static_method: bool,
+    /// Names of the methods and getters declared so far, each with whether it is a getter
+    members: Vec<(&'a str, bool)>,
 }
 
 pub struct Compiler<'a, 'b, W: Write> {
@@ -120,24 +239,74 @@ pub struct Compiler<'a, 'b, W: Write> {
     function: Function,
     locals: Vec<Local<'a>>,
     curr_depth: usize,
-    loop_contexts: Vec<LoopContext>,
+    loop_contexts: Vec<LoopContext<'a>>,
     upvalues: Vec<Upvalue>,
     is_initializer: bool,
 
+    // Globals whose initializer is being compiled
+    globals_in_initializer: Vec<&'a str>,
+    // Offset of the last `Call` emitted in the current function
+    last_call: Option<usize>,
+    // End of the last instruction in the current function that loads the global `len`, so that
+    // `len(x)` can be compiled to `Len`
+    len_callee: Option<usize>,
+    // End of the last instruction that loads a variable, the variable and the token it is named
+    // by, so that a call right after it can be checked against the function the variable holds.
+    // Only set while the infix rule right after a lone identifier is compiled
+    direct_callee: Option<(usize, Callee, Token<'a>)>,
+    // Jumps emitted by `?.` that skip to the end of the enclosing property and call chain
+    optional_chain_jumps: Vec<usize>,
+    // Byte offset in the source where the left operand of the infix rule being compiled starts
+    operand_start: usize,
+    // Source offsets of the end of the last comparison and of its right operand, so that a
+    // comparison right after it, as in `1 < x < 10`, can be rejected
+    last_comparison: Option<(usize, usize)>,
+    // Whether each global is defined by the time the main function reaches the current token.
+    // Natives are defined from the start
+    defined_globals: Vec<bool>,
+    // References to globals that may be undefined when they run, with whether they are in the
+    // main function. Checked once all declarations are known
+    global_refs: Vec<(usize, Token<'a>, bool)>,
+    // What each global holds, for checking direct calls
+    global_bindings: Vec<GlobalBinding>,
+    // Direct calls of globals, with their argument counts. Checked once all globals are bound
+    global_calls: Vec<(usize, Token<'a>, u8)>,
+    // Whether each global is declared by `const`
+    const_globals: Vec<bool>,
+    // Assignments to globals not declared by `const` when they were compiled. Checked once all
+    // globals are declared
+    global_assignments: Vec<(usize, Token<'a>)>,
+
+    // Files of the program and the index of the one being compiled among them
+    imports: Imports,
+    file_id: usize,
+
     // Saved contexts for nested functions
     contexts: Vec<CompilerContext<'a>>,
-    class_contexts: Vec<ClassContext>,
+    class_contexts: Vec<ClassContext<'a>>,
 
     // Shared state
     gc: &'b mut GC,
     str_intern_table: &'b mut StringInternTable,
-    sym_table: &'b mut SymbolTable<'a>,
-    had_error: bool,
+    sym_table: &'b mut SymbolTable,
+    const_pool: &'b mut ConstantPool,
+    // Every error found so far, in the order they were found
+    errors: Vec<CompileError<'a>>,
+    // Whether errors are also written to `err_stream` as they are found
+    print_errors: bool,
+    // Number of errors written in full, the rest are summed up once compiling ends
+    max_errors: usize,
+    // Whether diagnostics are written as one line of JSON each rather than for people to read
+    json_errors: bool,
+    // Whether warnings about likely mistakes that are still valid code are reported
+    strict: bool,
     err_stream: &'b mut W,
+    // Nesting depth of the productions being traced, `None` unless tracing the parse
+    trace_depth: Option<usize>,
 }
 
 impl<'a, 'b, W: Write> Compiler<'a, 'b, W> {
-    const RULES: [ParseRule<'a, 'b, W>; 53] = [
+    const RULES: [ParseRule<'a, 'b, W>; 67] = [
         ParseRule {
             prefix_rule: Some(Self::grouping),
             infix_rule: Some(Self::call),
@@ -273,11 +442,56 @@ impl<'a, 'b, W: Write> Compiler<'a, 'b, W> {
             infix_rule: None,
             precedence: Precedence::None,
         }, // SlashEqual
+        ParseRule {
+            prefix_rule: Some(Self::unary),
+            infix_rule: None,
+            precedence: Precedence::None,
+        }, // Tilde
+        ParseRule {
+            prefix_rule: None,
+            infix_rule: Some(Self::binary),
+            precedence: Precedence::Shift,
+        }, // LessLess
+        ParseRule {
+            prefix_rule: None,
+            infix_rule: Some(Self::binary),
+            precedence: Precedence::Shift,
+        }, // GreaterGreater
+        ParseRule {
+            prefix_rule: None,
+            infix_rule: Some(Self::binary),
+            precedence: Precedence::BitAnd,
+        }, // Ampersand
+        ParseRule {
+            prefix_rule: None,
+            infix_rule: Some(Self::binary),
+            precedence: Precedence::BitOr,
+        }, // Pipe
+        ParseRule {
+            prefix_rule: None,
+            infix_rule: Some(Self::binary),
+            precedence: Precedence::BitXor,
+        }, // Caret
+        ParseRule {
+            prefix_rule: None,
+            infix_rule: Some(Self::question_dot),
+            precedence: Precedence::Call,
+        }, // QuestionDot
+        ParseRule {
+            prefix_rule: None,
+            infix_rule: None,
+            precedence: Precedence::None,
+        }, // Ellipsis
         ParseRule {
             prefix_rule: Some(Self::variable),
             infix_rule: None,
             precedence: Precedence::None,
         }, // Identifier
+        ParseRule {
+            prefix_rule: None,
+            infix_rule: None,
+            precedence: Precedence::None,
+        }, // Label
         ParseRule {
             prefix_rule: Some(Self::string),
             infix_rule: None,
@@ -303,6 +517,11 @@ impl<'a, 'b, W: Write> Compiler<'a, 'b, W> {
             infix_rule: None,
             precedence: Precedence::None,
         }, // Number
+        ParseRule {
+            prefix_rule: Some(Self::number),
+            infix_rule: None,
+            precedence: Precedence::None,
+        }, // Integer
         ParseRule {
             prefix_rule: None,
             infix_rule: Some(Self::logical_and),
@@ -393,6 +612,26 @@ impl<'a, 'b, W: Write> Compiler<'a, 'b, W> {
             infix_rule: None,
             precedence: Precedence::None,
         }, // Continue
+        ParseRule {
+            prefix_rule: None,
+            infix_rule: None,
+            precedence: Precedence::None,
+        }, // LoopKw
+        ParseRule {
+            prefix_rule: None,
+            infix_rule: None,
+            precedence: Precedence::None,
+        }, // Import
+        ParseRule {
+            prefix_rule: None,
+            infix_rule: None,
+            precedence: Precedence::None,
+        }, // Const
+        ParseRule {
+            prefix_rule: None,
+            infix_rule: None,
+            precedence: Precedence::None,
+        }, // Static
         ParseRule {
             prefix_rule: None,
             infix_rule: None,
@@ -405,12 +644,15 @@ impl<'a, 'b, W: Write> Compiler<'a, 'b, W> {
         }, // Eof
     ];
 
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         source: &'a str,
+        source_name: &str,
         func_name: &str,
         gc: &'b mut GC,
         str_intern_table: &'b mut StringInternTable,
-        sym_table: &'b mut SymbolTable<'a>,
+        sym_table: &'b mut SymbolTable,
+        const_pool: &'b mut ConstantPool,
         err_stream: &'b mut W,
     ) -> Self {
         Compiler {
@@ -421,35 +663,93 @@ impl<'a, 'b, W: Write> Compiler<'a, 'b, W> {
                 lexeme: "",
                 line: 0,
                 column: 0,
+                span: 0..0,
             },
             prev_token: Token {
                 kind: TokenKind::Eof,
                 lexeme: "",
                 line: 0,
                 column: 0,
+                span: 0..0,
             },
             function: Function {
                 name: func_name.to_owned(),
+                source_name: source_name.to_owned(),
                 arity: 0,
                 upvalue_count: 0,
                 chunk: Chunk::new(),
+                object_constants: Vec::new(),
+                entry_points: Vec::new(),
+                variadic: false,
             },
             locals: Vec::new(),
             curr_depth: 0,
             loop_contexts: Vec::new(),
             upvalues: Vec::new(),
             is_initializer: false,
-            had_error: false,
+            globals_in_initializer: Vec::new(),
+            last_call: None,
+            len_callee: None,
+            direct_callee: None,
+            optional_chain_jumps: Vec::new(),
+            operand_start: 0,
+            last_comparison: None,
+            defined_globals: vec![true; sym_table.len()],
+            global_refs: Vec::new(),
+            global_bindings: Vec::new(),
+            global_calls: Vec::new(),
+            const_globals: Vec::new(),
+            global_assignments: Vec::new(),
+            imports: Imports::new(source_name),
+            file_id: 0,
+            errors: Vec::new(),
+            print_errors: false,
+            max_errors: DEFAULT_MAX_ERRORS,
+            json_errors: false,
+            strict: false,
             contexts: Vec::new(),
             class_contexts: Vec::new(),
             gc,
             str_intern_table,
             sym_table,
+            const_pool,
             err_stream,
+            trace_depth: None,
         }
     }
 
-    pub fn compile(mut self) -> Option<Function> {
+    /// Logs each production the parser enters and leaves to the error stream, indented by
+    /// nesting, to debug how a program is parsed
+    pub fn set_parse_trace(&mut self, enabled: bool) {
+        self.trace_depth = enabled.then_some(0);
+    }
+
+    /// Limits how many errors [`Compiler::compile_and_report`] writes in full. Compiling goes
+    /// on past the limit, and the errors left out are counted in a last line
+    pub fn set_max_errors(&mut self, max_errors: usize) {
+        self.max_errors = max_errors;
+    }
+
+    /// Writes errors and warnings as one line of JSON each, for editors and other tools. Every
+    /// error is written, regardless of [`Compiler::set_max_errors`]
+    pub fn set_json_errors(&mut self, enabled: bool) {
+        self.json_errors = enabled;
+    }
+
+    /// Also warns about likely mistakes that are valid code, such as the empty body of
+    /// `while (cond);`
+    pub fn set_strict(&mut self, enabled: bool) {
+        self.strict = enabled;
+    }
+
+    /// Compiles the program into its main function, or returns every compile error in it in
+    /// source order. Warnings are still written to the error stream
+    pub fn compile(mut self) -> std::result::Result<Function, Vec<CompileError<'a>>> {
+        self.compile_declarations();
+        self.finish()
+    }
+
+    fn compile_declarations(&mut self) {
         if let Err(err) = self.advance() {
             self.report_err(err);
 
@@ -465,11 +765,13 @@ impl<'a, 'b, W: Write> Compiler<'a, 'b, W> {
                 self.synchronize();
             }
         }
-
-        self.finish()
     }
 
     fn declaration(&mut self) -> Result<'a, ()> {
+        self.traced("declaration", Self::parse_declaration)
+    }
+
+    fn parse_declaration(&mut self) -> Result<'a, ()> {
         match self.curr_token.kind {
             TokenKind::Var => {
                 self.advance()?;
@@ -483,24 +785,44 @@ impl<'a, 'b, W: Write> Compiler<'a, 'b, W> {
                 self.advance()?;
                 self.class_declaration()
             }
+            TokenKind::Const => {
+                self.advance()?;
+                self.const_declaration()
+            }
+            TokenKind::Import => {
+                self.advance()?;
+                self.import_statement()
+            }
             _ => self.statement(),
         }
     }
 
     fn var_declaration(&mut self) -> Result<'a, ()> {
+        if self.check(TokenKind::LeftParen) {
+            self.advance()?;
+            return self.destructuring_var_declaration();
+        }
+
         self.consume(TokenKind::Identifier, Expected::VariableName)?;
 
         let name = self.prev_token.lexeme;
         let index = if self.curr_depth > 0 {
             self.declare_local(name)?
         } else {
-            self.sym_table.declare(name)
+            self.declare_global(name)?
         };
 
         // Consume the initializer, if any
         if self.check(TokenKind::Equal) {
             self.advance()?;
-            self.expression()?;
+
+            // Reject globals read in their own initializer, like locals
+            if self.curr_depth == 0 {
+                self.globals_in_initializer.push(name);
+            }
+            let initializer = self.expression();
+            self.globals_in_initializer.clear();
+            initializer?;
         } else {
             self.emit_opcode(OpCode::Nil);
         }
@@ -511,15 +833,230 @@ impl<'a, 'b, W: Write> Compiler<'a, 'b, W> {
             self.mark_as_initialized(index);
             Ok(())
         } else {
-            self.emit_opcode_with_num(
-                OpCode::DefineGlobal,
-                OpCode::DefineGlobalLong,
-                index,
-                CompileErrorKind::TooManyGlobals,
-            )
+            self.define_global(index, None)
+        }
+    }
+
+    /// Compiles `const NAME = value;`, a variable that can never be assigned after its declaration
+    fn const_declaration(&mut self) -> Result<'a, ()> {
+        self.consume(TokenKind::Identifier, Expected::ConstantName)?;
+
+        let name = self.prev_token.lexeme;
+        let index = if self.curr_depth > 0 {
+            self.declare_local(name)?
+        } else {
+            self.declare_global(name)?
+        };
+
+        self.consume(TokenKind::Equal, Expected::EqualAfterConstantName)?;
+
+        // Reject globals read in their own initializer, like locals
+        if self.curr_depth == 0 {
+            self.globals_in_initializer.push(name);
+        }
+        let initializer = self.expression();
+        self.globals_in_initializer.clear();
+        initializer?;
+
+        self.consume(TokenKind::Semicolon, Expected::Semicolon)?;
+
+        if self.curr_depth > 0 {
+            self.mark_as_initialized(index);
+            self.locals[index].constant = true;
+            Ok(())
+        } else {
+            self.define_global(index, None)?;
+
+            if self.const_globals.len() <= index {
+                self.const_globals.resize(index + 1, false);
+            }
+            self.const_globals[index] = true;
+            Ok(())
         }
     }
 
+    /// Compiles `var (a, b) = list;`, which binds the items of the list to the variables in order.
+    /// Variables without an item are bound to `nil`, and extra items are a runtime error
+    fn destructuring_var_declaration(&mut self) -> Result<'a, ()> {
+        let mut names = Vec::new();
+        let mut indices = Vec::new();
+
+        loop {
+            self.consume(TokenKind::Identifier, Expected::VariableName)?;
+
+            if names.len() == u8::MAX as usize {
+                return Err(CompileError::new(
+                    self.prev_token.clone(),
+                    CompileErrorKind::TooManyDestructuredVariables,
+                ));
+            }
+
+            let name = self.prev_token.lexeme;
+            let index = if self.curr_depth > 0 {
+                self.declare_local(name)?
+            } else {
+                self.declare_global(name)?
+            };
+
+            names.push(name);
+            indices.push(index);
+
+            if !self.check(TokenKind::Comma) {
+                break;
+            }
+            self.advance()?;
+        }
+
+        self.consume(
+            TokenKind::RightParen,
+            Expected::RightParenAfterVariableNames,
+        )?;
+        self.consume(TokenKind::Equal, Expected::EqualAfterVariableNames)?;
+
+        // Reject globals read in their own initializer, like locals
+        if self.curr_depth == 0 {
+            self.globals_in_initializer = names.clone();
+        }
+        let initializer = self.expression();
+        self.globals_in_initializer.clear();
+        initializer?;
+
+        self.emit_opcode(OpCode::Unpack);
+        self.emit_byte(names.len() as u8);
+        self.consume(TokenKind::Semicolon, Expected::Semicolon)?;
+
+        if self.curr_depth > 0 {
+            for index in indices {
+                self.mark_as_initialized(index);
+            }
+            Ok(())
+        } else {
+            // The last value is on top of the stack
+            for index in indices.into_iter().rev() {
+                self.define_global(index, None)?;
+            }
+            Ok(())
+        }
+    }
+
+    /// Compiles `import "path";`, which runs the top level of the file at the path, relative to
+    /// the importing file, in the global namespace. Each file is imported only once
+    fn import_statement(&mut self) -> Result<'a, ()> {
+        self.consume(TokenKind::String, Expected::ImportPath)?;
+        let token = self.prev_token.clone();
+        self.consume(TokenKind::Semicolon, Expected::Semicolon)?;
+
+        if !self.contexts.is_empty() || self.curr_depth > 0 {
+            return Err(CompileError::new(
+                token,
+                CompileErrorKind::ImportNotAtTopLevel,
+            ));
+        }
+
+        let dir = Path::new(&self.function.source_name)
+            .parent()
+            .unwrap_or(Path::new(""));
+        let path = dir.join(&token.lexeme[1..token.lexeme.len() - 1]);
+        let name = path.to_string_lossy().into_owned();
+
+        let read = fs::canonicalize(&path).and_then(|canonical| {
+            let source = fs::read_to_string(&canonical)?;
+            Ok((canonical, source))
+        });
+        let (canonical, source) = match read {
+            Ok(read) => read,
+            Err(err) => {
+                let reason = err.kind();
+                let kind = CompileErrorKind::ImportFailed { path: name, reason };
+                return Err(CompileError::new(token, kind));
+            }
+        };
+
+        match self
+            .imports
+            .paths
+            .iter()
+            .position(|path| *path == canonical)
+        {
+            Some(id) if self.imports.in_progress.contains(&id) => Err(CompileError::new(
+                token,
+                CompileErrorKind::CyclicImport(name),
+            )),
+            Some(_) => Ok(()),
+            None => self.compile_import(&source, name, canonical, token),
+        }
+    }
+
+    /// Compiles the imported `source` into a function and emits a call of it
+    fn compile_import(
+        &mut self,
+        source: &str,
+        name: String,
+        canonical: PathBuf,
+        token: Token<'a>,
+    ) -> Result<'a, ()> {
+        let file_id = self.imports.paths.len();
+        self.imports.paths.push(canonical);
+        self.imports.names.push(name.clone());
+        self.imports.in_progress.push(file_id);
+
+        let mut compiler = Compiler::new(
+            source,
+            &name,
+            "<main>",
+            &mut *self.gc,
+            &mut *self.str_intern_table,
+            &mut *self.sym_table,
+            &mut *self.const_pool,
+            &mut *self.err_stream,
+        );
+        compiler.print_errors = self.print_errors;
+        compiler.max_errors = self.max_errors;
+        compiler.json_errors = self.json_errors;
+        compiler.strict = self.strict;
+        compiler.file_id = file_id;
+        compiler.imports = std::mem::take(&mut self.imports);
+
+        // The importing file's globals are defined by the time the import runs
+        compiler.defined_globals = self.defined_globals.clone();
+        compiler.const_globals = self.const_globals.clone();
+
+        // Reserve the slot of the imported file's closure, which is called like a function
+        compiler
+            .locals
+            .push(Local::new("", token.clone(), 0, true, false));
+
+        compiler.compile_declarations();
+
+        self.imports = std::mem::take(&mut compiler.imports);
+        self.imports.in_progress.pop();
+        self.defined_globals = std::mem::take(&mut compiler.defined_globals);
+        self.const_globals = std::mem::take(&mut compiler.const_globals);
+        let bindings = std::mem::take(&mut compiler.global_bindings);
+
+        let Ok(function) = compiler.finish() else {
+            return Err(CompileError::new(
+                token,
+                CompileErrorKind::ImportErrors(name),
+            ));
+        };
+
+        // The imported file may define or assign any global it shares with this one
+        for (index, binding) in bindings.into_iter().enumerate() {
+            if !matches!(binding, GlobalBinding::Unbound) {
+                self.bind_global(index, GlobalBinding::Rebound);
+            }
+        }
+
+        let func_value = self.gc.alloc_function(function);
+        self.emit_opcode_with_constant_long(OpCode::Closure, OpCode::ClosureLong, func_value)?;
+        self.emit_opcode(OpCode::Call);
+        self.emit_byte(0);
+        self.emit_opcode(OpCode::Pop);
+
+        Ok(())
+    }
+
     fn fun_declaration(&mut self) -> Result<'a, ()> {
         self.consume(TokenKind::Identifier, Expected::FunctionName)?;
 
@@ -531,7 +1068,7 @@ impl<'a, 'b, W: Write> Compiler<'a, 'b, W> {
             self.mark_as_initialized(index);
             index
         } else {
-            self.sym_table.declare(name)
+            self.declare_global(name)?
         };
 
         // Save the current context
@@ -543,36 +1080,59 @@ impl<'a, 'b, W: Write> Compiler<'a, 'b, W> {
         self.mark_as_initialized(func_index);
 
         // Compile the function body
-        self.function()?;
+        let signature = self.function()?;
 
         // Define it as a variable
         if self.curr_depth > 0 {
             // Local variable
+            self.locals[index].function = Some(signature);
             Ok(())
         } else {
             // Global variable
-            self.emit_opcode_with_num(
-                OpCode::DefineGlobal,
-                OpCode::DefineGlobalLong,
-                index,
-                CompileErrorKind::TooManyGlobals,
-            )
+            self.define_global(index, Some(signature))
         }
     }
 
     /// Compiles a function signature and body, assumes the `fun` keyword has been consumed
     /// and a new scope has been created. The caller does not have to explicitly end the scope
     /// because this function will pop the new function's compilation context anyway
-    fn function(&mut self) -> Result<'a, ()> {
+    /// Compiles the `name` of a trailing `...name` parameter, which collects the surplus
+    /// arguments of a call into a list
+    fn rest_parameter(&mut self) -> Result<'a, ()> {
+        self.consume(TokenKind::Identifier, Expected::ParameterName)?;
+
+        let index = self.declare_local(self.prev_token.lexeme)?;
+        self.mark_as_initialized(index);
+        self.locals[index].used = true;
+        self.function.variadic = true;
+
+        if self.check(TokenKind::Comma) {
+            return Err(CompileError::new(
+                self.curr_token.clone(),
+                CompileErrorKind::ParameterAfterRest,
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn function(&mut self) -> Result<'a, Signature> {
         const MAX_PARAMS: u8 = 255;
 
         // Compile the parameter list
         self.consume(TokenKind::LeftParen, Expected::LeftParenAfterFunctionName)?;
 
         let mut arity: u8 = 0;
+        let mut entry_points = Vec::new();
 
         if !self.check(TokenKind::RightParen) {
             loop {
+                if self.check(TokenKind::Ellipsis) {
+                    self.advance()?;
+                    self.rest_parameter()?;
+                    break;
+                }
+
                 self.consume(TokenKind::Identifier, Expected::ParameterName)?;
 
                 if arity == MAX_PARAMS {
@@ -586,6 +1146,25 @@ impl<'a, 'b, W: Write> Compiler<'a, 'b, W> {
 
                 let name = self.prev_token.lexeme;
                 let index = self.declare_local(name)?;
+
+                // Parameters are never reported as unused
+                self.locals[index].used = true;
+
+                if self.check(TokenKind::Equal) {
+                    self.advance()?;
+
+                    // A call that leaves this parameter out starts here, the default is pushed
+                    // into the parameter's slot. The parameter is not initialized yet, so only
+                    // earlier parameters can be read
+                    entry_points.push(self.chunk().code.len());
+                    self.expression()?;
+                } else if !entry_points.is_empty() {
+                    return Err(CompileError::new(
+                        self.prev_token.clone(),
+                        CompileErrorKind::RequiredParameterAfterDefault(name.to_string()),
+                    ));
+                }
+
                 self.mark_as_initialized(index);
 
                 if !self.check(TokenKind::Comma) {
@@ -596,22 +1175,44 @@ impl<'a, 'b, W: Write> Compiler<'a, 'b, W> {
             }
         }
 
+        // A call with every argument starts at the body
+        if !entry_points.is_empty() {
+            entry_points.push(self.chunk().code.len());
+        }
+
+        // The rest parameter is collected once every other parameter has its value
+        if self.function.variadic {
+            self.emit_opcode(OpCode::CollectRest);
+            self.emit_byte(arity);
+        }
+
         self.function.arity = arity;
+        self.function.entry_points = entry_points;
         self.consume(TokenKind::RightParen, Expected::RightParenAfterParameters)?;
 
-        // Compile the body
+        self.function_body()
+    }
+
+    /// Compiles the body of the function of the current context once its parameters are declared
+    /// and emits its closure
+    fn function_body(&mut self) -> Result<'a, Signature> {
         self.consume(TokenKind::LeftBrace, Expected::LeftBraceBeforeFunctionBody)?;
         self.block()?;
 
         // Implicit return
         self.emit_return()?;
 
+        // The function's outermost scope is discarded with its context rather than ended
+        self.warn_unused_locals(0);
+        self.check_local_calls(0);
+
         // Restore the previous context
         let upvalues = std::mem::take(&mut self.upvalues);
         let mut function = self.pop_context();
 
         // Fill in the upvalue count
         function.upvalue_count = upvalues.len();
+        let signature = Signature::of(&function);
 
         // Allocate the function value
         let func_value = self.gc.alloc_function(function);
@@ -635,7 +1236,7 @@ impl<'a, 'b, W: Write> Compiler<'a, 'b, W> {
             self.emit_byte(upvalue.index as u8);
         }
 
-        Ok(())
+        Ok(signature)
     }
 
     /// Compiles a class declaration, assumes the `class` keyword has been consumed
@@ -653,16 +1254,13 @@ impl<'a, 'b, W: Write> Compiler<'a, 'b, W> {
         // Define it as a variable
         if self.curr_depth == 0 {
             // Global variable
-            self.emit_opcode_with_num(
-                OpCode::DefineGlobal,
-                OpCode::DefineGlobalLong,
-                index,
-                CompileErrorKind::TooManyGlobals,
-            )?;
+            self.define_global(index, None)?;
         }
 
         self.class_contexts.push(ClassContext {
             has_superclass: false,
+            in_static_method: false,
+            members: Vec::new(),
         });
 
         // Compile the superclass, if any
@@ -723,10 +1321,29 @@ impl<'a, 'b, W: Write> Compiler<'a, 'b, W> {
 
     /// Compiles a method declaration
     fn method_declaration(&mut self) -> Result<'a, ()> {
+        if self.check(TokenKind::Static) {
+            self.advance()?;
+            return self.static_method_declaration();
+        }
+
         // Method declarations don't begin with `fun` keyword
         self.consume(TokenKind::Identifier, Expected::MethodName)?;
 
         let method_name = self.prev_token.lexeme;
+
+        // A getter has no parameter list, the initializer always has one
+        let is_getter = method_name != "init" && self.check(TokenKind::LeftBrace);
+        let class = self.class_contexts.last_mut().unwrap();
+        let clashes = class.members.contains(&(method_name, !is_getter));
+        class.members.push((method_name, is_getter));
+
+        // Reading the property would run the getter, so the method could never be called. The
+        // declaration still compiles, it is well-formed
+        if clashes {
+            let kind = CompileErrorKind::GetterAndMethod(method_name.to_string());
+            self.report_err(CompileError::new(self.prev_token.clone(), kind));
+        }
+
         let method_name_ptr = self.str_intern_table.intern_slice(method_name, self.gc);
 
         // Save the current context
@@ -737,15 +1354,47 @@ impl<'a, 'b, W: Write> Compiler<'a, 'b, W> {
         let this_index = self.declare_local("this")?;
         self.mark_as_initialized(this_index);
 
-        // Compile the function body
-        self.function()?;
+        let opcode = if is_getter {
+            self.function_body()?;
+            OpCode::Getter
+        } else {
+            self.function()?;
+            OpCode::Method
+        };
 
         // At this point the method's closure is on the stack with the
         // parent class directly below it
-        self.emit_opcode_with_constant(OpCode::Method, Value::String(method_name_ptr))
+        self.emit_opcode_with_constant(opcode, Value::String(method_name_ptr))
+    }
+
+    /// Compiles a static method declaration, assumes the `static` keyword has been consumed. A
+    /// static method is called on the class itself, so it has no `this`
+    fn static_method_declaration(&mut self) -> Result<'a, ()> {
+        self.consume(TokenKind::Identifier, Expected::MethodName)?;
+
+        let method_name = self.prev_token.lexeme;
+        let method_name_ptr = self.str_intern_table.intern_slice(method_name, self.gc);
+
+        self.push_context(method_name, false);
+        self.begin_scope();
+
+        // Reserve a slot for the method itself, like for a function
+        let func_index = self.declare_local("")?;
+        self.mark_as_initialized(func_index);
+
+        self.class_contexts.last_mut().unwrap().in_static_method = true;
+        let result = self.function();
+        self.class_contexts.last_mut().unwrap().in_static_method = false;
+        result?;
+
+        self.emit_opcode_with_constant(OpCode::StaticMethod, Value::String(method_name_ptr))
     }
 
     fn statement(&mut self) -> Result<'a, ()> {
+        self.traced("statement", Self::parse_statement)
+    }
+
+    fn parse_statement(&mut self) -> Result<'a, ()> {
         match self.curr_token.kind {
             TokenKind::Print => {
                 self.advance()?;
@@ -764,11 +1413,19 @@ impl<'a, 'b, W: Write> Compiler<'a, 'b, W> {
             }
             TokenKind::While => {
                 self.advance()?;
-                self.while_stmt()
+                self.while_stmt(None)
             }
             TokenKind::For => {
                 self.advance()?;
-                self.for_stmt()
+                self.for_stmt(None)
+            }
+            TokenKind::LoopKw => {
+                self.advance()?;
+                self.loop_stmt(None)
+            }
+            TokenKind::Label => {
+                self.advance()?;
+                self.labeled_stmt()
             }
             TokenKind::Continue => {
                 self.advance()?;
@@ -782,19 +1439,70 @@ impl<'a, 'b, W: Write> Compiler<'a, 'b, W> {
                 self.advance()?;
                 self.return_stmt()
             }
+            // An empty statement does nothing
+            TokenKind::Semicolon => self.advance(),
             _ => self.expression_statement(),
         }
     }
 
+    /// Compiles the body of a loop or `if`, warning in strict mode if it is a bare `;`, which is
+    /// almost always a stray one after the header
+    fn body(&mut self, keyword: &str) -> Result<'a, ()> {
+        if self.strict && self.check(TokenKind::Semicolon) {
+            self.report_warning(CompileWarning::new(
+                self.curr_token.clone(),
+                CompileWarningKind::EmptyBody(keyword.to_string()),
+            ));
+        }
+
+        self.statement()
+    }
+
     fn print_statement(&mut self) -> Result<'a, ()> {
+        const MAX_VALUES: u8 = 255;
+        let mut count: u8 = 1;
+        let mut newline = true;
+
         self.expression()?;
+
+        while self.check(TokenKind::Comma) {
+            self.advance()?;
+
+            // A trailing comma leaves the line open
+            if self.check(TokenKind::Semicolon) {
+                newline = false;
+                break;
+            }
+
+            if count == MAX_VALUES {
+                return Err(CompileError::new(
+                    self.curr_token.clone(),
+                    CompileErrorKind::TooManyPrintValues,
+                ));
+            }
+
+            count += 1;
+            self.expression()?;
+        }
+
         self.consume(TokenKind::Semicolon, Expected::Semicolon)?;
-        self.emit_opcode(OpCode::Print);
+
+        if count == 1 && newline {
+            self.emit_opcode(OpCode::Print);
+        } else {
+            self.emit_opcode(OpCode::PrintN);
+            self.emit_byte(count);
+            self.emit_byte(newline as u8);
+        }
 
         Ok(())
     }
 
     fn block(&mut self) -> Result<'a, ()> {
+        // The keyword of a `return`, `break` or `continue` compiled directly in this block. Ones
+        // in nested blocks or statements don't count since they may not always run
+        let mut terminated: Option<&'a str> = None;
+
         loop {
             match self.curr_token.kind {
                 TokenKind::RightBrace => {
@@ -807,47 +1515,103 @@ impl<'a, 'b, W: Write> Compiler<'a, 'b, W> {
                         CompileErrorKind::Expected(Expected::RightBraceToCloseBlock),
                     ))
                 }
-                _ => self.declaration()?,
+                kind => {
+                    // Only warn about the first unreachable declaration
+                    if let Some(keyword) = terminated.take() {
+                        self.report_warning(CompileWarning::new(
+                            self.curr_token.clone(),
+                            CompileWarningKind::UnreachableCode(keyword.to_string()),
+                        ));
+                    }
+
+                    if let TokenKind::Return | TokenKind::Break | TokenKind::Continue = kind {
+                        terminated = Some(self.curr_token.lexeme);
+                    }
+
+                    self.declaration()?
+                }
             }
         }
     }
 
+    /// Compiles an `if` statement along with its `else if` clauses and trailing `else`. The
+    /// clauses are compiled in a loop rather than by recursing into `statement`, and every
+    /// branch jumps straight to the end of the whole chain
     fn if_stmt(&mut self) -> Result<'a, ()> {
-        self.consume(TokenKind::LeftParen, Expected::LeftParen)?;
-        // Compile the condition
-        self.expression()?;
-        self.consume(TokenKind::RightParen, Expected::RightParen)?;
+        let mut end_jumps = Vec::new();
 
-        let then_jump = self.emit_jump(OpCode::JumpIfFalse);
+        loop {
+            self.consume(TokenKind::LeftParen, Expected::LeftParen)?;
+            // Compile the condition
+            self.expression()?;
+            self.consume(TokenKind::RightParen, Expected::RightParen)?;
 
-        // Pop the condition
-        self.emit_opcode(OpCode::Pop);
-        // Compile the block
-        self.statement()?;
+            let then_jump = self.emit_jump(OpCode::JumpIfFalse);
 
-        // To skip the `else` block after executing the `if` block
-        let else_jump = self.emit_jump(OpCode::Jump);
+            // Pop the condition
+            self.emit_opcode(OpCode::Pop);
+            // Compile the block
+            self.body("if")?;
 
-        // `else` branch starts now
-        self.patch_jump(then_jump)?;
+            // To skip the remaining clauses after executing this one
+            end_jumps.push(self.emit_jump(OpCode::Jump));
 
-        // Pop the condition in the `else` branch
-        self.emit_opcode(OpCode::Pop);
+            // The next clause starts now
+            self.patch_jump(then_jump)?;
 
-        // Compile the `else` branch if present
-        if self.check(TokenKind::Else) {
+            // Pop the condition in the `else` branch
+            self.emit_opcode(OpCode::Pop);
+
+            if !self.check(TokenKind::Else) {
+                break;
+            }
             self.advance()?;
-            self.statement()?;
+
+            // Compile an `else if` clause in the next iteration, or the final `else` branch
+            if self.check(TokenKind::If) {
+                self.advance()?;
+            } else {
+                self.body("else")?;
+                break;
+            }
+        }
+
+        // End of the whole chain
+        for jump in end_jumps {
+            self.patch_jump(jump)?;
         }
 
-        // End of `else` branch
-        self.patch_jump(else_jump)
+        Ok(())
+    }
+
+    /// Compiles a labeled loop, assumes the label has been consumed
+    fn labeled_stmt(&mut self) -> Result<'a, ()> {
+        let label = self.prev_token.lexeme;
+
+        self.consume(TokenKind::Colon, Expected::Colon)?;
+
+        // The scanner only produces a label when a loop keyword follows the colon
+        match self.curr_token.kind {
+            TokenKind::While => {
+                self.advance()?;
+                self.while_stmt(Some(label))
+            }
+            TokenKind::For => {
+                self.advance()?;
+                self.for_stmt(Some(label))
+            }
+            TokenKind::LoopKw => {
+                self.advance()?;
+                self.loop_stmt(Some(label))
+            }
+            _ => unreachable!("label not followed by a loop"),
+        }
     }
 
-    fn while_stmt(&mut self) -> Result<'a, ()> {
+    fn while_stmt(&mut self, label: Option<&'a str>) -> Result<'a, ()> {
         let loop_start = self.chunk().code.len();
 
-        self.begin_loop(loop_start);
+        self.begin_loop(loop_start, label);
 
         self.consume(TokenKind::LeftParen, Expected::LeftParen)?;
         // Compile the condition
@@ -859,7 +1623,7 @@ impl<'a, 'b, W: Write> Compiler<'a, 'b, W> {
         // Pop the condition
         self.emit_opcode(OpCode::Pop);
         // Compile the body
-        self.statement()?;
+        self.body("while")?;
 
         self.emit_loop(loop_start)?;
         self.patch_jump(exit_jump)?;
@@ -869,18 +1633,43 @@ impl<'a, 'b, W: Write> Compiler<'a, 'b, W> {
         Ok(())
     }
 
-    fn for_stmt(&mut self) -> Result<'a, ()> {
+    /// Compiles an infinite `loop { ... }`, which only a `break` or `return` can leave
+    fn loop_stmt(&mut self, label: Option<&'a str>) -> Result<'a, ()> {
+        let loop_start = self.chunk().code.len();
+
+        self.begin_loop(loop_start, label);
+
+        self.consume(TokenKind::LeftBrace, Expected::LeftBraceBeforeLoopBody)?;
+        self.begin_scope();
+        self.block()?;
+        self.end_scope();
+
+        // No condition to test, just jump back to the top
+        self.emit_loop(loop_start)?;
+        self.end_loop()
+    }
+
+    fn for_stmt(&mut self, label: Option<&'a str>) -> Result<'a, ()> {
         // Start a new scope for the initializer
         self.begin_scope();
 
         self.consume(TokenKind::LeftParen, Expected::LeftParen)?;
 
+        if self.for_in_stmt(label)? {
+            self.end_scope();
+            return Ok(());
+        }
+
         // Compile the initializer, if any. It can be a variable declaration,
-        // expression statement or just ';'
+        // expression statement or just ';'. A declared variable may be the counter of a counted
+        // loop
+        let mut counter = None;
+
         match self.curr_token.kind {
             TokenKind::Var => {
                 self.advance()?;
                 self.var_declaration()?;
+                counter = Some(self.locals.len() - 1);
             }
             TokenKind::Semicolon => {
                 self.advance()?;
@@ -891,26 +1680,52 @@ impl<'a, 'b, W: Write> Compiler<'a, 'b, W> {
         }
 
         let mut loop_start = self.chunk().code.len();
-        let mut exit_jump: isize = -1;
+        let mut has_condition = false;
+        let mut bound = None;
 
         // Compile the condition, if any
         if !self.check(TokenKind::Semicolon) {
             self.expression()?;
+            has_condition = true;
+            bound = counter.and_then(|counter| self.counted_loop_bound(loop_start, counter));
+        }
+
+        self.consume(TokenKind::Semicolon, Expected::SemicolonAfterCondition)?;
+
+        // Compile the update expression, if any. It is compiled right after the condition so
+        // that the two can be checked for the canonical `i < n; i = i + 1` form, which gets
+        // specialized bytecode, and moved behind the condition's jump otherwise
+        let mut update = None;
+
+        if !self.check(TokenKind::RightParen) {
+            let update_start = self.chunk().code.len();
+            self.expression()?;
+
+            if let (Some(counter), Some(bound)) = (counter, bound.take()) {
+                if self.is_counter_increment(update_start, counter) {
+                    self.counted_for_stmt(loop_start, counter, bound, label)?;
+                    self.end_scope();
+                    return Ok(());
+                }
+            }
+
+            update = Some(self.chunk().split_off(update_start));
+        }
+
+        let mut exit_jump: isize = -1;
 
+        if has_condition {
             // We have the condition value on top of the stack
             exit_jump = self.emit_jump(OpCode::JumpIfFalse) as isize;
             self.emit_opcode(OpCode::Pop);
         }
 
-        self.consume(TokenKind::Semicolon, Expected::SemicolonAfterCondition)?;
-
-        // Compile the update expression, if any
-        if !self.check(TokenKind::RightParen) {
+        if let Some((code, lines)) = update {
             // Need to jump over the update expression after running the condition
             let update_jump = self.emit_jump(OpCode::Jump);
             let update_start = self.chunk().code.len();
 
-            self.expression()?;
+            self.chunk().write_bytes(&code, &lines);
             // We also have to discard its value
             self.emit_opcode(OpCode::Pop);
 
@@ -923,10 +1738,10 @@ impl<'a, 'b, W: Write> Compiler<'a, 'b, W> {
 
         self.consume(TokenKind::RightParen, Expected::RightParen)?;
 
-        self.begin_loop(loop_start);
+        self.begin_loop(loop_start, label);
 
         // Compile the body
-        self.statement()?;
+        self.body("for")?;
         // Append a jump back to the start of the loop
         self.emit_loop(loop_start)?;
 
@@ -942,6 +1757,179 @@ impl<'a, 'b, W: Write> Compiler<'a, 'b, W> {
         Ok(())
     }
 
+    /// Compiles the rest of a `for` loop whose condition and update, compiled from
+    /// `condition_start` on, have the form `counter < bound; counter = counter + 1`. Their general
+    /// bytecode is replaced by an increment of the counter and a `ForNum` test
+    fn counted_for_stmt(
+        &mut self,
+        condition_start: usize,
+        counter: usize,
+        bound: CountedBound,
+        label: Option<&'a str>,
+    ) -> Result<'a, ()> {
+        let condition_line = self.chunk().get_line_of(condition_start);
+        self.chunk().split_off(condition_start);
+
+        // A literal bound is kept in a hidden local right above the counter, while a variable
+        // bound is read from its own slot so that changes to it in the body are observed
+        let bound_slot = match bound {
+            CountedBound::Local(slot) => slot,
+            CountedBound::Literal(value) => {
+                self.emit_pool_constant(value)?;
+
+                let index = self.declare_local("")?;
+                self.mark_as_initialized(index);
+                index
+            }
+        };
+
+        // Skip the update on the first iteration
+        let update_jump = self.emit_jump(OpCode::Jump);
+        let loop_start = self.chunk().code.len();
+
+        self.emit_opcode(OpCode::IncrementLocal);
+        self.emit_byte(counter as u8);
+        self.patch_jump(update_jump)?;
+
+        self.consume(TokenKind::RightParen, Expected::RightParen)?;
+
+        // Test the condition, the jump address is patched once the body is compiled
+        self.chunk().write_opcode(OpCode::ForNum, condition_line);
+        self.chunk().write_bytes(
+            &[counter as u8, bound_slot as u8, 0, 0],
+            &[condition_line; 4],
+        );
+        let exit_jump = self.chunk().code.len() - 2;
+
+        self.begin_loop(loop_start, label);
+
+        // Compile the body
+        self.body("for")?;
+        self.emit_loop(loop_start)?;
+
+        // Unlike the general form, there is no condition value to pop on exit
+        self.patch_jump(exit_jump)?;
+        self.end_loop()
+    }
+
+    /// Compiles the rest of a `for (item in iterable)` loop if the current tokens start one. The
+    /// list and the position in it are kept in hidden locals, and `item` is bound to a new local
+    /// every iteration. Returns `false` without consuming any tokens otherwise
+    fn for_in_stmt(&mut self, label: Option<&'a str>) -> Result<'a, bool> {
+        // `in` is only a keyword right after the loop variable
+        let next = self.scanner.clone().scan_token();
+
+        if !self.check(TokenKind::Identifier)
+            || next.kind != TokenKind::Identifier
+            || next.lexeme != "in"
+        {
+            return Ok(false);
+        }
+
+        let item = self.curr_token.clone();
+        self.advance()?;
+        self.advance()?;
+
+        // Both hidden locals are unnamed, so they can't clash with the program's variables
+        self.expression()?;
+        let iterable = self.locals.len();
+        let token = self.prev_token.clone();
+        self.locals
+            .push(Local::new("", token.clone(), self.curr_depth, true, false));
+
+        self.emit_pool_constant(Value::Int(0))?;
+        let index = self.locals.len();
+        self.locals
+            .push(Local::new("", token, self.curr_depth, true, false));
+
+        self.consume(TokenKind::RightParen, Expected::RightParen)?;
+
+        if index > u8::MAX as usize {
+            return Err(CompileError::new(item, CompileErrorKind::TooManyLocals));
+        }
+
+        // Advance the loop, the jump address is patched once the body is compiled
+        let loop_start = self.chunk().code.len();
+        self.emit_opcode(OpCode::ForIn);
+        for byte in [iterable as u8, index as u8, 0, 0] {
+            self.emit_byte(byte);
+        }
+        let exit_jump = self.chunk().code.len() - 2;
+
+        self.begin_loop(loop_start, label);
+
+        // The item pushed by `ForIn` is the loop variable, scoped to a single iteration so that
+        // `break` and `continue` pop it and closures capture the item of their own iteration
+        self.begin_scope();
+        self.locals.push(Local::new(
+            item.lexeme,
+            item.clone(),
+            self.curr_depth,
+            true,
+            false,
+        ));
+
+        // Compile the body
+        self.body("for")?;
+        self.end_scope();
+        self.emit_loop(loop_start)?;
+
+        self.patch_jump(exit_jump)?;
+        self.end_loop()?;
+        Ok(true)
+    }
+
+    /// Returns the bound of the condition compiled from `start` on if it reads `counter < bound`,
+    /// where `bound` is a local variable or a number literal
+    fn counted_loop_bound(&self, start: usize, counter: usize) -> Option<CountedBound> {
+        let [get_counter, counter_slot, get_bound, operand, less] =
+            self.function.chunk.code[start..]
+        else {
+            return None;
+        };
+
+        if get_counter != u8::from(OpCode::GetLocal)
+            || counter_slot as usize != counter
+            || less != u8::from(OpCode::Less)
+        {
+            return None;
+        }
+
+        match OpCode::try_from(get_bound).ok()? {
+            OpCode::GetLocal => Some(CountedBound::Local(operand as usize)),
+            // The literal gets a hidden local, which must fit in a byte operand
+            OpCode::PoolConstant if self.locals.len() <= u8::MAX as usize => {
+                Some(CountedBound::Literal(self.const_pool.get(operand as usize)))
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns whether the expression compiled from `start` on assigns `counter + 1` or
+    /// `1 + counter` to the counter, where `1` is an integer literal
+    fn is_counter_increment(&self, start: usize, counter: usize) -> bool {
+        let is_counter =
+            |op: u8, slot: u8| op == u8::from(OpCode::GetLocal) && slot as usize == counter;
+        let is_one = |op: u8, index: u8| {
+            op == u8::from(OpCode::PoolConstant)
+                && matches!(self.const_pool.get(index as usize), Value::Int(1))
+        };
+
+        let [left, left_operand, right, right_operand, add, set, set_slot] =
+            self.function.chunk.code[start..]
+        else {
+            return false;
+        };
+
+        let operands_match = (is_counter(left, left_operand) && is_one(right, right_operand))
+            || (is_one(left, left_operand) && is_counter(right, right_operand));
+
+        operands_match
+            && add == u8::from(OpCode::Add)
+            && set == u8::from(OpCode::SetLocal)
+            && set_slot as usize == counter
+    }
+
     fn return_stmt(&mut self) -> Result<'a, ()> {
         if self.contexts.is_empty() {
             return Err(CompileError::new(
@@ -960,7 +1948,42 @@ impl<'a, 'b, W: Write> Compiler<'a, 'b, W> {
                     CompileErrorKind::ReturnInInitializer,
                 ));
             }
+
+            let start = self.chunk().code.len();
             self.expression()?;
+
+            // Several values are returned as a list of them
+            let mut count: u8 = 1;
+            while self.check(TokenKind::Comma) {
+                self.advance()?;
+
+                if count == u8::MAX {
+                    return Err(CompileError::new(
+                        self.prev_token.clone(),
+                        CompileErrorKind::TooManyReturnValues,
+                    ));
+                }
+
+                count += 1;
+                self.expression()?;
+            }
+
+            if count > 1 {
+                self.emit_opcode(OpCode::BuildList);
+                self.emit_byte(count);
+            }
+
+            // A call ending the expression is in tail position, since nothing is left to do
+            // with its result but return it
+            let end = self.chunk().code.len();
+
+            if self
+                .last_call
+                .is_some_and(|offset| offset >= start && offset + 2 == end)
+                && self.chunk().code[end - 2] == OpCode::Call as u8
+            {
+                self.chunk().code[end - 2] = OpCode::TailCall as u8;
+            }
         }
 
         self.emit_opcode(OpCode::Return);
@@ -976,12 +1999,38 @@ impl<'a, 'b, W: Write> Compiler<'a, 'b, W> {
     }
 
     fn expression(&mut self) -> Result<'a, ()> {
-        self.parse_precedence(Precedence::Assignment)
+        self.traced("expression", |this| {
+            this.parse_precedence(Precedence::Assignment)
+        })
     }
 
     fn call(&mut self, _: bool) -> Result<'a, ()> {
+        let calls_len = self.len_callee.take() == Some(self.chunk().code.len());
+        let code_len = self.chunk().code.len();
+        let callee = self
+            .direct_callee
+            .take()
+            .filter(|(end, ..)| *end == code_len);
         let arg_count = self.argument_list()?;
 
+        // Checked against the callee's parameters once it is known to never be reassigned
+        match callee {
+            Some((_, Callee::Local(index), token)) => {
+                self.locals[index].calls.push((token, arg_count))
+            }
+            Some((_, Callee::Global(index), token)) => {
+                self.global_calls.push((index, token, arg_count))
+            }
+            None => {}
+        }
+
+        // `Len` falls back to a regular call if `len` no longer holds the native at runtime
+        if calls_len && arg_count == 1 {
+            self.emit_opcode(OpCode::Len);
+            return Ok(());
+        }
+
+        self.last_call = Some(self.chunk().code.len());
         self.emit_opcode(OpCode::Call);
         self.emit_byte(arg_count);
 
@@ -1022,6 +2071,7 @@ impl<'a, 'b, W: Write> Compiler<'a, 'b, W> {
     fn variable(&mut self, can_assign: bool) -> Result<'a, ()> {
         let name = self.prev_token.lexeme;
         let index = Self::resolve_local(&self.locals, name);
+        let is_assignment = can_assign && self.curr_token.kind == TokenKind::Equal;
 
         // Pick local or global ops and final index
         let (get_op, get_op_long, set_op, set_op_long, idx) = if index != -1 {
@@ -1032,6 +2082,16 @@ impl<'a, 'b, W: Write> Compiler<'a, 'b, W> {
                 ));
             }
 
+            if is_assignment {
+                if self.locals[index as usize].constant {
+                    self.report_err(self.assign_to_constant());
+                }
+
+                self.locals[index as usize].function = None;
+            } else {
+                self.locals[index as usize].used = true;
+            }
+
             (
                 OpCode::GetLocal,
                 OpCode::GetLocalLong,
@@ -1040,9 +2100,18 @@ impl<'a, 'b, W: Write> Compiler<'a, 'b, W> {
                 index as usize,
             )
         } else {
-            let index = self.resolve_upvalue(name);
+            let index = self.resolve_upvalue(name, !is_assignment);
 
             if index != -1 {
+                if is_assignment {
+                    let local = self.enclosing_local(name).unwrap();
+                    local.function = None;
+
+                    if local.constant {
+                        self.report_err(self.assign_to_constant());
+                    }
+                }
+
                 (
                     OpCode::GetUpvalue,
                     OpCode::GetUpvalueLong,
@@ -1051,33 +2120,82 @@ impl<'a, 'b, W: Write> Compiler<'a, 'b, W> {
                     index as usize,
                 )
             } else {
-                (
-                    OpCode::GetGlobal,
-                    OpCode::GetGlobalLong,
-                    OpCode::SetGlobal,
-                    OpCode::SetGlobalLong,
-                    self.sym_table.resolve(name),
-                )
+                if self.curr_depth == 0 && self.globals_in_initializer.contains(&name) {
+                    return Err(CompileError::new(
+                        self.prev_token.to_owned(),
+                        CompileErrorKind::VariableInOwnInitializer(name.to_string()),
+                    ));
+                }
+
+                let (index, is_defined) = self.resolve_global(name);
+
+                if is_assignment {
+                    if self.const_globals.get(index) == Some(&true) {
+                        self.report_err(self.assign_to_constant());
+                    }
+
+                    self.bind_global(index, GlobalBinding::Rebound);
+                    self.global_assignments
+                        .push((index, self.prev_token.clone()));
+                }
+
+                if is_defined {
+                    (
+                        OpCode::GetDefinedGlobal,
+                        OpCode::GetDefinedGlobalLong,
+                        OpCode::SetDefinedGlobal,
+                        OpCode::SetDefinedGlobalLong,
+                        index,
+                    )
+                } else {
+                    (
+                        OpCode::GetGlobal,
+                        OpCode::GetGlobalLong,
+                        OpCode::SetGlobal,
+                        OpCode::SetGlobalLong,
+                        index,
+                    )
+                }
             }
         };
 
         // Assignment or read
-        if can_assign && self.curr_token.kind == TokenKind::Equal {
+        if is_assignment {
             self.advance()?;
             self.expression()?;
             self.emit_opcode_with_num(set_op, set_op_long, idx, CompileErrorKind::TooManyGlobals)
         } else {
-            self.emit_opcode_with_num(get_op, get_op_long, idx, CompileErrorKind::TooManyGlobals)
+            self.emit_opcode_with_num(get_op, get_op_long, idx, CompileErrorKind::TooManyGlobals)?;
+
+            if name == "len" && matches!(get_op, OpCode::GetGlobal | OpCode::GetDefinedGlobal) {
+                self.len_callee = Some(self.chunk().code.len());
+            }
+
+            let callee = match get_op {
+                OpCode::GetLocal => Some(Callee::Local(idx)),
+                OpCode::GetGlobal | OpCode::GetDefinedGlobal => Some(Callee::Global(idx)),
+                _ => None,
+            };
+            if let Some(callee) = callee {
+                let end = self.chunk().code.len();
+                self.direct_callee = Some((end, callee, self.prev_token.clone()));
+            }
+
+            Ok(())
         }
     }
 
     fn number(&mut self, _: bool) -> Result<'a, ()> {
-        match self.prev_token.lexeme.parse::<f64>() {
-            Ok(value) => self.emit_opcode_with_constant_long(
-                OpCode::Constant,
-                OpCode::ConstantLong,
-                Value::Number(value),
-            ),
+        let lexeme = self.prev_token.lexeme;
+
+        // Integer literals too large for an int are compiled as floats
+        let value = match (self.prev_token.kind, lexeme.parse::<i64>()) {
+            (TokenKind::Integer, Ok(value)) => Ok(Value::Int(value)),
+            _ => lexeme.parse::<f64>().map(Value::Number),
+        };
+
+        match value {
+            Ok(value) => self.emit_pool_constant(value),
             Err(_) => Err(CompileError::new(
                 self.prev_token.clone(),
                 CompileErrorKind::InvalidNumber,
@@ -1111,21 +2229,21 @@ impl<'a, 'b, W: Write> Compiler<'a, 'b, W> {
                 self.emit_string_chunk(lexeme)
             }
             TokenKind::StringInterp => {
-                // An interpolated string compiles to a left-folded chain of
-                // concatenations: each literal chunk and each embedded
-                // expression (coerced to a string) is appended in turn.
+                // An interpolated string compiles to its literal chunks and
+                // embedded expressions pushed in turn, then joined into one
+                // string by a single `Concat`.
+                let mut segments: u8 = 0;
                 let lexeme = self.prev_token.lexeme;
-                self.emit_string_chunk(lexeme)?;
+                self.emit_interp_chunk(lexeme, &mut segments)?;
 
                 loop {
                     // Compile the embedded expression; its value is left on the
-                    // stack, then coerced to a string and concatenated. An empty
+                    // stack to be stringified by `Concat`. An empty
                     // interpolation (`{}`) makes the next token a continuation
                     // chunk, which has no prefix rule and so reports "expected
                     // expression" here.
                     self.expression()?;
-                    self.emit_opcode(OpCode::Stringify);
-                    self.emit_opcode(OpCode::Add);
+                    self.add_interp_segment(&mut segments);
 
                     // The expression must be followed by a continuation chunk:
                     // `StringInterpCont` if another expression follows, or
@@ -1141,26 +2259,60 @@ impl<'a, 'b, W: Write> Compiler<'a, 'b, W> {
 
                     self.advance()?;
                     let chunk = self.prev_token.lexeme;
-                    self.emit_string_chunk(chunk)?;
-                    self.emit_opcode(OpCode::Add);
+                    self.emit_interp_chunk(chunk, &mut segments)?;
 
                     if self.prev_token.kind == TokenKind::StringInterpEnd {
                         break;
                     }
                 }
 
+                self.emit_opcode(OpCode::Concat);
+                self.emit_byte(segments);
                 Ok(())
             }
             _ => unreachable!("string() called on a non-string token"),
         }
     }
 
+    /// Emits a literal chunk of an interpolated string as one of its segments,
+    /// unless the chunk is empty
+    fn emit_interp_chunk(&mut self, lexeme: &'a str, segments: &mut u8) -> Result<'a, ()> {
+        // Only the delimiters
+        if lexeme.len() == 2 {
+            return Ok(());
+        }
+
+        self.emit_string_chunk(lexeme)?;
+        self.add_interp_segment(segments);
+        Ok(())
+    }
+
+    /// Counts a value pushed as a segment of an interpolated string. Once a
+    /// `Concat` can't take any more, joins the segments so far into one
+    fn add_interp_segment(&mut self, segments: &mut u8) {
+        if *segments == u8::MAX {
+            self.emit_opcode(OpCode::Concat);
+            self.emit_byte(u8::MAX);
+            *segments = 1;
+        }
+
+        *segments += 1;
+    }
+
     /// Interns a string literal chunk and emits a constant for it. The `lexeme`
     /// includes one delimiter character on each side (`"`/`{` or `}`/`"`), both
-    /// of which are stripped to recover the literal text.
+    /// of which are stripped to recover the literal text. Backslash escapes are
+    /// resolved, see [`Compiler::unescape`]. Braces that don't start an
+    /// interpolation, `{{` and `{}`, are kept as they are for `format` to read.
     fn emit_string_chunk(&mut self, lexeme: &'a str) -> Result<'a, ()> {
         let s = &lexeme[1..lexeme.len() - 1];
-        let str_ptr = self.str_intern_table.intern_slice(s, self.gc);
+        let str_ptr = if s.contains('\\') {
+            let unescaped = Self::unescape(s)
+                .map_err(|kind| CompileError::new(self.prev_token.clone(), kind))?;
+            self.str_intern_table.intern_owned(unescaped, self.gc)
+        } else {
+            self.str_intern_table.intern_slice(s, self.gc)
+        };
 
         self.emit_opcode_with_constant_long(
             OpCode::Constant,
@@ -1169,6 +2321,58 @@ impl<'a, 'b, W: Write> Compiler<'a, 'b, W> {
         )
     }
 
+    /// Resolves the escapes of a string literal chunk: `\\`, `\"`, `\{`, `\n`,
+    /// `\t` and `\u{XXXX}`, which becomes the code point it names. A backslash
+    /// that starts none of them is kept as it is
+    fn unescape(s: &str) -> std::result::Result<String, CompileErrorKind> {
+        let mut unescaped = String::with_capacity(s.len());
+        let mut rest = s;
+
+        while let Some(index) = rest.find('\\') {
+            unescaped.push_str(&rest[..index]);
+            rest = &rest[index..];
+
+            let escaped = match rest.as_bytes().get(1) {
+                Some(b'\\') => Some('\\'),
+                Some(b'"') => Some('"'),
+                Some(b'{') => Some('{'),
+                Some(b'n') => Some('\n'),
+                Some(b't') => Some('\t'),
+                _ => None,
+            };
+
+            if let Some(c) = escaped {
+                unescaped.push(c);
+                rest = &rest[2..];
+            } else if let Some(escape) = rest.strip_prefix("\\u{") {
+                let Some(end) = escape.find('}') else {
+                    return Err(CompileErrorKind::MalformedUnicodeEscape(rest.to_string()));
+                };
+                let digits = &escape[..end];
+                let text = rest[..end + 4].to_string();
+
+                if !(1..=6).contains(&digits.len())
+                    || !digits.chars().all(|c| c.is_ascii_hexdigit())
+                {
+                    return Err(CompileErrorKind::MalformedUnicodeEscape(text));
+                }
+
+                let code_point = u32::from_str_radix(digits, 16).unwrap();
+                match char::from_u32(code_point) {
+                    Some(c) => unescaped.push(c),
+                    None => return Err(CompileErrorKind::InvalidCodePoint(text)),
+                }
+                rest = &escape[end + 1..];
+            } else {
+                unescaped.push_str(&rest[..1]);
+                rest = &rest[1..];
+            }
+        }
+
+        unescaped.push_str(rest);
+        Ok(unescaped)
+    }
+
     fn grouping(&mut self, _: bool) -> Result<'a, ()> {
         self.expression()?;
         self.consume(TokenKind::RightParen, Expected::RightParen)
@@ -1186,6 +2390,7 @@ impl<'a, 'b, W: Write> Compiler<'a, 'b, W> {
         match operator_kind {
             TokenKind::Minus => self.emit_opcode_at_line(OpCode::Negate, operator_line),
             TokenKind::Bang => self.emit_opcode_at_line(OpCode::Not, operator_line),
+            TokenKind::Tilde => self.emit_opcode_at_line(OpCode::BitNot, operator_line),
             _ => unreachable!("unary() called on a non-unary operator"),
         }
 
@@ -1196,10 +2401,17 @@ impl<'a, 'b, W: Write> Compiler<'a, 'b, W> {
         let operator_token = self.prev_token.clone();
         let operator_kind = self.prev_token.kind;
         let operator_line = operator_token.line;
+        let left_start = self.operand_start;
+        let right_start = self.curr_token.span.start;
 
         // Compile the operand
         self.parse_precedence(self.get_rule(operator_kind).precedence + 1)?;
 
+        if self.get_rule(operator_kind).precedence == Precedence::Comparison {
+            self.check_chained_comparison(&operator_token, left_start, right_start)?;
+            self.last_comparison = Some((self.prev_token.span.end, right_start));
+        }
+
         // Emit the operator instruction at the operator's line, not the
         // operand's, so errors point at the operator even across line breaks.
         match operator_kind {
@@ -1215,32 +2427,96 @@ impl<'a, 'b, W: Write> Compiler<'a, 'b, W> {
             }
             TokenKind::Less => self.emit_opcode_at_line(OpCode::Less, operator_line),
             TokenKind::LessEqual => self.emit_opcode_at_line(OpCode::LessEqual, operator_line),
+            TokenKind::LessLess => self.emit_opcode_at_line(OpCode::ShiftLeft, operator_line),
+            TokenKind::GreaterGreater => {
+                self.emit_opcode_at_line(OpCode::ShiftRight, operator_line)
+            }
+            TokenKind::Ampersand => self.emit_opcode_at_line(OpCode::BitAnd, operator_line),
+            TokenKind::Pipe => self.emit_opcode_at_line(OpCode::BitOr, operator_line),
+            TokenKind::Caret => self.emit_opcode_at_line(OpCode::BitXor, operator_line),
             _ => unreachable!("binary() called on a non-binary operator"),
         }
 
         Ok(())
     }
 
+    /// Rejects a comparison whose left operand ends with the last comparison, which compares
+    /// the boolean result of that comparison rather than its right operand
+    fn check_chained_comparison(
+        &self,
+        operator: &Token<'a>,
+        left_start: usize,
+        right_start: usize,
+    ) -> Result<'a, ()> {
+        let Some((end, middle_start)) = self.last_comparison else {
+            return Ok(());
+        };
+
+        // A parenthesized comparison is compared on purpose
+        if middle_start < left_start
+            || end > operator.span.start
+            || !self.source[end..operator.span.start].trim().is_empty()
+        {
+            return Ok(());
+        }
+
+        let left = &self.source[left_start..end];
+        let middle = &self.source[middle_start..end];
+        let right = &self.source[right_start..self.prev_token.span.end];
+
+        Err(CompileError::new(
+            operator.clone(),
+            CompileErrorKind::ChainedComparison(format!(
+                "{left} and {middle} {} {right}",
+                operator.lexeme
+            )),
+        ))
+    }
+
     fn ternary(&mut self, _: bool) -> Result<'a, ()> {
         let operator_kind = self.prev_token.kind;
 
         if let TokenKind::Question = operator_kind {
-            // Compile the 2nd operand
+            let condition = &self.source[self.operand_start..self.prev_token.span.start];
+            let then_start = self.curr_token.span.start;
+
+            // The predicate can be of any type, only the branch it selects is evaluated
+            let then_jump = self.emit_jump(OpCode::JumpIfFalse);
+
+            // Pop the predicate and compile the 2nd operand. Parsing it at the lowest
+            // precedence makes a ternary in the 3rd operand nest to the right
+            self.emit_opcode(OpCode::Pop);
             self.parse_precedence(Precedence::Assignment)?;
 
-            // Consume the colon
-            self.consume(TokenKind::Colon, Expected::Colon)?;
+            // To skip the 3rd operand after evaluating the 2nd one
+            let else_jump = self.emit_jump(OpCode::Jump);
+
+            if !self.check(TokenKind::Colon) {
+                let then_branch = &self.source[then_start..self.prev_token.span.end];
+
+                return Err(CompileError::new(
+                    self.curr_token.clone(),
+                    CompileErrorKind::TernaryMissingColon {
+                        condition: condition.trim().to_string(),
+                        then_branch: then_branch.to_string(),
+                    },
+                ));
+            }
+            self.advance()?;
 
-            // Compile the 3rd operand
+            // Pop the predicate and compile the 3rd operand
+            self.patch_jump(then_jump)?;
+            self.emit_opcode(OpCode::Pop);
             self.parse_precedence(Precedence::Assignment)?;
 
-            self.emit_opcode(OpCode::Ternary);
-            Ok(())
+            self.patch_jump(else_jump)
         } else {
             unreachable!("ternary() called on a non-'?' operator")
         }
     }
 
+    /// Compiles `a or b`. The result is the left operand if it is truthy, and otherwise the right
+    /// operand. Neither is converted to a bool, so `nil or "x"` is `"x"` and `1 or 2` is `1`
     fn logical_or(&mut self, _: bool) -> Result<'a, ()> {
         let operator_kind = self.prev_token.kind;
 
@@ -1259,6 +2535,8 @@ impl<'a, 'b, W: Write> Compiler<'a, 'b, W> {
         }
     }
 
+    /// Compiles `a and b`. The result is the left operand if it is falsey, and otherwise the right
+    /// operand, so `1 and 2` is `2` and `nil and 2` is `nil`
     fn logical_and(&mut self, _: bool) -> Result<'a, ()> {
         let operator_kind = self.prev_token.kind;
 
@@ -1278,18 +2556,20 @@ impl<'a, 'b, W: Write> Compiler<'a, 'b, W> {
     }
 
     fn continue_stmt(&mut self) -> Result<'a, ()> {
-        let (loop_start, scope_depth) = if let Some(loop_context) = self.innermost_loop() {
-            (loop_context.loop_start, loop_context.scope_depth)
-        } else {
-            return Err(CompileError::new(
-                self.prev_token.clone(),
-                CompileErrorKind::ContinueOutsideLoop,
-            ));
+        let keyword = self.prev_token.clone();
+        let label = self.loop_label()?;
+
+        let (loop_start, scope_depth) = match self.find_loop(label) {
+            Some(index) => {
+                let loop_context = &self.loop_contexts[index];
+                (loop_context.loop_start, loop_context.scope_depth)
+            }
+            None => return Err(self.loop_not_found(keyword, label)),
         };
 
         self.consume(TokenKind::Semicolon, Expected::Semicolon)?;
 
-        // Pop the locals in the loop body
+        // Pop the locals in the body of every loop up to the target loop
         self.emit_pop_scopes(scope_depth);
 
         // Jump back to the start of the loop
@@ -1297,32 +2577,51 @@ impl<'a, 'b, W: Write> Compiler<'a, 'b, W> {
     }
 
     fn break_stmt(&mut self) -> Result<'a, ()> {
-        let scope_depth = if let Some(loop_context) = self.innermost_loop() {
-            loop_context.scope_depth
-        } else {
-            return Err(CompileError::new(
-                self.prev_token.clone(),
-                CompileErrorKind::BreakOutsideLoop,
-            ));
+        let keyword = self.prev_token.clone();
+        let label = self.loop_label()?;
+
+        let (index, scope_depth) = match self.find_loop(label) {
+            Some(index) => (index, self.loop_contexts[index].scope_depth),
+            None => return Err(self.loop_not_found(keyword, label)),
         };
 
         self.consume(TokenKind::Semicolon, Expected::Semicolon)?;
 
-        // Pop the locals in the loop body
+        // Pop the locals in the body of every loop up to the target loop
         self.emit_pop_scopes(scope_depth);
 
         // Emit a jump to the end of the loop
         let break_jump = self.emit_jump(OpCode::Jump);
 
-        // Push the jump to the loop context
-        self.loop_contexts
-            .last_mut()
-            .unwrap()
-            .break_jumps
-            .push(break_jump);
+        // Push the jump to the target loop's context
+        self.loop_contexts[index].break_jumps.push(break_jump);
         Ok(())
     }
 
+    /// Consumes the optional label after `break` or `continue`
+    fn loop_label(&mut self) -> Result<'a, Option<&'a str>> {
+        if self.check(TokenKind::Identifier) {
+            self.advance()?;
+            Ok(Some(self.prev_token.lexeme))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Builds the error for a `break` or `continue` whose target loop could not be found
+    fn loop_not_found(&self, keyword: Token<'a>, label: Option<&'a str>) -> CompileError<'a> {
+        match label {
+            Some(label) => CompileError::new(
+                self.prev_token.clone(),
+                CompileErrorKind::UndefinedLabel(label.to_string()),
+            ),
+            None if keyword.kind == TokenKind::Break => {
+                CompileError::new(keyword, CompileErrorKind::BreakOutsideLoop)
+            }
+            None => CompileError::new(keyword, CompileErrorKind::ContinueOutsideLoop),
+        }
+    }
+
     fn dot(&mut self, can_assign: bool) -> Result<'a, ()> {
         self.consume(TokenKind::Identifier, Expected::PropertyName)?;
 
@@ -1332,7 +2631,7 @@ impl<'a, 'b, W: Write> Compiler<'a, 'b, W> {
         if can_assign && self.check(TokenKind::Equal) {
             self.advance()?;
             self.expression()?;
-            self.emit_opcode_with_constant(OpCode::SetProperty, Value::String(name_ptr))
+            self.emit_property_instr(OpCode::SetProperty, name_ptr)
         } else if self.check(TokenKind::LeftParen) {
             // Immediate method invocation
             self.advance()?;
@@ -1342,10 +2641,24 @@ impl<'a, 'b, W: Write> Compiler<'a, 'b, W> {
             self.emit_byte(arg_count);
             Ok(())
         } else {
-            self.emit_opcode_with_constant(OpCode::GetProperty, Value::String(name_ptr))
+            self.emit_property_instr(OpCode::GetProperty, name_ptr)
         }
     }
 
+    /// Compiles `?.`, a property access that produces `nil` if the receiver is `nil` or the
+    /// property is missing. A `nil` receiver skips the rest of the chain
+    fn question_dot(&mut self, _: bool) -> Result<'a, ()> {
+        self.consume(TokenKind::Identifier, Expected::PropertyName)?;
+
+        let jump = self.emit_jump(OpCode::JumpIfNil);
+        self.optional_chain_jumps.push(jump);
+
+        let name = self.prev_token.lexeme;
+        let name_ptr = self.str_intern_table.intern_slice(name, self.gc);
+
+        self.emit_property_instr(OpCode::GetPropertyOrNil, name_ptr)
+    }
+
     fn this(&mut self, _: bool) -> Result<'a, ()> {
         if self.class_contexts.is_empty() {
             return Err(CompileError::new(
@@ -1354,6 +2667,13 @@ impl<'a, 'b, W: Write> Compiler<'a, 'b, W> {
             ));
         }
 
+        if self.class_contexts.last().unwrap().in_static_method {
+            return Err(CompileError::new(
+                self.prev_token.clone(),
+                CompileErrorKind::ThisInStaticMethod,
+            ));
+        }
+
         self.variable(false)
     }
 
@@ -1365,6 +2685,13 @@ impl<'a, 'b, W: Write> Compiler<'a, 'b, W> {
             ));
         }
 
+        if self.class_contexts.last().unwrap().in_static_method {
+            return Err(CompileError::new(
+                self.prev_token.clone(),
+                CompileErrorKind::SuperInStaticMethod,
+            ));
+        }
+
         if !self.class_contexts.last().unwrap().has_superclass {
             return Err(CompileError::new(
                 self.prev_token.clone(),
@@ -1401,9 +2728,13 @@ impl<'a, 'b, W: Write> Compiler<'a, 'b, W> {
 
         let prefix_rule = self.get_rule(self.prev_token.kind).prefix_rule;
         let can_assign = precedence <= Precedence::Assignment;
+        let operand_start = self.prev_token.span.start;
+
+        let is_identifier = self.prev_token.kind == TokenKind::Identifier;
+        self.direct_callee = None;
 
         match prefix_rule {
-            Some(prefix_rule) => prefix_rule(self, can_assign)?,
+            Some(prefix_rule) => self.traced_rule(prefix_rule, can_assign, None)?,
             None => {
                 return Err(CompileError::new(
                     self.prev_token.clone(),
@@ -1412,13 +2743,39 @@ impl<'a, 'b, W: Write> Compiler<'a, 'b, W> {
             }
         }
 
+        // Only a call right after a lone identifier calls the variable it names directly
+        let mut direct_callee = if is_identifier {
+            self.direct_callee.take()
+        } else {
+            None
+        };
+        self.direct_callee = None;
+
+        // A chain of property accesses and calls ends at the first operator that binds looser
+        let chain_start = self.optional_chain_jumps.len();
+
         while precedence <= self.get_rule(self.curr_token.kind).precedence {
             self.advance()?;
 
-            let infix_rule = self.get_rule(self.prev_token.kind).infix_rule;
+            let rule = self.get_rule(self.prev_token.kind);
+            let infix_rule = rule.infix_rule;
+            let rule_precedence = rule.precedence;
+
+            if rule_precedence < Precedence::Call {
+                self.end_optional_chain(chain_start)?;
+            }
+
+            // Nothing can be assigned through `?.`
+            let can_assign = can_assign && self.optional_chain_jumps.len() == chain_start;
+            self.operand_start = operand_start;
+
+            self.direct_callee = direct_callee.take();
 
             match infix_rule {
-                Some(infix_rule) => infix_rule(self, can_assign)?,
+                Some(infix_rule) => {
+                    self.traced_rule(infix_rule, can_assign, Some(rule_precedence))?;
+                    self.direct_callee = None;
+                }
                 None => {
                     return Err(CompileError::new(
                         self.prev_token.clone(),
@@ -1428,6 +2785,8 @@ impl<'a, 'b, W: Write> Compiler<'a, 'b, W> {
             }
         }
 
+        self.end_optional_chain(chain_start)?;
+
         if can_assign && self.check(TokenKind::Equal) {
             Err(CompileError::new(
                 self.curr_token.clone(),
@@ -1438,6 +2797,16 @@ impl<'a, 'b, W: Write> Compiler<'a, 'b, W> {
         }
     }
 
+    /// Patches the `?.` jumps emitted since `chain_start` to land after the code emitted so far
+    fn end_optional_chain(&mut self, chain_start: usize) -> Result<'a, ()> {
+        while self.optional_chain_jumps.len() > chain_start {
+            let jump = self.optional_chain_jumps.pop().unwrap();
+            self.patch_jump(jump)?;
+        }
+
+        Ok(())
+    }
+
     fn advance(&mut self) -> Result<'a, ()> {
         let token = self.scanner.scan_token();
 
@@ -1467,14 +2836,20 @@ impl<'a, 'b, W: Write> Compiler<'a, 'b, W> {
     }
 
     fn synchronize(&mut self) {
+        // Jumps of a chain that failed to compile are never patched
+        self.optional_chain_jumps.clear();
+
         loop {
             match self.curr_token.kind {
                 TokenKind::Eof => return,
                 TokenKind::For => return,
                 TokenKind::If => return,
                 TokenKind::While => return,
+                TokenKind::LoopKw => return,
+                TokenKind::Import => return,
                 TokenKind::Fun => return,
                 TokenKind::Var => return,
+                TokenKind::Const => return,
                 TokenKind::Print => return,
                 TokenKind::Semicolon => {
                     if let Err(err) = self.advance() {
@@ -1497,14 +2872,44 @@ impl<'a, 'b, W: Write> Compiler<'a, 'b, W> {
         self.curr_token.kind == kind
     }
 
-    fn finish(mut self) -> Option<Function> {
+    /// Like [`Compiler::compile`], but writes each error to the error stream as it is found, the
+    /// way the command line reports them, and only tells whether compiling succeeded
+    pub fn compile_and_report(mut self) -> Option<Function> {
+        self.print_errors = true;
+        self.compile().ok()
+    }
+
+    fn finish(mut self) -> std::result::Result<Function, Vec<CompileError<'a>>> {
         // `emit_return` will emit a `nil` since `finish` is only called from the global scope
         let _err = self.emit_return();
 
-        if !self.had_error {
-            Some(self.function)
+        // Declarations skipped while recovering from errors would make for misleading warnings.
+        // An imported file may use the globals of the file importing it, which are only known
+        // once the main file is compiled
+        if self.errors.is_empty() && self.file_id == 0 {
+            self.check_const_assignments();
+            self.check_global_calls();
+            self.warn_undefined_globals();
+        }
+
+        if self.errors.is_empty() {
+            // A mismatch here is a compiler bug, such as a `break` that leaves a temporary behind
+            #[cfg(debug_assertions)]
+            if let Err(err) = crate::stack_check::check_function(&self.function) {
+                panic!("{err}");
+            }
+        }
+
+        if self.print_errors && !self.json_errors && self.errors.len() > self.max_errors {
+            let more = self.errors.len() - self.max_errors;
+            let plural = if more == 1 { "" } else { "s" };
+            let _ = writeln!(self.err_stream, "... {more} more error{plural}");
+        }
+
+        if self.errors.is_empty() {
+            Ok(self.function)
         } else {
-            None
+            Err(self.errors)
         }
     }
 
@@ -1523,18 +2928,55 @@ impl<'a, 'b, W: Write> Compiler<'a, 'b, W> {
             }
         }
 
-        self.locals
-            .push(Local::new(name, self.curr_depth, false, false));
+        self.locals.push(Local::new(
+            name,
+            self.prev_token.clone(),
+            self.curr_depth,
+            false,
+            false,
+        ));
 
         Ok(self.locals.len() - 1)
     }
 
+    /// Declares the global with the given name, assuming the previous token names it. A global can
+    /// only be declared by one file of the program
+    fn declare_global(&mut self, name: &'a str) -> Result<'a, usize> {
+        let index = self.sym_table.declare(name);
+        let owners = &mut self.imports.owners;
+
+        if owners.len() <= index {
+            owners.resize(index + 1, None);
+        }
+
+        if self.const_globals.get(index) == Some(&true) {
+            return Err(CompileError::new(
+                self.prev_token.clone(),
+                CompileErrorKind::RedeclaredVariable(name.to_string()),
+            ));
+        }
+
+        match owners[index] {
+            Some(file) if file != self.file_id => Err(CompileError::new(
+                self.prev_token.clone(),
+                CompileErrorKind::DeclaredInOtherFile {
+                    name: name.to_string(),
+                    file: self.imports.names[file].as_str().into(),
+                },
+            )),
+            _ => {
+                owners[index] = Some(self.file_id);
+                Ok(index)
+            }
+        }
+    }
+
     /// Declares a variable with the given name in the current scope. This will also mark the local
     /// variable as initialized
     fn declare_variable(&mut self, name: &'a str) -> Result<'a, usize> {
         let index = if self.curr_depth == 0 {
             // Global variable
-            self.sym_table.declare(name)
+            self.declare_global(name)?
         } else {
             // Local variable
             let index = self.declare_local(name)?;
@@ -1545,6 +2987,162 @@ impl<'a, 'b, W: Write> Compiler<'a, 'b, W> {
         Ok(index)
     }
 
+    /// Emits the definition of the global at the given index. Every statement of the main function
+    /// after this one runs after it, so they can skip checking that the global is defined
+    fn define_global(&mut self, index: usize, signature: Option<Signature>) -> Result<'a, ()> {
+        // Natives and globals defined earlier are defined here already
+        let binding = match signature {
+            Some(signature) if !self.is_defined_global(index) => GlobalBinding::Function(signature),
+            _ => GlobalBinding::Rebound,
+        };
+        self.bind_global(index, binding);
+
+        if self.defined_globals.len() <= index {
+            self.defined_globals.resize(index + 1, false);
+        }
+
+        self.defined_globals[index] = true;
+
+        self.emit_opcode_with_num(
+            OpCode::DefineGlobal,
+            OpCode::DefineGlobalLong,
+            index,
+            CompileErrorKind::TooManyGlobals,
+        )
+    }
+
+    /// Records a definition of or an assignment to the global at the given index. Only the first
+    /// binding can be a function, any later one rebinds the global
+    fn bind_global(&mut self, index: usize, binding: GlobalBinding) {
+        if self.global_bindings.len() <= index {
+            self.global_bindings
+                .resize(index + 1, GlobalBinding::Unbound);
+        }
+
+        self.global_bindings[index] = match self.global_bindings[index] {
+            GlobalBinding::Unbound => binding,
+            _ => GlobalBinding::Rebound,
+        };
+    }
+
+    /// Returns the local of an enclosing function that the given name, resolved as an upvalue,
+    /// refers to
+    fn enclosing_local(&mut self, name: &'a str) -> Option<&mut Local<'a>> {
+        for context in self.contexts.iter_mut().rev() {
+            let index = Self::resolve_local(&context.locals, name);
+
+            if index != -1 {
+                return Some(&mut context.locals[index as usize]);
+            }
+        }
+
+        None
+    }
+
+    /// Returns the error for an assignment to the constant named by the previous token. It is
+    /// reported without unwinding, since the assignment itself still parses
+    fn assign_to_constant(&self) -> CompileError<'a> {
+        CompileError::new(
+            self.prev_token.clone(),
+            CompileErrorKind::AssignToConstant(self.prev_token.lexeme.to_string()),
+        )
+    }
+
+    /// Reports the assignments to globals compiled before the globals were declared by `const`
+    fn check_const_assignments(&mut self) {
+        for (index, token) in std::mem::take(&mut self.global_assignments) {
+            if self.const_globals.get(index) == Some(&true) {
+                let kind = CompileErrorKind::AssignToConstant(token.lexeme.to_string());
+                self.report_err(CompileError::new(token, kind));
+            }
+        }
+    }
+
+    /// Reports each direct call with an argument count the callee can never accept
+    fn check_calls(&mut self, calls: Vec<(Token<'a>, u8)>, signature: Signature) {
+        for (token, got) in calls {
+            if !signature.accepts(got) {
+                let kind = CompileErrorKind::ArgCount {
+                    name: token.lexeme.to_string(),
+                    min: signature.min,
+                    max: (!signature.variadic).then_some(signature.max),
+                    got,
+                };
+                self.report_err(CompileError::new(token, kind));
+            }
+        }
+    }
+
+    /// Checks the direct calls of the locals from the given index on, which go out of scope.
+    /// Skipped after an error, as a statement skipped while recovering may have assigned them
+    fn check_local_calls(&mut self, from: usize) {
+        if !self.errors.is_empty() {
+            return;
+        }
+
+        for index in from..self.locals.len() {
+            let local = &mut self.locals[index];
+            let calls = std::mem::take(&mut local.calls);
+
+            if let Some(signature) = local.function {
+                self.check_calls(calls, signature);
+            }
+        }
+    }
+
+    /// Checks the direct calls of globals that are only ever defined by `fun`
+    fn check_global_calls(&mut self) {
+        for (index, token, got) in std::mem::take(&mut self.global_calls) {
+            if let Some(GlobalBinding::Function(signature)) = self.global_bindings.get(index) {
+                self.check_calls(vec![(token, got)], *signature);
+            }
+        }
+    }
+
+    /// Resolves the global referenced by the previous token and returns its index and whether it
+    /// is known to be defined here. Remembers references that may be undefined when they run
+    fn resolve_global(&mut self, name: &'a str) -> (usize, bool) {
+        let index = self.sym_table.resolve(name);
+        let is_defined = self.is_defined_global(index);
+        let in_main = self.contexts.is_empty();
+
+        if (in_main && !is_defined) || !self.sym_table.is_declared(index) {
+            self.global_refs
+                .push((index, self.prev_token.clone(), in_main));
+        }
+
+        (index, is_defined)
+    }
+
+    /// Warns about the first reference to each global that is never declared, and to each global
+    /// the main function uses before its definition. Functions may refer to globals declared
+    /// after them, as long as they are declared somewhere
+    fn warn_undefined_globals(&mut self) {
+        let mut warned = vec![false; self.sym_table.len()];
+
+        for (index, token, in_main) in std::mem::take(&mut self.global_refs) {
+            let name = token.lexeme.to_string();
+            let kind = if !self.sym_table.is_declared(index) {
+                CompileWarningKind::UndefinedVariable(name)
+            } else if in_main {
+                CompileWarningKind::UsedBeforeDefinition(name)
+            } else {
+                continue;
+            };
+
+            if !std::mem::replace(&mut warned[index], true) {
+                self.report_warning(CompileWarning::new(token, kind));
+            }
+        }
+    }
+
+    /// Returns whether the global at the given index is known to be defined where it is accessed.
+    /// Functions can run before the globals they reference are defined, so only accesses in the
+    /// main function qualify
+    fn is_defined_global(&self, index: usize) -> bool {
+        self.contexts.is_empty() && self.defined_globals.get(index) == Some(&true)
+    }
+
     /// Mark the local as being initialized
     fn mark_as_initialized(&mut self, index: usize) {
         self.locals[index].initialized = true;
@@ -1563,8 +3161,9 @@ impl<'a, 'b, W: Write> Compiler<'a, 'b, W> {
     }
 
     /// Resolves the given name in the chain of function scopes starting from the current
-    /// function upto the global scope and returns the index of the upvalue if found, -1 otherwise
-    fn resolve_upvalue(&mut self, name: &'a str) -> i32 {
+    /// function upto the global scope and returns the index of the upvalue if found, -1 otherwise.
+    /// `is_read` marks the captured local as used
+    fn resolve_upvalue(&mut self, name: &'a str, is_read: bool) -> i32 {
         // The current context is not stored in `self.contexts` so we've to handle it separately
         let len = self.contexts.len();
 
@@ -1579,13 +3178,15 @@ impl<'a, 'b, W: Write> Compiler<'a, 'b, W> {
         if index != -1 {
             // The name is a local variable in the enclosing function. Mark it as captured
             let index = index as usize;
-            self.contexts.last_mut().unwrap().locals[index].captured = true;
+            let local = &mut self.contexts.last_mut().unwrap().locals[index];
+            local.captured = true;
+            local.used |= is_read;
 
             return Self::add_upvalue(&mut self.upvalues, true, index) as i32;
         }
 
         // Check if the name is an upvalue in the enclosing function
-        let index = Self::resolve_upvalue_helper(&mut self.contexts, name);
+        let index = Self::resolve_upvalue_helper(&mut self.contexts, name, is_read);
 
         if index != -1 {
             // The name is an upvalue in the enclosing function
@@ -1597,7 +3198,11 @@ impl<'a, 'b, W: Write> Compiler<'a, 'b, W> {
 
     /// Resolves the given name in the chain of function scopes starting from the current
     /// function upto the global scope and returns the index of the upvalue if found, -1 otherwise
-    fn resolve_upvalue_helper(contexts: &mut [CompilerContext], name: &'a str) -> i32 {
+    fn resolve_upvalue_helper(
+        contexts: &mut [CompilerContext],
+        name: &'a str,
+        is_read: bool,
+    ) -> i32 {
         // If there is only one context, we've reached the global scope,
         // so the name must be a global variable (or it is undefined)
         let len = contexts.len();
@@ -1613,14 +3218,16 @@ impl<'a, 'b, W: Write> Compiler<'a, 'b, W> {
         if index != -1 {
             // The name is a local variable in the enclosing function. Mark it as captured
             let index = index as usize;
-            contexts[len - 2].locals[index].captured = true;
+            let local = &mut contexts[len - 2].locals[index];
+            local.captured = true;
+            local.used |= is_read;
 
             return Self::add_upvalue(&mut contexts.last_mut().unwrap().upvalues, true, index)
                 as i32;
         }
 
         // Check if the name is an upvalue in the enclosing function
-        let index = Self::resolve_upvalue_helper(&mut contexts[..len - 1], name);
+        let index = Self::resolve_upvalue_helper(&mut contexts[..len - 1], name, is_read);
 
         if index != -1 {
             // The name is an upvalue in the enclosing function
@@ -1649,16 +3256,22 @@ impl<'a, 'b, W: Write> Compiler<'a, 'b, W> {
 
             (OpCode::GetLocal, OpCode::GetLocalLong, index as usize)
         } else {
-            let index = self.resolve_upvalue(name);
+            let index = self.resolve_upvalue(name, false);
 
             if index != -1 {
                 (OpCode::GetUpvalue, OpCode::GetUpvalueLong, index as usize)
             } else {
-                (
-                    OpCode::GetGlobal,
-                    OpCode::GetGlobalLong,
-                    self.sym_table.resolve(name),
-                )
+                let (index, is_defined) = self.resolve_global(name);
+
+                if is_defined {
+                    (
+                        OpCode::GetDefinedGlobal,
+                        OpCode::GetDefinedGlobalLong,
+                        index,
+                    )
+                } else {
+                    (OpCode::GetGlobal, OpCode::GetGlobalLong, index)
+                }
             }
         };
 
@@ -1688,6 +3301,14 @@ impl<'a, 'b, W: Write> Compiler<'a, 'b, W> {
     /// pops all locals upto (including) that depth. Also emits instructions
     /// to close-over the locals that have been captured by upvalues
     fn end_scope(&mut self) {
+        let scope_start = self
+            .locals
+            .iter()
+            .rposition(|local| local.depth < self.curr_depth)
+            .map_or(0, |index| index + 1);
+        self.warn_unused_locals(scope_start);
+        self.check_local_calls(scope_start);
+
         while let Some(local) = self.locals.last() {
             if local.depth < self.curr_depth {
                 break;
@@ -1705,6 +3326,31 @@ impl<'a, 'b, W: Write> Compiler<'a, 'b, W> {
         self.curr_depth -= 1;
     }
 
+    /// Warns about every local from the given index on that is never read. Parameters, locals the
+    /// compiler declares for itself and names starting with `_` are exempt
+    fn warn_unused_locals(&mut self, from: usize) {
+        let unused: Vec<Token<'a>> = self.locals[from..]
+            .iter()
+            .filter(|local| {
+                local.initialized
+                    && !local.used
+                    && !local.name.is_empty()
+                    && !local.name.starts_with('_')
+                    && local.name != "this"
+                    && local.name != "super"
+            })
+            .map(|local| local.token.clone())
+            .collect();
+
+        for token in unused {
+            let name = token.lexeme.to_string();
+            self.report_warning(CompileWarning::new(
+                token,
+                CompileWarningKind::UnusedVariable(name),
+            ));
+        }
+    }
+
     /// Emits instructions to pop (or close-over) all locals upto (but excluding) the given depth
     fn emit_pop_scopes(&mut self, upto_depth: usize) {
         let mut chunk = std::mem::take(&mut self.function.chunk);
@@ -1726,11 +3372,12 @@ impl<'a, 'b, W: Write> Compiler<'a, 'b, W> {
     }
 
     /// Pushes a new loop context
-    fn begin_loop(&mut self, loop_start: usize) {
+    fn begin_loop(&mut self, loop_start: usize, label: Option<&'a str>) {
         self.loop_contexts.push(LoopContext {
             loop_start,
             scope_depth: self.curr_depth,
             break_jumps: Vec::new(),
+            label,
         });
     }
 
@@ -1746,22 +3393,35 @@ impl<'a, 'b, W: Write> Compiler<'a, 'b, W> {
         Ok(())
     }
 
-    /// Returns the topmost (innermost) loop context
-    fn innermost_loop(&self) -> Option<&LoopContext> {
-        self.loop_contexts.last()
+    /// Returns the index of the innermost loop context with the given label, or of the
+    /// innermost loop context if no label is given
+    fn find_loop(&self, label: Option<&'a str>) -> Option<usize> {
+        match label {
+            Some(label) => self
+                .loop_contexts
+                .iter()
+                .rposition(|loop_context| loop_context.label == Some(label)),
+            None => self.loop_contexts.len().checked_sub(1),
+        }
     }
 
     /// Saves the current compilation context and sets up a new one for the given function
     fn push_context(&mut self, func_name: &str, is_initializer: bool) {
+        let source_name = self.function.source_name.clone();
+
         // Save current context
         let saved_context = CompilerContext {
             function: std::mem::replace(
                 &mut self.function,
                 Function {
                     name: func_name.to_owned(),
+                    source_name,
                     arity: 0,
                     upvalue_count: 0,
                     chunk: Chunk::new(),
+                    object_constants: Vec::new(),
+                    entry_points: Vec::new(),
+                    variadic: false,
                 },
             ),
             locals: std::mem::take(&mut self.locals),
@@ -1769,7 +3429,10 @@ impl<'a, 'b, W: Write> Compiler<'a, 'b, W> {
             loop_contexts: std::mem::take(&mut self.loop_contexts),
             upvalues: std::mem::take(&mut self.upvalues),
             is_initializer: std::mem::replace(&mut self.is_initializer, is_initializer),
+            last_call: self.last_call.take(),
+            len_callee: self.len_callee.take(),
         };
+        self.direct_callee = None;
 
         self.contexts.push(saved_context);
     }
@@ -1787,6 +3450,9 @@ impl<'a, 'b, W: Write> Compiler<'a, 'b, W> {
         self.loop_contexts = saved_context.loop_contexts;
         self.upvalues = saved_context.upvalues;
         self.is_initializer = saved_context.is_initializer;
+        self.last_call = saved_context.last_call;
+        self.len_callee = saved_context.len_callee;
+        self.direct_callee = None;
 
         compiled_function
     }
@@ -1852,8 +3518,18 @@ impl<'a, 'b, W: Write> Compiler<'a, 'b, W> {
         }
     }
 
+    /// Adds a constant to the current chunk and returns its index. Heap objects are also
+    /// recorded in the function's `object_constants`, so the GC can skip the other constants
+    fn add_constant(&mut self, value: Value) -> usize {
+        if value.is_object() {
+            self.function.object_constants.push(value);
+        }
+
+        self.chunk().add_constant(value)
+    }
+
     fn emit_opcode_with_constant(&mut self, opcode: OpCode, value: Value) -> Result<'a, ()> {
-        let index = self.chunk().add_constant(value);
+        let index = self.add_constant(value);
 
         if index <= u8::MAX as usize {
             self.emit_opcode(opcode);
@@ -1867,13 +3543,24 @@ impl<'a, 'b, W: Write> Compiler<'a, 'b, W> {
         }
     }
 
+    /// Emits a property instruction for the property `name` with a new property cache. Every
+    /// property instruction adds a constant too, so there are never more caches than a byte can
+    /// index once the constant fits
+    fn emit_property_instr(&mut self, opcode: OpCode, name: *mut String) -> Result<'a, ()> {
+        self.emit_opcode_with_constant(opcode, Value::String(name))?;
+
+        let cache = self.chunk().add_property_cache();
+        self.emit_byte(cache as u8);
+        Ok(())
+    }
+
     fn emit_opcode_with_constant_long(
         &mut self,
         opcode: OpCode,
         opcode_long: OpCode,
         value: Value,
     ) -> Result<'a, ()> {
-        let index = self.chunk().add_constant(value);
+        let index = self.add_constant(value);
         self.emit_opcode_with_num(
             opcode,
             opcode_long,
@@ -1882,18 +3569,28 @@ impl<'a, 'b, W: Write> Compiler<'a, 'b, W> {
         )
     }
 
+    /// Emits an instruction producing `value`, which must be a number, from the program's
+    /// constant pool
+    fn emit_pool_constant(&mut self, value: Value) -> Result<'a, ()> {
+        let index = self.const_pool.add(value);
+        self.emit_opcode_with_num(
+            OpCode::PoolConstant,
+            OpCode::PoolConstantLong,
+            index,
+            CompileErrorKind::TooManyConstants,
+        )
+    }
+
     /// Emits a jump instruction and returns the location of the first byte of the jump address
     fn emit_jump(&mut self, opcode: OpCode) -> usize {
         let line = self.prev_token.line;
 
         self.chunk().write_opcode(opcode, line);
-        self.chunk().write_bytes(&[0; 2], &[line; 2]);
+        self.chunk().write_int16(0, line);
         self.chunk().code.len() - 2
     }
 
     fn patch_jump(&mut self, offset: usize) -> Result<'a, ()> {
-        const BYTE_MASK: usize = (1usize << 8) - 1;
-
         let jump_dist = self.chunk().code.len() - offset - 2; // -2 for the operands
 
         if jump_dist > u16::MAX as usize {
@@ -1902,16 +3599,13 @@ impl<'a, 'b, W: Write> Compiler<'a, 'b, W> {
                 CompileErrorKind::JumpTooLarge,
             ))
         } else {
-            self.chunk().code[offset] = ((jump_dist >> 8) & BYTE_MASK) as u8;
-            self.chunk().code[offset + 1] = (jump_dist & BYTE_MASK) as u8;
+            self.chunk().patch_int16(offset, jump_dist);
             Ok(())
         }
     }
 
     fn emit_loop(&mut self, loop_start: usize) -> Result<'a, ()> {
         // Jumps to the start of the loop
-        const BYTE_MASK: usize = (1usize << 8) - 1;
-
         self.emit_opcode(OpCode::Loop);
 
         let jump_dist = self.chunk().code.len() - loop_start + 2; // +2 for the operands
@@ -1922,8 +3616,8 @@ impl<'a, 'b, W: Write> Compiler<'a, 'b, W> {
                 CompileErrorKind::JumpTooLarge,
             ))
         } else {
-            self.emit_byte(((jump_dist >> 8) & BYTE_MASK) as u8);
-            self.emit_byte((jump_dist & BYTE_MASK) as u8);
+            let line = self.prev_token.line;
+            self.chunk().write_int16(jump_dist, line);
             Ok(())
         }
     }
@@ -1932,12 +3626,102 @@ impl<'a, 'b, W: Write> Compiler<'a, 'b, W> {
         &Self::RULES[kind.as_usize()]
     }
 
-    fn report_err(&mut self, err: CompileError<'a>) {
-        self.had_error = true;
+    /// Runs `parse`, when tracing the parse logging that it enters and leaves `production` at the
+    /// current token, see [`Compiler::set_parse_trace`]
+    fn traced<T>(
+        &mut self,
+        production: &str,
+        parse: impl FnOnce(&mut Self) -> Result<'a, T>,
+    ) -> Result<'a, T> {
+        if self.trace_depth.is_none() {
+            return parse(self);
+        }
+
+        let token = self.curr_token.clone();
+        self.trace(production, &token, parse)
+    }
+
+    /// Runs the parse rule for the token just consumed, traced like [`Self::traced`]. Infix rules
+    /// pass their `precedence`, prefix rules `None`
+    fn traced_rule(
+        &mut self,
+        rule: ParseFn<'a, 'b, W>,
+        can_assign: bool,
+        precedence: Option<Precedence>,
+    ) -> Result<'a, ()> {
+        if self.trace_depth.is_none() {
+            return rule(self, can_assign);
+        }
+
+        let token = self.prev_token.clone();
+        let production = match precedence {
+            Some(precedence) => format!("infix {:?} ({precedence:?})", token.kind),
+            None => format!("prefix {:?}", token.kind),
+        };
+        self.trace(&production, &token, |this| rule(this, can_assign))
+    }
 
+    fn trace<T>(
+        &mut self,
+        production: &str,
+        token: &Token<'a>,
+        parse: impl FnOnce(&mut Self) -> Result<'a, T>,
+    ) -> Result<'a, T> {
+        let depth = self.trace_depth.unwrap_or_default();
+        let indent = "  ".repeat(depth);
+        let line = token.line;
+        let _ = writeln!(
+            self.err_stream,
+            "{indent}{production} `{}` line {line}",
+            token.lexeme
+        );
+
+        self.trace_depth = Some(depth + 1);
+        let result = parse(self);
+        self.trace_depth = Some(depth);
+
+        let outcome = if result.is_ok() { "" } else { " (error)" };
+        let _ = writeln!(self.err_stream, "{indent}end {production}{outcome}");
+        result
+    }
+
+    fn report_warning(&mut self, warning: CompileWarning<'a>) {
         let mut rendered = String::new();
-        err.render(self.source, &mut rendered);
-        // Blank line separates consecutive diagnostics.
-        writeln!(self.err_stream, "{rendered}").unwrap();
+        if self.json_errors {
+            warning.render_json(self.source, self.file_name(), &mut rendered);
+            let _ = writeln!(self.err_stream, "{rendered}");
+            return;
+        }
+
+        if self.file_id == 0 {
+            warning.render(self.source, &mut rendered);
+        } else {
+            warning.render_in_file(self.source, &self.function.source_name, &mut rendered);
+        }
+        let _ = writeln!(self.err_stream, "{rendered}");
+    }
+
+    fn report_err(&mut self, err: CompileError<'a>) {
+        if self.print_errors && self.json_errors {
+            let mut rendered = String::new();
+            err.render_json(self.source, self.file_name(), &mut rendered);
+            let _ = writeln!(self.err_stream, "{rendered}");
+        } else if self.print_errors && self.errors.len() < self.max_errors {
+            let mut rendered = String::new();
+            if self.file_id == 0 {
+                err.render(self.source, &mut rendered);
+            } else {
+                err.render_in_file(self.source, &self.function.source_name, &mut rendered);
+            }
+            // Blank line separates consecutive diagnostics.
+            let _ = writeln!(self.err_stream, "{rendered}");
+        }
+
+        self.errors.push(err);
+    }
+
+    /// Returns the name diagnostics are located in, only given for imported files
+    fn file_name(&self) -> Option<&str> {
+        (self.file_id != 0).then_some(self.function.source_name.as_str())
     }
 }
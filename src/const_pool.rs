@@ -0,0 +1,54 @@
+use crate::value::Value;
+use std::collections::HashMap;
+
+/// Identifies a number by its type and exact bit pattern, so that e.g. `1` and `1.0` or `0.0`
+/// and `-0.0` get separate slots
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum NumberKey {
+    Int(i64),
+    Float(u64),
+}
+
+/// A constant pool shared by every chunk of a program.
+///
+/// Numbers are stored once no matter how many functions use them and are referenced by their
+/// index in the pool. Other constants, such as strings and functions, still live in the
+/// constant table of the chunk that uses them.
+#[derive(Debug, Default)]
+pub struct ConstantPool {
+    values: Vec<Value>,
+    indices: HashMap<NumberKey, usize>,
+}
+
+impl ConstantPool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a number to the pool, or returns the index of an identical number already in it
+    ///
+    /// # Panics
+    /// Panics if `value` is not a number
+    pub fn add(&mut self, value: Value) -> usize {
+        let key = match value {
+            Value::Int(value) => NumberKey::Int(value),
+            Value::Number(value) => NumberKey::Float(value.to_bits()),
+            _ => panic!("only numbers can be pooled, got {}", value.type_name()),
+        };
+
+        *self.indices.entry(key).or_insert_with(|| {
+            self.values.push(value);
+            self.values.len() - 1
+        })
+    }
+
+    /// Returns the number at `index`
+    pub fn get(&self, index: usize) -> Value {
+        self.values[index]
+    }
+
+    /// Consumes the pool, returning its values in index order
+    pub fn into_values(self) -> Vec<Value> {
+        self.values
+    }
+}
@@ -1,21 +1,40 @@
 use crate::chunk::{Chunk, OpCode};
+use crate::value::{Function, Value};
 
-pub fn disassemble_chunk(chunk: &Chunk, chunk_name: &str) {
+/// Disassembles the chunk of `function`, followed by the chunks of the functions nested in it.
+/// `pool` is the program's constant pool
+pub fn disassemble_function(function: &Function, pool: &[Value]) {
+    disassemble_chunk(&function.chunk, pool, &function.name);
+
+    for constant in &function.chunk.constants {
+        if let Some(nested) = constant.as_function() {
+            println!();
+            disassemble_function(nested, pool);
+        }
+    }
+}
+
+pub fn disassemble_chunk(chunk: &Chunk, pool: &[Value], chunk_name: &str) {
     println!("== {} ==", chunk_name);
 
     let mut offset: usize = 0;
 
     while offset < chunk.code.len() {
-        offset = disassemble_instr(chunk, offset);
+        offset = disassemble_instr(chunk, pool, offset);
     }
 }
 
-pub fn disassemble_instr(chunk: &Chunk, offset: usize) -> usize {
+pub fn disassemble_instr(chunk: &Chunk, pool: &[Value], offset: usize) -> usize {
     print!("{:04} {:04} ", offset, chunk.get_line_of(offset));
 
     let instr = chunk.code[offset];
 
-    match OpCode::from(instr) {
+    let Ok(opcode) = OpCode::try_from(instr) else {
+        println!("INVALID_OPCODE {}", instr);
+        return offset + 1;
+    };
+
+    match opcode {
         OpCode::Constant => instr_with_const8(chunk, "CONSTANT", offset),
         OpCode::ConstantLong => instr_with_const24(chunk, "CONSTANT_LONG", offset),
         OpCode::Nil => simple_instr("NIL", offset),
@@ -27,7 +46,6 @@ pub fn disassemble_instr(chunk: &Chunk, offset: usize) -> usize {
         OpCode::Sub => simple_instr("SUB", offset),
         OpCode::Mult => simple_instr("MULT", offset),
         OpCode::Divide => simple_instr("DIVIDE", offset),
-        OpCode::Ternary => simple_instr("TERNARY", offset),
         OpCode::Not => simple_instr("NOT", offset),
         OpCode::Equal => simple_instr("EQUAL", offset),
         OpCode::NotEqual => simple_instr("NOT_EQUAL", offset),
@@ -62,14 +80,39 @@ pub fn disassemble_instr(chunk: &Chunk, offset: usize) -> usize {
         OpCode::SetUpvalueLong => unary_instr24(chunk, "SET_UPVALUE_LONG", offset),
         OpCode::CloseUpvalue => simple_instr("CLOSE_UPVALUE", offset),
         OpCode::Class => instr_with_const8(chunk, "CLASS", offset),
-        OpCode::GetProperty => instr_with_const8(chunk, "GET_PROPERTY", offset),
-        OpCode::SetProperty => instr_with_const8(chunk, "SET_PROPERTY", offset),
+        OpCode::GetProperty => property_instr(chunk, "GET_PROPERTY", offset),
+        OpCode::SetProperty => property_instr(chunk, "SET_PROPERTY", offset),
         OpCode::Method => instr_with_const8(chunk, "METHOD", offset),
+        OpCode::Getter => instr_with_const8(chunk, "GETTER", offset),
+        OpCode::StaticMethod => instr_with_const8(chunk, "STATIC_METHOD", offset),
         OpCode::Invoke => invoke_instr(chunk, "INVOKE", offset),
         OpCode::Inherit => simple_instr("INHERIT", offset),
         OpCode::GetSuper => instr_with_const8(chunk, "GET_SUPER", offset),
         OpCode::SuperInvoke => invoke_instr(chunk, "SUPER_INVOKE", offset),
-        OpCode::Stringify => simple_instr("STRINGIFY", offset),
+        OpCode::Concat => unary_instr8(chunk, "CONCAT", offset),
+        OpCode::ForNum => for_num_instr(chunk, offset),
+        OpCode::IncrementLocal => unary_instr8(chunk, "INCREMENT_LOCAL", offset),
+        OpCode::PrintN => print_n_instr(chunk, offset),
+        OpCode::TailCall => unary_instr8(chunk, "TAIL_CALL", offset),
+        OpCode::ForIn => for_in_instr(chunk, offset),
+        OpCode::GetDefinedGlobal => unary_instr8(chunk, "GET_DEFINED_GLOBAL", offset),
+        OpCode::GetDefinedGlobalLong => unary_instr24(chunk, "GET_DEFINED_GLOBAL_LONG", offset),
+        OpCode::SetDefinedGlobal => unary_instr8(chunk, "SET_DEFINED_GLOBAL", offset),
+        OpCode::SetDefinedGlobalLong => unary_instr24(chunk, "SET_DEFINED_GLOBAL_LONG", offset),
+        OpCode::BitNot => simple_instr("BIT_NOT", offset),
+        OpCode::ShiftLeft => simple_instr("SHIFT_LEFT", offset),
+        OpCode::ShiftRight => simple_instr("SHIFT_RIGHT", offset),
+        OpCode::BitAnd => simple_instr("BIT_AND", offset),
+        OpCode::BitOr => simple_instr("BIT_OR", offset),
+        OpCode::BitXor => simple_instr("BIT_XOR", offset),
+        OpCode::PoolConstant => pool_constant_instr(chunk, pool, offset),
+        OpCode::PoolConstantLong => pool_constant_long_instr(chunk, pool, offset),
+        OpCode::Len => simple_instr("LEN", offset),
+        OpCode::GetPropertyOrNil => property_instr(chunk, "GET_PROPERTY_OR_NIL", offset),
+        OpCode::JumpIfNil => unary_instr16(chunk, "JUMP_IF_NIL", offset),
+        OpCode::BuildList => unary_instr8(chunk, "BUILD_LIST", offset),
+        OpCode::Unpack => unary_instr8(chunk, "UNPACK", offset),
+        OpCode::CollectRest => unary_instr8(chunk, "COLLECT_REST", offset),
     }
 }
 
@@ -87,6 +130,28 @@ fn instr_with_const24(chunk: &Chunk, name: &str, offset: usize) -> usize {
     offset + 4
 }
 
+fn property_instr(chunk: &Chunk, name: &str, offset: usize) -> usize {
+    let idx = chunk.code[offset + 1];
+    let cache = chunk.code[offset + 2];
+
+    println!("{} {:#?} {}", name, chunk.constants[idx as usize], cache);
+    offset + 3
+}
+
+fn pool_constant_instr(chunk: &Chunk, pool: &[Value], offset: usize) -> usize {
+    let idx = chunk.code[offset + 1];
+
+    println!("POOL_CONSTANT {:#?}", pool[idx as usize]);
+    offset + 2
+}
+
+fn pool_constant_long_instr(chunk: &Chunk, pool: &[Value], offset: usize) -> usize {
+    let idx = Chunk::read_int24(&chunk.code[offset + 1..offset + 4]);
+
+    println!("POOL_CONSTANT_LONG {:#?}", pool[idx]);
+    offset + 4
+}
+
 fn simple_instr(name: &str, offset: usize) -> usize {
     println!("{}", name);
     offset + 1
@@ -176,14 +241,41 @@ fn invoke_instr(chunk: &Chunk, name: &str, offset: usize) -> usize {
     offset + 3
 }
 
+fn for_num_instr(chunk: &Chunk, offset: usize) -> usize {
+    let counter = chunk.code[offset + 1];
+    let bound = chunk.code[offset + 2];
+    let jump: usize = Chunk::read_int16(&chunk.code[offset + 3..offset + 5]);
+
+    println!("FOR_NUM {} {} {}", counter, bound, jump);
+    offset + 5
+}
+
+fn for_in_instr(chunk: &Chunk, offset: usize) -> usize {
+    let iterable = chunk.code[offset + 1];
+    let index = chunk.code[offset + 2];
+    let jump: usize = Chunk::read_int16(&chunk.code[offset + 3..offset + 5]);
+
+    println!("FOR_IN {} {} {}", iterable, index, jump);
+    offset + 5
+}
+
+fn print_n_instr(chunk: &Chunk, offset: usize) -> usize {
+    let count = chunk.code[offset + 1];
+    let newline = chunk.code[offset + 2];
+
+    println!("PRINT_N {} {}", count, newline);
+    offset + 3
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::value::Value;
+    use crate::const_pool::ConstantPool;
 
     #[test]
     fn test_chunk() {
         let mut chunk = Chunk::default();
+        let mut pool = ConstantPool::new();
 
         // Constants
         for _ in 0..2 {
@@ -200,12 +292,27 @@ mod tests {
             chunk.write_int24(idx, 2);
         }
 
+        // Pooled constants
+        let idx = pool.add(Value::Int(42));
+        chunk.write_opcode(OpCode::PoolConstant, 2);
+        chunk.write_byte(idx as u8, 2);
+
+        let idx = pool.add(Value::Number(0.5));
+        chunk.write_opcode(OpCode::PoolConstantLong, 2);
+        chunk.write_int24(idx, 2);
+
         // Arithmetic
         chunk.write_opcode(OpCode::Negate, 3);
         chunk.write_opcode(OpCode::Add, 4);
         chunk.write_opcode(OpCode::Sub, 5);
         chunk.write_opcode(OpCode::Divide, 6);
         chunk.write_opcode(OpCode::Mult, 6);
+        chunk.write_opcode(OpCode::BitNot, 6);
+        chunk.write_opcode(OpCode::ShiftLeft, 6);
+        chunk.write_opcode(OpCode::ShiftRight, 6);
+        chunk.write_opcode(OpCode::BitAnd, 6);
+        chunk.write_opcode(OpCode::BitOr, 6);
+        chunk.write_opcode(OpCode::BitXor, 6);
 
         // Nil, True, False literals
         chunk.write_opcode(OpCode::Nil, 7);
@@ -222,8 +329,11 @@ mod tests {
         chunk.write_opcode(OpCode::LessEqual, 8);
 
         chunk.write_opcode(OpCode::Print, 9);
+        chunk.write_opcode(OpCode::PrintN, 9);
+        chunk.write_bytes(&[3, 1], &[9; 2]);
         chunk.write_opcode(OpCode::Pop, 9);
-        chunk.write_opcode(OpCode::Ternary, 9);
+        chunk.write_opcode(OpCode::Concat, 9);
+        chunk.write_byte(4, 9);
 
         // Global variable operations
         chunk.write_opcode(OpCode::DefineGlobal, 10);
@@ -244,6 +354,18 @@ mod tests {
         chunk.write_opcode(OpCode::SetGlobalLong, 12);
         chunk.write_int24(500, 12);
 
+        chunk.write_opcode(OpCode::GetDefinedGlobal, 12);
+        chunk.write_byte(5, 12);
+
+        chunk.write_opcode(OpCode::GetDefinedGlobalLong, 12);
+        chunk.write_int24(500, 12);
+
+        chunk.write_opcode(OpCode::SetDefinedGlobal, 12);
+        chunk.write_byte(5, 12);
+
+        chunk.write_opcode(OpCode::SetDefinedGlobalLong, 12);
+        chunk.write_int24(500, 12);
+
         // Local variable operations
         chunk.write_opcode(OpCode::GetLocal, 13);
         chunk.write_byte(1, 13);
@@ -276,6 +398,56 @@ mod tests {
         chunk.write_opcode(OpCode::JumpIfTrue, 8);
         chunk.write_int16(375, 9);
 
-        disassemble_chunk(&chunk, "simple test chunk");
+        chunk.write_opcode(OpCode::JumpIfNil, 8);
+        chunk.write_int16(625, 9);
+
+        // Counted loops
+        chunk.write_opcode(OpCode::ForNum, 10);
+        chunk.write_bytes(&[1, 2], &[10; 2]);
+        chunk.write_int16(42, 10);
+
+        chunk.write_opcode(OpCode::IncrementLocal, 10);
+        chunk.write_byte(1, 10);
+
+        chunk.write_opcode(OpCode::ForIn, 10);
+        chunk.write_bytes(&[1, 2], &[10; 2]);
+        chunk.write_int16(42, 10);
+
+        // Calls
+        chunk.write_opcode(OpCode::Call, 11);
+        chunk.write_byte(2, 11);
+
+        chunk.write_opcode(OpCode::TailCall, 11);
+        chunk.write_byte(2, 11);
+
+        chunk.write_opcode(OpCode::Len, 11);
+
+        // Multiple values
+        chunk.write_opcode(OpCode::BuildList, 11);
+        chunk.write_byte(2, 11);
+
+        chunk.write_opcode(OpCode::Unpack, 11);
+        chunk.write_byte(2, 11);
+
+        chunk.write_opcode(OpCode::CollectRest, 11);
+        chunk.write_byte(1, 11);
+
+        // Properties
+        chunk.write_opcode(OpCode::GetProperty, 11);
+        chunk.write_byte(0, 11);
+        chunk.write_byte(0, 11);
+
+        chunk.write_opcode(OpCode::SetProperty, 11);
+        chunk.write_byte(0, 11);
+        chunk.write_byte(1, 11);
+
+        chunk.write_opcode(OpCode::GetPropertyOrNil, 11);
+        chunk.write_byte(0, 11);
+        chunk.write_byte(2, 11);
+
+        // Corrupt bytecode
+        chunk.write_byte(u8::MAX, 12);
+
+        disassemble_chunk(&chunk, &pool.into_values(), "simple test chunk");
     }
 }
@@ -5,8 +5,11 @@
 //! - [`CompileError`] for errors produced while parsing/compiling, rendered as
 //!   rustc-style diagnostics with a source snippet,
 //! - [`RuntimeError`] for errors raised by the virtual machine at run time.
+//!
+//! Compile diagnostics can also be rendered as one line of JSON each, for editors and CI.
 
 use std::fmt::{self, Display, Write as _};
+use std::ops::Range;
 
 use crate::token::{Token, TokenKind};
 
@@ -39,6 +42,7 @@ impl Display for ScanError {
 pub enum Expected {
     Expression,
     VariableName,
+    ConstantName,
     FunctionName,
     ClassName,
     SuperclassName,
@@ -48,13 +52,18 @@ pub enum Expected {
     SuperclassMethodName,
     Semicolon,
     SemicolonAfterCondition,
+    ImportPath,
     LeftParen,
     LeftParenAfterFunctionName,
     RightParen,
     RightParenAfterParameters,
     RightParenAfterArguments,
+    RightParenAfterVariableNames,
+    EqualAfterVariableNames,
+    EqualAfterConstantName,
     LeftBraceBeforeFunctionBody,
     LeftBraceBeforeClassBody,
+    LeftBraceBeforeLoopBody,
     RightBraceAfterClassBody,
     RightBraceToCloseBlock,
     RightBraceToCloseInterpolation,
@@ -67,6 +76,7 @@ impl Display for Expected {
         let s = match self {
             Expected::Expression => "expression",
             Expected::VariableName => "variable name",
+            Expected::ConstantName => "constant name",
             Expected::FunctionName => "function name",
             Expected::ClassName => "class name",
             Expected::SuperclassName => "superclass name after ':'",
@@ -76,13 +86,18 @@ impl Display for Expected {
             Expected::SuperclassMethodName => "superclass method name",
             Expected::Semicolon => "';' after statement",
             Expected::SemicolonAfterCondition => "';' after loop condition",
+            Expected::ImportPath => "file path string after 'import'",
             Expected::LeftParen => "'('",
             Expected::LeftParenAfterFunctionName => "'(' after function name",
             Expected::RightParen => "')'",
             Expected::RightParenAfterParameters => "')' after parameters",
             Expected::RightParenAfterArguments => "')' after arguments",
+            Expected::RightParenAfterVariableNames => "')' after variable names",
+            Expected::EqualAfterVariableNames => "'=' after variable names",
+            Expected::EqualAfterConstantName => "'=' and an initializer after constant name",
             Expected::LeftBraceBeforeFunctionBody => "'{' before function body",
             Expected::LeftBraceBeforeClassBody => "'{' before class body",
+            Expected::LeftBraceBeforeLoopBody => "'{' before loop body",
             Expected::RightBraceAfterClassBody => "'}' after class body",
             Expected::RightBraceToCloseBlock => "'}' to close block",
             Expected::RightBraceToCloseInterpolation => "'}' to close interpolation",
@@ -108,8 +123,24 @@ pub enum CompileErrorKind {
     VariableInOwnInitializer(String),
     /// A variable declared twice in the same scope.
     RedeclaredVariable(String),
+    /// An assignment to a variable declared with `const`.
+    AssignToConstant(String),
     /// A class listed itself as its own superclass.
     InheritFromSelf,
+    /// A `cond ? then` without the `:` and else branch. Holds the source text of the
+    /// condition and the then branch.
+    TernaryMissingColon {
+        condition: String,
+        then_branch: String,
+    },
+    /// A `\u{...}` escape in a string literal without 1 to 6 hex digits between
+    /// its braces. Holds the escape.
+    MalformedUnicodeEscape(String),
+    /// A `\u{...}` escape naming a surrogate or a number past the last code
+    /// point. Holds the escape.
+    InvalidCodePoint(String),
+    /// A comparison chained to another, as in `1 < x < 10`. Holds the suggested rewrite.
+    ChainedComparison(String),
     /// A `return` statement outside of any function body.
     ReturnOutsideFunction,
     /// A value returned from a class initializer.
@@ -118,16 +149,56 @@ pub enum CompileErrorKind {
     ContinueOutsideLoop,
     /// A `break` statement outside of any loop.
     BreakOutsideLoop,
+    /// A `break` or `continue` naming a label that no enclosing loop has.
+    UndefinedLabel(String),
+    /// An `import` statement inside a block or function.
+    ImportNotAtTopLevel,
+    /// An imported file that could not be read. Holds its path and the reason.
+    ImportFailed {
+        path: String,
+        reason: std::io::ErrorKind,
+    },
+    /// An imported file that is still being imported, directly or through other imports.
+    CyclicImport(String),
+    /// An imported file with compile errors of its own.
+    ImportErrors(String),
+    /// A global declared in one file that another file of the program already declares. Holds
+    /// the name of the global and the path of the other file.
+    DeclaredInOtherFile { name: String, file: Box<str> },
     /// `this` used outside of a method.
     ThisOutsideClass,
     /// `super` used outside of a class.
     SuperOutsideClass,
     /// `super` used in a class that has no superclass.
     SuperWithoutSuperclass,
+    /// `this` used in a static method, which has no instance.
+    ThisInStaticMethod,
+    /// `super` used in a static method, which has no instance.
+    SuperInStaticMethod,
+    /// A getter and a method of the same name declared in one class. Holds the name.
+    GetterAndMethod(String),
     /// More than 255 parameters in a function declaration.
     TooManyParameters,
+    /// A parameter without a default value after one with a default value.
+    RequiredParameterAfterDefault(String),
+    /// A parameter after the `...rest` parameter.
+    ParameterAfterRest,
     /// More than 255 arguments in a call expression.
     TooManyArguments,
+    /// A direct call of a function declared with `fun` with an argument count it never accepts.
+    /// `max` is `None` if the function has a `...rest` parameter.
+    ArgCount {
+        name: String,
+        min: u8,
+        max: Option<u8>,
+        got: u8,
+    },
+    /// More than 255 values in a `print` statement.
+    TooManyPrintValues,
+    /// More than 255 values in a `return` statement.
+    TooManyReturnValues,
+    /// More than 255 variables in a destructuring `var` declaration.
+    TooManyDestructuredVariables,
     /// More upvalues captured by a closure than the bytecode can encode.
     TooManyUpvalues,
     /// More constants in a chunk than the bytecode can encode.
@@ -153,7 +224,31 @@ impl Display for CompileErrorKind {
             CompileErrorKind::RedeclaredVariable(name) => {
                 write!(f, "variable '{name}' is already declared in this scope")
             }
+            CompileErrorKind::AssignToConstant(name) => {
+                write!(f, "cannot assign to constant '{name}'")
+            }
             CompileErrorKind::InheritFromSelf => f.write_str("a class cannot inherit from itself"),
+            CompileErrorKind::TernaryMissingColon {
+                condition,
+                then_branch,
+            } => {
+                write!(
+                    f,
+                    "expected ':' and an else branch after '{condition} ? {then_branch}'"
+                )
+            }
+            CompileErrorKind::MalformedUnicodeEscape(escape) => {
+                write!(
+                    f,
+                    "malformed unicode escape '{escape}', expected 1 to 6 hex digits in '\\u{{...}}'"
+                )
+            }
+            CompileErrorKind::InvalidCodePoint(escape) => {
+                write!(f, "'{escape}' is not a valid unicode code point")
+            }
+            CompileErrorKind::ChainedComparison(suggestion) => {
+                write!(f, "comparisons cannot be chained, use '{suggestion}'")
+            }
             CompileErrorKind::ReturnOutsideFunction => {
                 f.write_str("'return' can only be used inside a function")
             }
@@ -166,6 +261,27 @@ impl Display for CompileErrorKind {
             CompileErrorKind::BreakOutsideLoop => {
                 f.write_str("'break' can only be used inside a loop")
             }
+            CompileErrorKind::UndefinedLabel(label) => {
+                write!(f, "no enclosing loop is labeled '{label}'")
+            }
+            CompileErrorKind::ImportNotAtTopLevel => {
+                f.write_str("'import' can only be used at the top level of a file")
+            }
+            CompileErrorKind::ImportFailed { path, reason } => {
+                write!(f, "cannot import '{path}': {reason}")
+            }
+            CompileErrorKind::CyclicImport(path) => {
+                write!(
+                    f,
+                    "'{path}' imports itself, directly or through other imports"
+                )
+            }
+            CompileErrorKind::ImportErrors(path) => {
+                write!(f, "could not compile imported file '{path}'")
+            }
+            CompileErrorKind::DeclaredInOtherFile { name, file } => {
+                write!(f, "'{name}' is already declared in '{file}'")
+            }
             CompileErrorKind::ThisOutsideClass => {
                 f.write_str("'this' can only be used inside a method")
             }
@@ -175,12 +291,53 @@ impl Display for CompileErrorKind {
             CompileErrorKind::SuperWithoutSuperclass => {
                 f.write_str("'super' can only be used in a class with a superclass")
             }
+            CompileErrorKind::ThisInStaticMethod => {
+                f.write_str("'this' can't be used in a static method")
+            }
+            CompileErrorKind::SuperInStaticMethod => {
+                f.write_str("'super' can't be used in a static method")
+            }
+            CompileErrorKind::GetterAndMethod(name) => {
+                write!(f, "'{name}' is declared as both a getter and a method")
+            }
             CompileErrorKind::TooManyParameters => {
                 f.write_str("cannot have more than 255 parameters")
             }
+            CompileErrorKind::ParameterAfterRest => {
+                f.write_str("the '...' parameter must be the last parameter")
+            }
+            CompileErrorKind::RequiredParameterAfterDefault(name) => {
+                write!(
+                    f,
+                    "parameter '{name}' needs a default value, as an earlier parameter has one"
+                )
+            }
             CompileErrorKind::TooManyArguments => {
                 f.write_str("cannot have more than 255 arguments")
             }
+            CompileErrorKind::ArgCount {
+                name,
+                min,
+                max,
+                got,
+            } => {
+                write!(f, "incorrect number of arguments to '{name}': expected ")?;
+                match max {
+                    None => write!(f, "at least {min}")?,
+                    Some(max) if max == min => write!(f, "{max}")?,
+                    Some(max) => write!(f, "{min} to {max}")?,
+                }
+                write!(f, ", got {got}")
+            }
+            CompileErrorKind::TooManyPrintValues => {
+                f.write_str("cannot print more than 255 values")
+            }
+            CompileErrorKind::TooManyReturnValues => {
+                f.write_str("cannot return more than 255 values")
+            }
+            CompileErrorKind::TooManyDestructuredVariables => {
+                f.write_str("cannot destructure into more than 255 variables")
+            }
             CompileErrorKind::TooManyUpvalues => {
                 f.write_str("too many variables captured by a closure")
             }
@@ -193,14 +350,19 @@ impl Display for CompileErrorKind {
 }
 
 /// A compile error together with the source location it refers to.
+#[derive(Debug, Clone)]
 pub struct CompileError<'a> {
     pub kind: CompileErrorKind,
     pub token: Token<'a>,
+    /// Byte range of the offending token in the source, for tools that
+    /// underline errors.
+    pub span: Range<usize>,
 }
 
 impl<'a> CompileError<'a> {
     pub fn new(token: Token<'a>, kind: CompileErrorKind) -> Self {
-        CompileError { kind, token }
+        let span = token.span.clone();
+        CompileError { kind, token, span }
     }
 
     /// Renders this error as a rustc-style diagnostic into `out`.
@@ -208,56 +370,240 @@ impl<'a> CompileError<'a> {
     /// `source` must be the full source text the token was scanned from so the
     /// offending line can be shown with a caret underneath the token.
     pub fn render(&self, source: &str, out: &mut String) {
-        let line_no = self.token.line.max(1);
-        let src_line = source.lines().nth(line_no - 1).unwrap_or("");
-        let line_width = src_line.chars().count();
+        self.render_at(source, None, out);
+    }
 
-        // Determine where the caret points and how wide it is.
-        let (caret_col, caret_len) = match self.token.kind {
-            // Past the end of input: point just after the last character.
-            TokenKind::Eof => (line_width + 1, 1),
-            // Unterminated literals span to end of file, so a single caret at
-            // the opening delimiter reads more clearly than a giant underline.
-            _ => match &self.kind {
-                CompileErrorKind::Scan(
-                    ScanError::UnterminatedString | ScanError::UnterminatedComment,
-                ) => (self.token.column.max(1), 1),
-                _ => (
-                    self.token.column.max(1),
-                    self.token.lexeme.chars().count().max(1),
-                ),
-            },
-        };
+    /// Like [`CompileError::render`], but points at the line in the named file, for errors in
+    /// imported files.
+    pub fn render_in_file(&self, source: &str, file: &str, out: &mut String) {
+        self.render_at(source, Some(file), out);
+    }
 
-        let line_str = line_no.to_string();
-        let gutter = " ".repeat(line_str.len());
-        let pad = " ".repeat(caret_col - 1);
-        let carets = "^".repeat(caret_len);
+    fn render_at(&self, source: &str, file: Option<&str>, out: &mut String) {
+        // Unterminated literals span to end of file, so a single caret at the
+        // opening delimiter reads more clearly than a giant underline.
+        let single_caret = matches!(
+            self.kind,
+            CompileErrorKind::Scan(ScanError::UnterminatedString | ScanError::UnterminatedComment)
+        );
+
+        render_diagnostic(
+            "error",
+            &self.kind,
+            &self.token,
+            single_caret,
+            source,
+            file,
+            out,
+        );
+    }
 
-        // `write!` into a String is infallible; the `?`-free `.ok()` keeps the
-        // call sites tidy without an unwrap.
-        let _ = writeln!(out, "error: {}", self.kind);
-        let _ = writeln!(out, "{gutter}--> line {line_no}:{caret_col}");
-        let _ = writeln!(out, "{gutter} |");
-        let _ = writeln!(out, "{line_str} | {src_line}");
-        let _ = writeln!(out, "{gutter} | {pad}{carets}");
+    /// Renders this error as one line of JSON into `out`, with its line, column, message and
+    /// severity. The file is only included when given, as for errors in imported files.
+    pub fn render_json(&self, source: &str, file: Option<&str>, out: &mut String) {
+        render_json_diagnostic("error", &self.kind, &self.token, source, file, out);
     }
 }
 
+/// The kind of warning produced while compiling source into bytecode. Unlike
+/// errors, warnings do not stop the program from running.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CompileWarningKind {
+    /// A statement following a `return`, `break` or `continue` in the same
+    /// block. Holds the keyword.
+    UnreachableCode(String),
+    /// A local variable that is never read. Holds the variable's name.
+    UnusedVariable(String),
+    /// A global variable that is never declared. Holds the variable's name.
+    UndefinedVariable(String),
+    /// A global variable used by the main function before its declaration runs.
+    /// Holds the variable's name.
+    UsedBeforeDefinition(String),
+    /// A bare `;` as the body of a loop or `if`, e.g. `while (cond);`. Holds the keyword.
+    /// Only reported in strict mode.
+    EmptyBody(String),
+}
+
+impl Display for CompileWarningKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompileWarningKind::UnreachableCode(keyword) => {
+                write!(f, "unreachable code after '{keyword}'")
+            }
+            CompileWarningKind::UnusedVariable(name) => write!(f, "unused variable '{name}'"),
+            CompileWarningKind::UndefinedVariable(name) => {
+                write!(f, "undefined variable '{name}'")
+            }
+            CompileWarningKind::UsedBeforeDefinition(name) => {
+                write!(f, "variable '{name}' is used before its definition")
+            }
+            CompileWarningKind::EmptyBody(keyword) => {
+                write!(
+                    f,
+                    "empty body of '{keyword}', use '{{}}' for an intentionally empty body"
+                )
+            }
+        }
+    }
+}
+
+/// A compile warning together with the source location it refers to.
+pub struct CompileWarning<'a> {
+    pub kind: CompileWarningKind,
+    pub token: Token<'a>,
+}
+
+impl<'a> CompileWarning<'a> {
+    pub fn new(token: Token<'a>, kind: CompileWarningKind) -> Self {
+        CompileWarning { kind, token }
+    }
+
+    /// Renders this warning as a rustc-style diagnostic into `out`, see
+    /// [`CompileError::render`].
+    pub fn render(&self, source: &str, out: &mut String) {
+        render_diagnostic("warning", &self.kind, &self.token, false, source, None, out);
+    }
+
+    /// Renders this warning pointing at the line in the named file, see
+    /// [`CompileError::render_in_file`].
+    pub fn render_in_file(&self, source: &str, file: &str, out: &mut String) {
+        render_diagnostic(
+            "warning",
+            &self.kind,
+            &self.token,
+            false,
+            source,
+            Some(file),
+            out,
+        );
+    }
+
+    /// Renders this warning as one line of JSON into `out`, see [`CompileError::render_json`].
+    pub fn render_json(&self, source: &str, file: Option<&str>, out: &mut String) {
+        render_json_diagnostic("warning", &self.kind, &self.token, source, file, out);
+    }
+}
+
+/// Renders a diagnostic headed by `severity` with a caret underneath `token`, located in `file`
+/// if given.
+fn render_diagnostic(
+    severity: &str,
+    message: &dyn Display,
+    token: &Token,
+    single_caret: bool,
+    source: &str,
+    file: Option<&str>,
+    out: &mut String,
+) {
+    let line_no = token.line.max(1);
+    let src_line = source.lines().nth(line_no - 1).unwrap_or("");
+    let caret_col = column_of(token, source);
+
+    // Determine how wide the caret is.
+    let caret_len = match token.kind {
+        TokenKind::Eof => 1,
+        _ if single_caret => 1,
+        _ => token.lexeme.chars().count().max(1),
+    };
+
+    let line_str = line_no.to_string();
+    let gutter = " ".repeat(line_str.len());
+    let pad = " ".repeat(caret_col - 1);
+    let carets = "^".repeat(caret_len);
+
+    // `write!` into a String is infallible; the `?`-free `.ok()` keeps the
+    // call sites tidy without an unwrap.
+    let _ = writeln!(out, "{severity}: {message}");
+    let _ = match file {
+        Some(file) => writeln!(out, "{gutter}--> {file}:{line_no}:{caret_col}"),
+        None => writeln!(out, "{gutter}--> line {line_no}:{caret_col}"),
+    };
+    let _ = writeln!(out, "{gutter} |");
+    let _ = writeln!(out, "{line_str} | {src_line}");
+    let _ = writeln!(out, "{gutter} | {pad}{carets}");
+}
+
+/// Renders a diagnostic as a JSON object on a single line, located in `file` if given.
+fn render_json_diagnostic(
+    severity: &str,
+    message: &dyn Display,
+    token: &Token,
+    source: &str,
+    file: Option<&str>,
+    out: &mut String,
+) {
+    out.push('{');
+    if let Some(file) = file {
+        out.push_str("\"file\":");
+        write_json_string(file, out);
+        out.push(',');
+    }
+    let _ = write!(
+        out,
+        "\"line\":{},\"col\":{},\"message\":",
+        token.line.max(1),
+        column_of(token, source)
+    );
+    write_json_string(&message.to_string(), out);
+    out.push_str(",\"severity\":");
+    write_json_string(severity, out);
+    out.push('}');
+}
+
+/// Returns the column a diagnostic about `token` points at, counted in characters from 1.
+fn column_of(token: &Token, source: &str) -> usize {
+    match token.kind {
+        // Past the end of input: point just after the last character.
+        TokenKind::Eof => {
+            let line_no = token.line.max(1);
+            let src_line = source.lines().nth(line_no - 1).unwrap_or("");
+            src_line.chars().count() + 1
+        }
+        _ => token.column.max(1),
+    }
+}
+
+/// Writes `value` to `out` as a quoted JSON string.
+pub(crate) fn write_json_string(value: &str, out: &mut String) {
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
 /// An error raised by the virtual machine while executing bytecode.
 #[derive(Debug, Clone, PartialEq)]
 pub enum RuntimeError {
     /// Operand to unary `-` was not a number.
     NegateOperandNotNumber,
-    /// Operand to unary `!` was not a bool.
-    NotOperandNotBool,
-    /// The predicate of a ternary expression was not a bool.
-    TernaryPredicateNotBool,
-    /// A loop or `if` condition was not a bool.
-    ConditionNotBool,
+    /// Operand to unary `~` was not an int.
+    BitNotOperandNotInt,
     /// Both operands to a numeric binary operator must be numbers.
     /// Holds the operator lexeme (e.g. `"-"`, `">="`).
     BinaryOperandsNotNumbers(&'static str),
+    /// Both operands to an ordering operator must be numbers.
+    /// Holds the operator lexeme and the type names of the operands.
+    ComparisonOperandsNotNumbers {
+        op: &'static str,
+        left: &'static str,
+        right: &'static str,
+    },
+    /// Both operands to a bitwise binary operator must be ints.
+    /// Holds the operator lexeme (e.g. `"<<"`).
+    BinaryOperandsNotInts(&'static str),
+    /// A division by zero under [`DivByZero::Error`](crate::vm::DivByZero::Error).
+    DivisionByZero,
     /// Operands to `+` must be two numbers or two strings.
     AddOperandsInvalid,
     /// A property was accessed on a value that is not a class instance.
@@ -270,8 +616,16 @@ pub enum RuntimeError {
     NotCallable,
     /// A call passed the wrong number of arguments.
     ArgCountMismatch { expected: u8, got: u8 },
+    /// A call to a function with default parameter values passed too few or too many arguments.
+    ArgCountOutOfRange { min: u8, max: u8, got: u8 },
+    /// A call to a function with a `...rest` parameter passed too few arguments.
+    ArgCountBelowMinimum { min: u8, got: u8 },
     /// A class initializer received arguments but takes none.
     InitializerArgCount(u8),
+    /// A call from the host passed more arguments than a call can hold. Holds the number passed.
+    TooManyArguments(usize),
+    /// A call from the host stopped without reporting why.
+    CallFailed,
     /// A reference to an undefined global variable.
     UndefinedVariable(String),
     /// A reference to a method that does not exist on a class.
@@ -280,6 +634,19 @@ pub enum RuntimeError {
     UndefinedProperty(String),
     /// The value stack exceeded its maximum size. Holds the limit.
     StackOverflow(usize),
+    /// A `for (item in iterable)` loop over a value that is not a list. Holds its type name.
+    NotIterable(&'static str),
+    /// A destructuring `var` declaration of a list with more items than variables.
+    UnpackTooManyValues { expected: u8, got: usize },
+    /// A `to_string` method returned something other than a string. Holds its type name.
+    ToStringNotString(&'static str),
+    /// `to_string` methods were nested more deeply than the limit it holds, e.g. by printing
+    /// `this` from within `to_string`.
+    ToStringTooDeep(usize),
+    /// An `equals` method returned something other than a bool. Holds its type name.
+    EqualsNotBool(&'static str),
+    /// The bytecode held a byte that is not an opcode.
+    InvalidOpcode(u8),
     /// An error returned by a native function.
     Native(String),
 }
@@ -288,14 +655,20 @@ impl Display for RuntimeError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             RuntimeError::NegateOperandNotNumber => f.write_str("Operand to '-' must be a number"),
-            RuntimeError::NotOperandNotBool => f.write_str("Operand to '!' must be a bool"),
-            RuntimeError::TernaryPredicateNotBool => {
-                f.write_str("Expected a boolean as ternary operator predicate")
-            }
-            RuntimeError::ConditionNotBool => f.write_str("Expected `bool` as condition"),
+            RuntimeError::BitNotOperandNotInt => f.write_str("Operand to '~' must be an int"),
             RuntimeError::BinaryOperandsNotNumbers(op) => {
                 write!(f, "Operands to '{op}' must be numbers")
             }
+            RuntimeError::ComparisonOperandsNotNumbers { op, left, right } => {
+                write!(
+                    f,
+                    "Operands to '{op}' must be numbers, got {left} and {right}"
+                )
+            }
+            RuntimeError::BinaryOperandsNotInts(op) => {
+                write!(f, "Operands to '{op}' must be ints")
+            }
+            RuntimeError::DivisionByZero => f.write_str("Division by zero"),
             RuntimeError::AddOperandsInvalid => {
                 f.write_str("Operands to '+' must be two numbers or strings")
             }
@@ -313,15 +686,59 @@ impl Display for RuntimeError {
                     "Incorrect number of arguments: expected {expected}, got {got}"
                 )
             }
+            RuntimeError::ArgCountBelowMinimum { min, got } => {
+                write!(
+                    f,
+                    "Incorrect number of arguments: expected at least {min}, got {got}"
+                )
+            }
+            RuntimeError::ArgCountOutOfRange { min, max, got } => {
+                write!(
+                    f,
+                    "Incorrect number of arguments: expected {min} to {max}, got {got}"
+                )
+            }
             RuntimeError::InitializerArgCount(got) => {
                 write!(f, "Expected 0 arguments for class initializer, got {got}")
             }
+            RuntimeError::TooManyArguments(got) => {
+                write!(f, "Can't pass more than 255 arguments, got {got}")
+            }
+            RuntimeError::CallFailed => f.write_str("Call failed"),
             RuntimeError::UndefinedVariable(name) => write!(f, "Undefined variable '{name}'"),
             RuntimeError::UndefinedMethod(name) => write!(f, "Undefined method '{name}'"),
             RuntimeError::UndefinedProperty(name) => write!(f, "Undefined property '{name}'"),
             RuntimeError::StackOverflow(limit) => {
                 write!(f, "Stack overflow: maximum stack size is {limit}")
             }
+            RuntimeError::NotIterable(type_name) => {
+                write!(
+                    f,
+                    "Can only iterate over lists, but got a value of type '{type_name}'"
+                )
+            }
+            RuntimeError::UnpackTooManyValues { expected, got } => {
+                write!(
+                    f,
+                    "Too many values to unpack: expected at most {expected}, got {got}"
+                )
+            }
+            RuntimeError::ToStringNotString(type_name) => {
+                write!(
+                    f,
+                    "'to_string' must return a string, but got a value of type '{type_name}'"
+                )
+            }
+            RuntimeError::ToStringTooDeep(limit) => {
+                write!(f, "Too many nested 'to_string' calls: the limit is {limit}")
+            }
+            RuntimeError::EqualsNotBool(type_name) => {
+                write!(
+                    f,
+                    "'equals' must return a bool, but got a value of type '{type_name}'"
+                )
+            }
+            RuntimeError::InvalidOpcode(byte) => write!(f, "Invalid opcode {byte}"),
             RuntimeError::Native(msg) => f.write_str(msg),
         }
     }
@@ -4,19 +4,32 @@
 //! memory for all heap-allocated objects in the runtime. Collection is triggered
 //! based on the number of live objects, with the threshold growing after each
 //! cycle.
+//!
+//! Every object is allocated through the typed `alloc_*` methods, one per kind of object. Each
+//! returns a [`Value`] wrapping the new object, and its `alloc_*_ptr` twin returns the raw
+//! pointer for callers that store it in another object. These are the only allocation entry
+//! points, both for the virtual machine and for embedders.
+//!
+//! Instances whose class defines a `finalize` method are not freed the first time they are
+//! found unreachable. Instead they are kept alive, along with everything they reference, and
+//! queued for the virtual machine to finalize. The queue is a root, so an instance is only freed
+//! once a later collection finds it unreachable again. A finalizer that stores `this` somewhere
+//! reachable therefore safely resurrects the instance, but its finalizer never runs twice.
 
 use crate::native::NativeFunc;
 use crate::value::BoundMethod;
-use crate::value::{Class, ClassInstance, Closure, Function, Upvalue, Value};
-use std::collections::HashSet;
+use crate::value::{Class, ClassInstance, Closure, Function, List, Upvalue, Value, WeakRef};
+use std::collections::{HashSet, VecDeque};
 
-static GC_DEFAULT_THRESHOLD: usize = 1024; // live objects
-static GC_THRESHOLD_GROWTH_FACTOR: f64 = 2.0;
+pub static GC_DEFAULT_THRESHOLD: usize = 1024; // live objects
+pub static GC_THRESHOLD_GROWTH_FACTOR: f64 = 2.0;
 
 #[derive(Debug)]
 pub struct GC {
     live_objects: usize,
     next_gc: usize,
+    threshold: usize,   // Minimum number of live objects before a collection
+    growth_factor: f64, // Factor by which the threshold grows after a collection
 
     strings: Vec<*mut String>,
     functions: Vec<*mut Function>,
@@ -26,6 +39,8 @@ pub struct GC {
     classes: Vec<*mut Class>,
     class_instances: Vec<*mut ClassInstance>,
     bound_methods: Vec<*mut BoundMethod>,
+    lists: Vec<*mut List>,
+    weak_refs: Vec<*mut WeakRef>,
 
     // "black" GC pointers that have had their references traced
     marked_strings: HashSet<*mut String>,
@@ -36,6 +51,8 @@ pub struct GC {
     marked_classes: HashSet<*mut Class>,
     marked_class_instances: HashSet<*mut ClassInstance>,
     marked_bound_methods: HashSet<*mut BoundMethod>,
+    marked_lists: HashSet<*mut List>,
+    marked_weak_refs: HashSet<*mut WeakRef>,
 
     // Currently "gray" GC pointers that have not had their references traced
     worklist_functions: Vec<*mut Function>,
@@ -44,6 +61,10 @@ pub struct GC {
     worklist_classes: Vec<*mut Class>,
     worklist_class_instances: Vec<*mut ClassInstance>,
     worklist_bound_methods: Vec<*mut BoundMethod>,
+    worklist_lists: Vec<*mut List>,
+
+    // Unreachable instances whose `finalize` method has yet to run, oldest first
+    finalize_queue: VecDeque<*mut ClassInstance>,
 }
 
 macro_rules! impl_alloc_methods {
@@ -75,9 +96,18 @@ impl Default for GC {
 
 impl GC {
     pub fn new() -> Self {
+        Self::with_params(GC_DEFAULT_THRESHOLD, GC_THRESHOLD_GROWTH_FACTOR)
+    }
+
+    /// Creates a collector that first runs once more than `threshold` objects are live. After
+    /// each collection, the next one is scheduled for when the number of live objects has grown
+    /// by `growth_factor`, but never below `threshold`
+    pub fn with_params(threshold: usize, growth_factor: f64) -> Self {
         GC {
             live_objects: 0,
-            next_gc: GC_DEFAULT_THRESHOLD,
+            next_gc: threshold,
+            threshold,
+            growth_factor,
             strings: Vec::new(),
             functions: Vec::new(),
             closures: Vec::new(),
@@ -86,6 +116,8 @@ impl GC {
             classes: Vec::new(),
             class_instances: Vec::new(),
             bound_methods: Vec::new(),
+            lists: Vec::new(),
+            weak_refs: Vec::new(),
             marked_strings: HashSet::new(),
             marked_functions: HashSet::new(),
             marked_closures: HashSet::new(),
@@ -94,12 +126,16 @@ impl GC {
             marked_classes: HashSet::new(),
             marked_class_instances: HashSet::new(),
             marked_bound_methods: HashSet::new(),
+            marked_lists: HashSet::new(),
+            marked_weak_refs: HashSet::new(),
             worklist_functions: Vec::new(),
             worklist_closures: Vec::new(),
             worklist_upvalues: Vec::new(),
             worklist_classes: Vec::new(),
             worklist_class_instances: Vec::new(),
             worklist_bound_methods: Vec::new(),
+            worklist_lists: Vec::new(),
+            finalize_queue: VecDeque::new(),
         }
     }
 
@@ -135,7 +171,9 @@ impl GC {
             bound_methods,
             BoundMethod,
             BoundMethod
-        )
+        ),
+        (alloc_list, alloc_list_ptr, lists, List, List),
+        (alloc_weak_ref, alloc_weak_ref_ptr, weak_refs, WeakRef, Weak)
     );
 
     /// Marks a value as reachable
@@ -179,7 +217,17 @@ impl GC {
                 }
                 self.mark_bound_method(ptr)
             }
-            Value::Nil | Value::Bool(_) | Value::Number(_) => {}
+            Value::List(ptr) => {
+                if self.marked_lists.contains(&ptr) {
+                    return;
+                }
+                self.mark_list(ptr)
+            }
+            // The target is deliberately not marked, see `clear_dead_weak_refs`
+            Value::Weak(ptr) => {
+                self.marked_weak_refs.insert(ptr);
+            }
+            Value::Nil | Value::Bool(_) | Value::Int(_) | Value::Number(_) => {}
         }
     }
 
@@ -229,6 +277,12 @@ impl GC {
         self.worklist_bound_methods.push(ptr);
     }
 
+    /// Marks a list pointer as reachable
+    pub fn mark_list(&mut self, ptr: *mut List) {
+        self.marked_lists.insert(ptr);
+        self.worklist_lists.push(ptr);
+    }
+
     /// Traces all values that are reachable from the roots
     pub fn trace_references(&mut self) {
         // FIXME: Not very efficient, but works for now
@@ -238,15 +292,23 @@ impl GC {
             || !self.worklist_classes.is_empty()
             || !self.worklist_class_instances.is_empty()
             || !self.worklist_bound_methods.is_empty()
+            || !self.worklist_lists.is_empty()
         {
             while let Some(ptr) = self.worklist_functions.pop() {
-                // Mark the constants in the function's chunk
+                // Mark the constants in the function's chunk that are objects
                 unsafe {
-                    let chunk = &(*ptr).chunk;
-
-                    for constant in &chunk.constants {
+                    for constant in &(*ptr).object_constants {
                         self.mark_value(*constant);
                     }
+
+                    // Keep cached classes alive, a new class at the same address would hit
+                    for cache in &(*ptr).chunk.property_caches {
+                        let class = cache.class as *mut Class;
+
+                        if !class.is_null() && !self.marked_classes.contains(&class) {
+                            self.mark_class(class);
+                        }
+                    }
                 }
             }
 
@@ -266,16 +328,18 @@ impl GC {
             }
 
             while let Some(ptr) = self.worklist_upvalues.pop() {
+                // An open upvalue's value is on the stack, which is a root
                 unsafe {
-                    // FIXME: Use the `closed` field instead?
-                    self.mark_value(*((*ptr).location));
+                    self.mark_value((*ptr).closed);
                 }
             }
 
             while let Some(ptr) = self.worklist_classes.pop() {
-                // Mark the methods
+                // Mark the methods, getters and static methods
                 unsafe {
-                    for v in (*ptr).methods.values() {
+                    let class = &*ptr;
+                    let methods = class.methods.values().chain(class.getters.values());
+                    for v in methods.chain(class.static_methods.values()) {
                         if !self.marked_closures.contains(v) {
                             self.mark_closure(*v);
                         }
@@ -290,7 +354,7 @@ impl GC {
                         self.mark_class((*ptr).class);
                     }
 
-                    for v in (*ptr).fields.values() {
+                    for v in (*ptr).fields.iter().flatten() {
                         self.mark_value(*v);
                     }
                 }
@@ -308,7 +372,65 @@ impl GC {
                     }
                 }
             }
+
+            while let Some(ptr) = self.worklist_lists.pop() {
+                // Mark the items
+                unsafe {
+                    for v in &(*ptr).items {
+                        self.mark_value(*v);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Keeps every instance that is waiting for its finalizer alive, and queues each newly
+    /// unreachable instance whose class defines `finalize`, in allocation order. Must run after
+    /// `trace_references` and before anything is freed
+    pub fn queue_finalizers(&mut self) {
+        // Instances queued by an earlier collection are still waiting for their finalizer
+        for i in 0..self.finalize_queue.len() {
+            let ptr = self.finalize_queue[i];
+            if !self.marked_class_instances.contains(&ptr) {
+                self.mark_class_instance(ptr);
+            }
+        }
+        self.trace_references();
+
+        let queued = self.finalize_queue.len();
+        for &ptr in &self.class_instances {
+            if self.marked_class_instances.contains(&ptr) {
+                continue;
+            }
+
+            unsafe {
+                if (*ptr).finalized || !(*(*ptr).class).methods.contains_key("finalize") {
+                    continue;
+                }
+                (*ptr).finalized = true;
+            }
+            self.finalize_queue.push_back(ptr);
         }
+
+        // Keep the newly queued instances, and everything they reference, alive
+        for i in queued..self.finalize_queue.len() {
+            let ptr = self.finalize_queue[i];
+            if !self.marked_class_instances.contains(&ptr) {
+                self.mark_class_instance(ptr);
+            }
+        }
+        self.trace_references();
+    }
+
+    /// Removes the oldest instance waiting for its finalizer from the queue. The caller must
+    /// root it before allocating
+    pub fn next_finalizer(&mut self) -> Option<*mut ClassInstance> {
+        self.finalize_queue.pop_front()
+    }
+
+    /// Returns true if any instance is waiting for its finalizer
+    pub fn has_pending_finalizers(&self) -> bool {
+        !self.finalize_queue.is_empty()
     }
 
     /// Clears all marks
@@ -321,10 +443,47 @@ impl GC {
         self.marked_classes.clear();
         self.marked_class_instances.clear();
         self.marked_bound_methods.clear();
+        self.marked_lists.clear();
+        self.marked_weak_refs.clear();
+    }
+
+    /// Returns true if the value survives the current collection
+    fn is_marked(&self, v: Value) -> bool {
+        match v {
+            Value::String(ptr) => self.marked_strings.contains(&ptr),
+            Value::Function(ptr) => self.marked_functions.contains(&ptr),
+            Value::Closure(ptr) => self.marked_closures.contains(&ptr),
+            Value::NativeFunc(ptr) => self.marked_natives.contains(&ptr),
+            Value::Upvalue(ptr) => self.marked_upvalues.contains(&ptr),
+            Value::Class(ptr) => self.marked_classes.contains(&ptr),
+            Value::ClassInstance(ptr) => self.marked_class_instances.contains(&ptr),
+            Value::BoundMethod(ptr) => self.marked_bound_methods.contains(&ptr),
+            Value::List(ptr) => self.marked_lists.contains(&ptr),
+            Value::Weak(ptr) => self.marked_weak_refs.contains(&ptr),
+            Value::Nil | Value::Bool(_) | Value::Int(_) | Value::Number(_) => true,
+        }
+    }
+
+    /// Resets the target of every surviving weak reference whose target is about to be freed.
+    /// Must run before anything is freed, so no weak reference is left pointing at freed memory
+    fn clear_dead_weak_refs(&mut self) {
+        for &ptr in &self.weak_refs {
+            if !self.marked_weak_refs.contains(&ptr) {
+                continue;
+            }
+
+            unsafe {
+                if !self.is_marked((*ptr).target) {
+                    (*ptr).target = Value::Nil;
+                }
+            }
+        }
     }
 
     /// Frees all unmarked pointers
     pub fn sweep(&mut self) {
+        self.clear_dead_weak_refs();
+
         macro_rules! sweep_objects {
             ($(($field:ident, $marked_set:ident)),*) => {
                 $(
@@ -351,12 +510,14 @@ impl GC {
             (upvalues, marked_upvalues),
             (classes, marked_classes),
             (class_instances, marked_class_instances),
-            (bound_methods, marked_bound_methods)
+            (bound_methods, marked_bound_methods),
+            (lists, marked_lists),
+            (weak_refs, marked_weak_refs)
         );
 
         // Set the next GC threshold
-        self.next_gc = (self.live_objects as f64 * GC_THRESHOLD_GROWTH_FACTOR) as usize;
-        self.next_gc = self.next_gc.max(GC_DEFAULT_THRESHOLD);
+        self.next_gc = (self.live_objects as f64 * self.growth_factor) as usize;
+        self.next_gc = self.next_gc.max(self.threshold);
     }
 
     /// Returns true if the given string is marked
@@ -387,6 +548,8 @@ impl Drop for GC {
         }
 
         free_all!(
+            weak_refs,
+            lists,
             bound_methods,
             class_instances,
             classes,
@@ -1,10 +1,14 @@
 pub mod chunk;
 pub mod compiler;
+pub mod const_pool;
 pub mod disassembler;
 pub mod error;
 pub mod gc;
 pub mod native;
 pub mod scanner;
+pub mod stack;
+#[cfg(debug_assertions)]
+mod stack_check;
 pub mod sym_table;
 pub mod table;
 pub mod token;
@@ -12,61 +16,70 @@ pub mod value;
 pub mod vm;
 
 use std::fs;
-use std::io::Write;
+use std::io::{self, Write};
 
-pub fn interpret<T, U>(path: &str, mut output_stream: T, mut err_stream: U)
+/// How [`interpret_with_options`] reports errors
+#[derive(Debug, Clone, Copy)]
+pub struct Options {
+    /// Number of compile errors written in full before the rest are only counted
+    pub max_errors: usize,
+    /// Whether compile and runtime errors are written as one line of JSON each
+    pub json_errors: bool,
+    /// Whether the compiler also warns about likely mistakes that are valid code
+    pub strict: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            max_errors: compiler::DEFAULT_MAX_ERRORS,
+            json_errors: false,
+            strict: false,
+        }
+    }
+}
+
+pub fn interpret<T, U>(path: &str, output_stream: T, err_stream: U)
 where
     T: Write,
     U: Write,
+{
+    interpret_with_gc(path, gc::GC::new(), output_stream, err_stream)
+}
+
+/// Like [`interpret`], but allocates through the given garbage collector
+pub fn interpret_with_gc<T, U>(path: &str, gc: gc::GC, output_stream: T, err_stream: U)
+where
+    T: Write,
+    U: Write,
+{
+    interpret_with_options(path, gc, Options::default(), output_stream, err_stream)
+}
+
+/// Like [`interpret_with_gc`], but reports errors as the given options say
+pub fn interpret_with_options<T, U>(
+    path: &str,
+    gc: gc::GC,
+    options: Options,
+    mut output_stream: T,
+    mut err_stream: U,
+) where
+    T: Write,
+    U: Write,
 {
     match fs::read_to_string(path) {
         Ok(source) => {
-            let mut gc = gc::GC::new();
-            let mut str_intern_table = table::StringInternTable::new();
-            let mut globals: Vec<Option<value::Value>> = Vec::new();
-
-            let (global_var_names, compiled_function) = {
-                let mut sym_table = sym_table::SymbolTable::new();
-                let native_funcs = native::get_native_funcs();
-
-                // Define native functions as global variables
-                for native_func in &native_funcs {
-                    sym_table.declare(&native_func.name);
-                    globals.push(Some(gc.alloc_native(native_func.clone())));
-                }
-
-                let compiler = compiler::Compiler::new(
-                    &source,
-                    "<main>",
-                    &mut gc,
-                    &mut str_intern_table,
-                    &mut sym_table,
-                    &mut err_stream,
-                );
-                let compiled_function = compiler.compile();
-                let global_var_names = sym_table.into_names();
-
-                // We need to push `None` for each global variable that is not a native function
-                for _ in &global_var_names[native_funcs.len()..] {
-                    globals.push(None);
-                }
+            let compiled = compile_program(
+                &source,
+                path,
+                gc,
+                &mut output_stream,
+                &mut err_stream,
+                false,
+                options,
+            );
 
-                (global_var_names, compiled_function)
-            };
-
-            if let Some(function) = compiled_function {
-                let main_closure = gc.alloc_function_ptr(function);
-                let main_closure = gc.alloc_closure_ptr(value::Closure::new(main_closure, 0));
-
-                let mut vm = vm::VM::new(
-                    main_closure,
-                    gc,
-                    str_intern_table,
-                    global_var_names,
-                    globals,
-                    &mut output_stream,
-                    &mut err_stream,
-                );
+            if let Some(mut vm) = compiled {
                 let _res = vm.run();
             }
         }
@@ -75,3 +88,170 @@ where
         }
     }
 }
+
+/// Compiles the program at `path` and prints the disassembly of every function in it to standard
+/// output instead of running it
+pub fn disassemble<U>(path: &str, mut err_stream: U)
+where
+    U: Write,
+{
+    match fs::read_to_string(path) {
+        Ok(source) => {
+            let mut output_stream = io::sink();
+
+            // Imports are found relative to the path
+            let compiled = compile_named(
+                &source,
+                path,
+                gc::GC::new(),
+                &mut output_stream,
+                &mut err_stream,
+            );
+
+            if let Some(vm) = compiled {
+                if let Some(function) = vm.main_function() {
+                    disassembler::disassemble_function(function, vm.constant_pool());
+                }
+            }
+        }
+        Err(err) => {
+            let _ = writeln!(err_stream, "{err}");
+        }
+    }
+}
+
+/// Compiles the program at `path` without running it, logging each production the parser enters
+/// and leaves to `err_stream` along with any compile errors
+pub fn trace_parse<U>(path: &str, mut err_stream: U)
+where
+    U: Write,
+{
+    match fs::read_to_string(path) {
+        Ok(source) => {
+            let mut output_stream = io::sink();
+            let _vm = compile_program(
+                &source,
+                path,
+                gc::GC::new(),
+                &mut output_stream,
+                &mut err_stream,
+                true,
+                Options::default(),
+            );
+        }
+        Err(err) => {
+            let _ = writeln!(err_stream, "{err}");
+        }
+    }
+}
+
+/// Compiles `source` into a virtual machine that runs it once [`vm::VM::run`] is called, with
+/// native functions defined as globals. Returns `None` if there were compile errors, which are
+/// reported to `err_stream`
+pub fn compile<'a, T, U>(
+    source: &str,
+    gc: gc::GC,
+    output_stream: &'a mut T,
+    err_stream: &'a mut U,
+) -> Option<vm::VM<'a, T, U>>
+where
+    T: Write,
+    U: Write,
+{
+    compile_named(source, "", gc, output_stream, err_stream)
+}
+
+/// Like [`compile`], but names the source, e.g. by its file path. Stack traces show the name
+/// along with the line of each call
+pub fn compile_named<'a, T, U>(
+    source: &str,
+    source_name: &str,
+    gc: gc::GC,
+    output_stream: &'a mut T,
+    err_stream: &'a mut U,
+) -> Option<vm::VM<'a, T, U>>
+where
+    T: Write,
+    U: Write,
+{
+    compile_program(
+        source,
+        source_name,
+        gc,
+        output_stream,
+        err_stream,
+        false,
+        Options::default(),
+    )
+}
+
+fn compile_program<'a, T, U>(
+    source: &str,
+    source_name: &str,
+    mut gc: gc::GC,
+    output_stream: &'a mut T,
+    err_stream: &'a mut U,
+    parse_trace: bool,
+    options: Options,
+) -> Option<vm::VM<'a, T, U>>
+where
+    T: Write,
+    U: Write,
+{
+    let mut str_intern_table = table::StringInternTable::new();
+    let mut const_pool = const_pool::ConstantPool::new();
+    let mut globals: Vec<Option<value::Value>> = Vec::new();
+
+    let (global_var_names, compiled_function) = {
+        let mut sym_table = sym_table::SymbolTable::new();
+        let native_funcs = native::get_native_funcs();
+
+        // Define native functions as global variables
+        for native_func in &native_funcs {
+            sym_table.declare(&native_func.name);
+            globals.push(Some(gc.alloc_native(native_func.clone())));
+        }
+
+        let mut compiler = compiler::Compiler::new(
+            source,
+            source_name,
+            "<main>",
+            &mut gc,
+            &mut str_intern_table,
+            &mut sym_table,
+            &mut const_pool,
+            &mut *err_stream,
+        );
+        compiler.set_parse_trace(parse_trace);
+        compiler.set_max_errors(options.max_errors);
+        compiler.set_json_errors(options.json_errors);
+        compiler.set_strict(options.strict);
+        let compiled_function = compiler.compile_and_report();
+        let global_var_names = sym_table.into_names();
+
+        // We need to push `None` for each global variable that is not a native function
+        for _ in &global_var_names[native_funcs.len()..] {
+            globals.push(None);
+        }
+
+        (global_var_names, compiled_function)
+    };
+
+    let function = compiled_function?;
+    let main_closure = gc.alloc_function_ptr(function);
+    let main_closure = gc.alloc_closure_ptr(value::Closure::new(main_closure, 0));
+
+    let mut vm = vm::VM::new(
+        main_closure,
+        gc,
+        str_intern_table,
+        global_var_names,
+        globals,
+        const_pool.into_values(),
+        output_stream,
+        err_stream,
+    );
+    vm.set_json_errors(options.json_errors);
+
+    Some(vm)
+}
@@ -1,12 +1,111 @@
+use holo::gc::{GC, GC_DEFAULT_THRESHOLD, GC_THRESHOLD_GROWTH_FACTOR};
+use holo::Options;
 use std::env;
 use std::io;
+use std::process;
+
+const USAGE: &str =
+    "Usage: holo [--gc-threshold OBJECTS] [--gc-growth FACTOR] [--max-errors N] [--json-errors] [--strict] <file>
+       holo disasm <file>
+       holo parse-trace <file>";
+
+/// Parses the command line into the script path, the garbage collector to run it with and how
+/// errors are reported
+fn parse_args(args: &[String]) -> Result<(String, GC, Options), String> {
+    let mut path = None;
+    let mut threshold = None;
+    let mut growth_factor = None;
+    let mut options = Options::default();
+    let mut max_errors = None;
+    let mut iter = args.iter();
+
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--gc-threshold" => {
+                let value = iter.next().ok_or("'--gc-threshold' expects a value")?;
+
+                match value.parse::<usize>() {
+                    Ok(n) if n > 0 => threshold = Some(n),
+                    _ => {
+                        return Err(format!(
+                            "'--gc-threshold' must be a positive integer, but got '{value}'"
+                        ))
+                    }
+                }
+            }
+            "--gc-growth" => {
+                let value = iter.next().ok_or("'--gc-growth' expects a value")?;
+
+                match value.parse::<f64>() {
+                    Ok(factor) if factor.is_finite() && factor > 1.0 => {
+                        growth_factor = Some(factor)
+                    }
+                    _ => {
+                        return Err(format!(
+                            "'--gc-growth' must be a number greater than 1, but got '{value}'"
+                        ))
+                    }
+                }
+            }
+            "--max-errors" => {
+                let value = iter.next().ok_or("'--max-errors' expects a value")?;
+
+                match value.parse::<usize>() {
+                    Ok(n) if n > 0 => max_errors = Some(n),
+                    _ => {
+                        return Err(format!(
+                            "'--max-errors' must be a positive integer, but got '{value}'"
+                        ))
+                    }
+                }
+            }
+            "--json-errors" => options.json_errors = true,
+            "--strict" => options.strict = true,
+            flag if flag.starts_with("--") => return Err(format!("unknown flag '{flag}'")),
+            _ if path.is_some() => return Err("expected a single script path".to_string()),
+            _ => path = Some(arg.clone()),
+        }
+    }
+
+    let path = path.ok_or("missing script path")?;
+
+    // JSON output is for tools, which get every error
+    if let Some(n) = max_errors {
+        if options.json_errors {
+            return Err("'--max-errors' cannot be used with '--json-errors'".to_string());
+        }
+        options.max_errors = n;
+    }
+    let gc = GC::with_params(
+        threshold.unwrap_or(GC_DEFAULT_THRESHOLD),
+        growth_factor.unwrap_or(GC_THRESHOLD_GROWTH_FACTOR),
+    );
+
+    Ok((path, gc, options))
+}
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    // `holo disasm <file>` prints the bytecode of a program and `holo parse-trace <file>` how it
+    // is parsed, instead of running it
+    let (command, args) = match args.split_first() {
+        Some((command, rest)) if command == "disasm" || command == "parse-trace" => {
+            (Some(command.as_str()), rest)
+        }
+        _ => (None, &args[..]),
+    };
 
-    if args.len() == 2 {
-        holo::interpret(&args[1], io::stdout(), io::stderr());
-    } else {
-        eprintln!("Usage: holo <file>");
+    match parse_args(args) {
+        Ok((path, ..)) if command == Some("disasm") => holo::disassemble(&path, io::stderr()),
+        Ok((path, ..)) if command == Some("parse-trace") => holo::trace_parse(&path, io::stderr()),
+        Ok((path, gc, options)) => {
+            holo::interpret_with_options(&path, gc, options, io::stdout(), io::stderr())
+        }
+        Err(err) => {
+            eprintln!("error: {err}");
+            eprintln!("{USAGE}");
+            process::exit(2);
+        }
     }
 }
@@ -4,34 +4,117 @@
 //! functionality. Native functions are implemented in Rust and exposed to
 //! the programs through the [`NativeFunc`] wrapper.
 
-use crate::value::Value;
+use crate::gc::GC;
+use crate::table::StringInternTable;
+use crate::value::{ClassInstance, List, Value, WeakRef};
+use std::fmt::Write as _;
+use std::io::Write;
+use std::ops::RangeInclusive;
+
+/// The parts of the virtual machine a native function can access while it runs.
+pub struct NativeContext<'a> {
+    /// Allocates objects returned by the function, the caller is responsible for
+    /// rooting them.
+    pub gc: &'a mut GC,
+    /// Interns strings returned by the function.
+    pub str_intern_table: &'a mut StringInternTable,
+    /// The stream `print` output is written to.
+    pub out_stream: &'a mut dyn Write,
+    /// The stream runtime errors are written to.
+    pub err_stream: &'a mut dyn Write,
+    /// The line of the call.
+    pub line: usize,
+    /// The generator behind `random`, `random_int` and `seed`.
+    pub rng: &'a mut Rng,
+}
+
+/// The pseudo-random number generator a virtual machine owns.
+///
+/// It is xorshift64* (Vigna, 2014): the 64-bit state is scrambled with the shifts 12, 25 and 27
+/// and the output is the state times `0x2545F4914F6CDD1D`. A seed is spread over the state with
+/// one step of splitmix64, so that small or similar seeds still give unrelated sequences and the
+/// state is never zero. Every run starts from seed 0, so a program's random numbers are the same
+/// each time unless it calls `seed`.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    /// Creates a generator in the state `seed` leads to.
+    pub fn new(seed: u64) -> Self {
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+
+        // Zero is the one state xorshift never leaves
+        Self {
+            state: if z == 0 { 0x9E37_79B9_7F4A_7C15 } else { z },
+        }
+    }
+
+    /// Returns the next 64 random bits.
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Returns a float in `[0, 1)` made from the top 53 bits of the next output.
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Returns an integer in `low..=high` by scaling the next output to the size of the range.
+    pub fn next_in_range(&mut self, low: i64, high: i64) -> i64 {
+        let span = (high as i128 - low as i128 + 1) as u128;
+        let offset = (self.next_u64() as u128 * span) >> 64;
+
+        (low as i128 + offset as i128) as i64
+    }
+}
+
+impl Default for Rng {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct NativeFunc {
     pub name: String,
-    arity: u8,
-    func: fn(&[Value]) -> Result<Value, String>,
+    arity: RangeInclusive<u8>,
+    func: fn(&mut NativeContext, &[Value]) -> Result<Value, String>,
 }
 
 impl NativeFunc {
-    pub fn call(&self, args: &[Value]) -> Result<Value, String> {
-        if args.len() as u8 != self.arity {
+    /// Calls the native function with access to the given parts of the virtual machine.
+    pub fn call(&self, ctx: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+        if !self.arity.contains(&(args.len() as u8)) {
+            let expected = if self.arity.start() == self.arity.end() {
+                format!("{} argument(s)", self.arity.start())
+            } else {
+                format!("{} to {} arguments", self.arity.start(), self.arity.end())
+            };
+
             return Err(format!(
-                "Function '{}' expects {} argument(s), but got {}",
+                "Function '{}' expects {}, but got {}",
                 self.name,
-                self.arity,
+                expected,
                 args.len()
             ));
         }
 
-        (self.func)(args)
+        (self.func)(ctx, args)
     }
 }
 
 // Built-in native functions
 
 /// Returns the current Unix timestamp in seconds with millisecond precision.
-fn clock(_args: &[Value]) -> Result<Value, String> {
+fn clock(_ctx: &mut NativeContext, _args: &[Value]) -> Result<Value, String> {
     let now = std::time::SystemTime::now();
     let since_unix_epoch = now
         .duration_since(std::time::UNIX_EPOCH)
@@ -41,10 +124,725 @@ fn clock(_args: &[Value]) -> Result<Value, String> {
     Ok(Value::Number(secs))
 }
 
+/// Returns the current Unix timestamp in whole milliseconds.
+fn time_millis(_ctx: &mut NativeContext, _args: &[Value]) -> Result<Value, String> {
+    let since_unix_epoch = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_err(|e| format!("Error: {:?}", e))?;
+
+    Ok(Value::Int(since_unix_epoch.as_millis() as i64))
+}
+
+/// Blocks for the given number of seconds, which may be fractional.
+fn sleep(_ctx: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    let duration = args[0]
+        .as_f64()
+        .and_then(|secs| std::time::Duration::try_from_secs_f64(secs).ok())
+        .ok_or_else(|| {
+            format!(
+                "Argument to 'sleep' must be a non-negative number of seconds, but got {:?} ({})",
+                args[0],
+                args[0].type_name()
+            )
+        })?;
+
+    std::thread::sleep(duration);
+    Ok(Value::Nil)
+}
+
+/// The most items `range` produces, so that a tiny step fails rather than exhausting memory
+const MAX_RANGE_ITEMS: usize = 1 << 24;
+
+/// Returns a list of numbers from `start` (inclusive) to `end` (exclusive),
+/// counting by `step` which defaults to 1. A negative `step` counts down. The
+/// items are ints if every argument is an int. Each item is computed as
+/// `start + i * step` so that float steps don't accumulate rounding errors.
+fn range(ctx: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    let mut bounds = [0.0, 0.0, 1.0];
+
+    for (bound, arg) in bounds.iter_mut().zip(args) {
+        match arg.as_f64() {
+            Some(n) if n.is_finite() => *bound = n,
+            _ => return Err("Arguments to 'range' must be finite numbers".to_string()),
+        }
+    }
+
+    let [start, end, step] = bounds;
+
+    if step == 0.0 {
+        return Err("Step of 'range' cannot be zero".to_string());
+    }
+
+    // Counting with ints only yields ints, counted exactly as a float can't tell large ints apart
+    let ints = match args {
+        [Value::Int(start), Value::Int(end)] => Some((*start, *end, 1)),
+        [Value::Int(start), Value::Int(end), Value::Int(step)] => Some((*start, *end, *step)),
+        _ => None,
+    };
+
+    let items: Vec<Value> = if let Some((start, end, step)) = ints {
+        let (start, step) = (start as i128, step as i128);
+        let distance = end as i128 - start;
+        let count = (distance / step + (distance % step != 0) as i128).max(0);
+
+        check_range_count(count as f64)?;
+        (0..count)
+            .map(|i| Value::Int((start + i * step) as i64))
+            .collect()
+    } else {
+        let count = ((end - start) / step).ceil().max(0.0);
+
+        check_range_count(count)?;
+        (0..count as usize)
+            .map(|i| Value::Number(start + i as f64 * step))
+            .collect()
+    };
+
+    Ok(ctx.gc.alloc_list(List::new(items)))
+}
+
+/// Fails unless `range` may produce `count` items
+fn check_range_count(count: f64) -> Result<(), String> {
+    if count > MAX_RANGE_ITEMS as f64 {
+        Err(format!(
+            "'range' cannot produce more than {MAX_RANGE_ITEMS} items"
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// Fails with a message naming both values and their types unless `expected`
+/// and `actual` are equal. The expected value comes first and the message says
+/// "expected ... but got ..." rather than "left"/"right", as existing scripts
+/// rely on both. A native error already reports the stack trace, so there is
+/// no dedicated opcode.
+fn assert_eq(_ctx: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    let (expected, actual) = (args[0], args[1]);
+
+    if expected == actual {
+        Ok(Value::Nil)
+    } else {
+        Err(format!(
+            "Assertion failed: expected {:?} ({}) but got {:?} ({})",
+            expected,
+            expected.type_name(),
+            actual,
+            actual.type_name()
+        ))
+    }
+}
+
+/// Prints `value` to the error stream along with the line of the call and returns
+/// it unchanged, so it can wrap any expression while debugging.
+fn debug(ctx: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    let value = args[0];
+    let _ = writeln!(ctx.err_stream, "[line {}] {:?}", ctx.line, value);
+
+    Ok(value)
+}
+
+/// Converts an argument of `name` to an `i64`. Floats are accepted only if they hold
+/// an integer inside the `i64` range.
+fn int_arg(name: &str, arg: Value) -> Result<i64, String> {
+    // 2^63, the first float past `i64::MAX`
+    const LIMIT: f64 = 9_223_372_036_854_775_808.0;
+
+    match arg {
+        Value::Int(n) => Ok(n),
+        Value::Number(n) if n.fract() == 0.0 && (-LIMIT..LIMIT).contains(&n) => Ok(n as i64),
+        _ => Err(format!(
+            "Arguments to '{}' must be integers, but got {:?} ({})",
+            name,
+            arg,
+            arg.type_name()
+        )),
+    }
+}
+
+/// Applies a checked integer operation to both arguments of `name`, failing
+/// instead of rounding when the result does not fit in an `i64`.
+fn checked_int_op(
+    name: &str,
+    args: &[Value],
+    op: fn(i64, i64) -> Option<i64>,
+) -> Result<Value, String> {
+    let (left, right) = (int_arg(name, args[0])?, int_arg(name, args[1])?);
+
+    op(left, right)
+        .map(Value::Int)
+        .ok_or_else(|| format!("Integer overflow in '{name}({left}, {right})'"))
+}
+
+/// Adds two integers exactly, failing on overflow.
+fn iadd(_ctx: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    checked_int_op("iadd", args, i64::checked_add)
+}
+
+/// Subtracts two integers exactly, failing on overflow.
+fn isub(_ctx: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    checked_int_op("isub", args, i64::checked_sub)
+}
+
+/// Multiplies two integers exactly, failing on overflow.
+fn imul(_ctx: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    checked_int_op("imul", args, i64::checked_mul)
+}
+
+/// Fills the `{}` placeholders in the format string, the first argument of `name`,
+/// with the remaining arguments in order. `{{` and `}}` stand for literal braces.
+fn format_args(name: &str, args: &[Value]) -> Result<String, String> {
+    let Some(fmt) = args[0].as_string() else {
+        return Err(format!(
+            "First argument to '{}' must be a string, but got {:?} ({})",
+            name,
+            args[0],
+            args[0].type_name()
+        ));
+    };
+
+    let values = &args[1..];
+    let mut formatted = String::with_capacity(fmt.len());
+    let mut placeholders = 0;
+    let mut chars = fmt.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('{', Some('{')) | ('}', Some('}')) => {
+                chars.next();
+                formatted.push(c);
+            }
+            ('{', Some('}')) => {
+                chars.next();
+
+                if let Some(value) = values.get(placeholders) {
+                    let _ = write!(formatted, "{value}");
+                }
+
+                placeholders += 1;
+            }
+            ('{' | '}', _) => {
+                return Err(format!("Unmatched '{c}' in format string of '{name}'"));
+            }
+            _ => formatted.push(c),
+        }
+    }
+
+    if placeholders != values.len() {
+        return Err(format!(
+            "Format string of '{}' has {} placeholder(s), but got {} value(s)",
+            name,
+            placeholders,
+            values.len()
+        ));
+    }
+
+    Ok(formatted)
+}
+
+/// Returns the format string with its placeholders filled, see [`format_args`].
+fn format(ctx: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    let formatted = format_args("format", args)?;
+
+    Ok(Value::String(
+        ctx.str_intern_table.intern_owned(formatted, ctx.gc),
+    ))
+}
+
+/// Prints the format string with its placeholders filled, see [`format_args`].
+fn println(ctx: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    let formatted = format_args("println", args)?;
+    let _ = writeln!(ctx.out_stream, "{formatted}");
+
+    Ok(Value::Nil)
+}
+
+/// Returns the first argument of `name`, which must be a list.
+fn list_arg(name: &str, args: &[Value]) -> Result<*mut List, String> {
+    args[0].as_list_ptr().ok_or_else(|| {
+        format!(
+            "First argument to '{}' must be a list, but got {:?} ({})",
+            name,
+            args[0],
+            args[0].type_name()
+        )
+    })
+}
+
+/// Appends `value` to the list and returns its new length. The GC counts
+/// objects rather than bytes, so growing a list needs no extra accounting.
+fn push(_ctx: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    let list = list_arg("push", args)?;
+    let items = unsafe {
+        // SAFETY: GC guarantees that all pointers are valid
+        &mut (*list).items
+    };
+
+    items.push(args[1]);
+    Ok(Value::Int(items.len() as i64))
+}
+
+/// Removes and returns the last item of the list, failing if it is empty.
+fn pop(_ctx: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    let list = list_arg("pop", args)?;
+    let items = unsafe {
+        // SAFETY: GC guarantees that all pointers are valid
+        &mut (*list).items
+    };
+
+    items
+        .pop()
+        .ok_or_else(|| "Cannot pop from an empty list".to_string())
+}
+
+/// Returns the number of characters in the string or items in the list.
+fn len(_ctx: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    length(args[0])
+}
+
+/// Returns the number of characters, not bytes, in a string or the number of items in a list.
+/// Shared by the `len` native and the `LEN` instruction.
+pub fn length(value: Value) -> Result<Value, String> {
+    let len = match value {
+        Value::String(_) => value.as_string().unwrap().chars().count(),
+        Value::List(_) => value.as_list().unwrap().items.len(),
+        _ => {
+            return Err(format!(
+                "'len' counts the characters of a string or the items of a list, but got {:?} ({})",
+                value,
+                value.type_name()
+            ))
+        }
+    };
+
+    Ok(Value::Int(len as i64))
+}
+
+/// Returns the character at scalar index `args[1]` of the string `args[0]`, or `None` past
+/// either end. Strings are indexed by Unicode scalar value, the way `len` counts them, rather
+/// than by byte or by grapheme cluster, so `"e\u{301}"` has two characters.
+fn nth_char(name: &str, args: &[Value]) -> Result<Option<char>, String> {
+    let string = string_arg(name, "First", &args[0])?;
+    let index = int_arg(name, args[1])?;
+
+    Ok(usize::try_from(index)
+        .ok()
+        .and_then(|index| string.chars().nth(index)))
+}
+
+/// Returns the character at the index as a string of its own, or `nil` out of range.
+fn char_at(ctx: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    let Some(c) = nth_char("char_at", args)? else {
+        return Ok(Value::Nil);
+    };
+
+    let mut buffer = [0; 4];
+    Ok(Value::String(
+        ctx.str_intern_table
+            .intern_slice(c.encode_utf8(&mut buffer), ctx.gc),
+    ))
+}
+
+/// Returns the code point of the character at the index, or `nil` out of range.
+fn char_code(_ctx: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    Ok(nth_char("char_code", args)?.map_or(Value::Nil, |c| Value::Int(c as i64)))
+}
+
+/// Returns the argument of `name` in the given position, which must be a string.
+fn string_arg<'v>(name: &str, position: &str, arg: &'v Value) -> Result<&'v str, String> {
+    arg.as_string().ok_or_else(|| {
+        format!(
+            "{} argument to '{}' must be a string, but got {:?} ({})",
+            position,
+            name,
+            arg,
+            arg.type_name()
+        )
+    })
+}
+
+/// Splits the string at each occurrence of the separator into a list of strings. An empty
+/// separator splits the string into its characters. Splitting an empty string gives a list
+/// holding one empty string, whatever the separator, so that `join(split(s, sep), sep)` is
+/// always `s`.
+fn split(ctx: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    let string = string_arg("split", "First", &args[0])?;
+    let separator = string_arg("split", "Second", &args[1])?;
+
+    let parts: Vec<&str> = if string.is_empty() {
+        vec![""]
+    } else if separator.is_empty() {
+        string
+            .char_indices()
+            .map(|(start, c)| &string[start..start + c.len_utf8()])
+            .collect()
+    } else {
+        string.split(separator).collect()
+    };
+
+    // Nothing is collected while a native runs, so the parts need no rooting
+    let items = parts
+        .into_iter()
+        .map(|part| Value::String(ctx.str_intern_table.intern_slice(part, ctx.gc)))
+        .collect();
+
+    Ok(ctx.gc.alloc_list(List::new(items)))
+}
+
+/// Joins the items of the list into one string with the separator between them. Items that
+/// are not strings are written the way `format` writes them.
+fn join(ctx: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    let list = list_arg("join", args)?;
+    let separator = string_arg("join", "Second", &args[1])?;
+    let items = unsafe {
+        // SAFETY: GC guarantees that all pointers are valid
+        &(*list).items
+    };
+
+    let mut joined = String::new();
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            joined.push_str(separator);
+        }
+        let _ = write!(joined, "{item}");
+    }
+
+    Ok(Value::String(
+        ctx.str_intern_table.intern_owned(joined, ctx.gc),
+    ))
+}
+
+/// Parses the string as an integer in base 10, or in the base given as the second argument,
+/// from 2 to 36. A leading `+` or `-` is allowed, but no whitespace or prefix such as `0x`.
+fn parse_int(_ctx: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    let string = string_arg("parse_int", "First", &args[0])?;
+    let radix = match args.get(1) {
+        Some(&radix) => int_arg("parse_int", radix)?,
+        None => 10,
+    };
+
+    if !(2..=36).contains(&radix) {
+        return Err(format!(
+            "Base of 'parse_int' must be between 2 and 36, but got {radix}"
+        ));
+    }
+
+    i64::from_str_radix(string, radix as u32)
+        .map(Value::Int)
+        .map_err(|_| format!("Cannot parse {:?} as an integer in base {}", args[0], radix))
+}
+
+/// Parses the string as a float, which may also be written without a fractional part or in
+/// exponent notation, such as `1e-3`. Text that names or overflows to a non-finite value, such as
+/// `nan`, `inf` or `1e999`, is rejected.
+fn parse_float(_ctx: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    let string = string_arg("parse_float", "First", &args[0])?;
+
+    match string.parse::<f64>() {
+        Ok(value) if value.is_finite() => Ok(Value::Number(value)),
+        _ => Err(format!("Cannot parse {:?} as a float", args[0])),
+    }
+}
+
+/// Returns whether any item of the list equals `value`. Natives can't call back into the
+/// script, so `equals` methods are not consulted: an instance only matches itself.
+fn contains(_ctx: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    let list = list_arg("contains", args)?;
+    let found = unsafe {
+        // SAFETY: GC guarantees that all pointers are valid
+        (*list).items.contains(&args[1])
+    };
+
+    Ok(Value::Bool(found))
+}
+
+/// Returns a weak reference to an object, which does not keep the object alive.
+fn weak(ctx: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    if !args[0].is_object() {
+        return Err(format!(
+            "Argument to 'weak' must be an object, but got {:?} ({})",
+            args[0],
+            args[0].type_name()
+        ));
+    }
+
+    Ok(ctx.gc.alloc_weak_ref(WeakRef::new(args[0])))
+}
+
+/// Returns the object a weak reference points to, or `nil` if it has been collected.
+fn deref_weak(_ctx: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    let weak_ref = args[0].as_weak().ok_or_else(|| {
+        format!(
+            "Argument to 'deref_weak' must be a weak reference, but got {:?} ({})",
+            args[0],
+            args[0].type_name()
+        )
+    })?;
+
+    Ok(weak_ref.target)
+}
+
+/// Returns a random float in `[0, 1)`.
+fn random(ctx: &mut NativeContext, _args: &[Value]) -> Result<Value, String> {
+    Ok(Value::Number(ctx.rng.next_f64()))
+}
+
+/// Returns a random integer between `low` and `high`, both inclusive.
+fn random_int(ctx: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    let (low, high) = (
+        int_arg("random_int", args[0])?,
+        int_arg("random_int", args[1])?,
+    );
+
+    if low > high {
+        return Err(format!(
+            "Empty range in 'random_int({low}, {high})', the low end must not exceed the high end"
+        ));
+    }
+
+    Ok(Value::Int(ctx.rng.next_in_range(low, high)))
+}
+
+/// Restarts the random numbers from the given seed, so the same seed gives the same numbers.
+fn seed(ctx: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    let seed = int_arg("seed", args[0])?;
+    *ctx.rng = Rng::new(seed as u64);
+
+    Ok(Value::Nil)
+}
+
+/// Returns the first argument of `name`, which must be a class instance.
+fn instance_arg(name: &str, args: &[Value]) -> Result<*mut ClassInstance, String> {
+    args[0].as_class_instance_ptr().ok_or_else(|| {
+        format!(
+            "First argument to '{}' must be an instance, but got {:?} ({})",
+            name,
+            args[0],
+            args[0].type_name()
+        )
+    })
+}
+
+/// Returns the second argument of `name`, which must be a string naming a field.
+fn field_name_arg<'v>(name: &str, args: &'v [Value]) -> Result<&'v str, String> {
+    args[1].as_string().ok_or_else(|| {
+        format!(
+            "Second argument to '{}' must be a string, but got {:?} ({})",
+            name,
+            args[1],
+            args[1].type_name()
+        )
+    })
+}
+
+/// Returns a list of the names of the fields set on the instance. The names are in
+/// the order their fields were first set on any instance of the class, which is
+/// the order of the field slots.
+fn fields(ctx: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    let instance = unsafe {
+        // SAFETY: GC guarantees that all pointers are valid
+        &*instance_arg("fields", args)?
+    };
+    let field_slots = unsafe {
+        // SAFETY: GC guarantees that all pointers are valid
+        &(*instance.class).field_slots
+    };
+
+    let mut names: Vec<(usize, &str)> = field_slots
+        .iter()
+        .filter(|(_, slot)| instance.field(**slot).is_some())
+        .map(|(name, slot)| (*slot, name.as_str()))
+        .collect();
+    names.sort_unstable();
+
+    // Nothing is collected while a native runs, so the names need no rooting
+    let items = names
+        .into_iter()
+        .map(|(_, name)| Value::String(ctx.str_intern_table.intern_slice(name, ctx.gc)))
+        .collect();
+
+    Ok(ctx.gc.alloc_list(List::new(items)))
+}
+
+/// Returns the field of the instance with the given name, failing like a property
+/// access if it is not set.
+fn get_field(_ctx: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    let instance = unsafe {
+        // SAFETY: GC guarantees that all pointers are valid
+        &*instance_arg("get_field", args)?
+    };
+    let name = field_name_arg("get_field", args)?;
+
+    instance
+        .get_field(name)
+        .copied()
+        .ok_or_else(|| format!("Undefined property '{name}'"))
+}
+
+/// Sets the field of the instance with the given name and returns the value, like
+/// an assignment to a property.
+fn set_field(_ctx: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    let instance = unsafe {
+        // SAFETY: GC guarantees that all pointers are valid
+        &mut *instance_arg("set_field", args)?
+    };
+    let name = field_name_arg("set_field", args)?;
+
+    let slot = unsafe {
+        // SAFETY: GC guarantees that all pointers are valid
+        (*instance.class).field_slot(name)
+    };
+    instance.set_field(slot, args[2]);
+
+    Ok(args[2])
+}
+
 pub fn get_native_funcs() -> Vec<NativeFunc> {
-    vec![NativeFunc {
-        name: "clock".to_string(),
-        arity: 0,
-        func: clock,
-    }]
+    vec![
+        NativeFunc {
+            name: "clock".to_string(),
+            arity: 0..=0,
+            func: clock,
+        },
+        NativeFunc {
+            name: "time_millis".to_string(),
+            arity: 0..=0,
+            func: time_millis,
+        },
+        NativeFunc {
+            name: "sleep".to_string(),
+            arity: 1..=1,
+            func: sleep,
+        },
+        NativeFunc {
+            name: "range".to_string(),
+            arity: 2..=3,
+            func: range,
+        },
+        NativeFunc {
+            name: "assert_eq".to_string(),
+            arity: 2..=2,
+            func: assert_eq,
+        },
+        NativeFunc {
+            name: "debug".to_string(),
+            arity: 1..=1,
+            func: debug,
+        },
+        NativeFunc {
+            name: "iadd".to_string(),
+            arity: 2..=2,
+            func: iadd,
+        },
+        NativeFunc {
+            name: "isub".to_string(),
+            arity: 2..=2,
+            func: isub,
+        },
+        NativeFunc {
+            name: "imul".to_string(),
+            arity: 2..=2,
+            func: imul,
+        },
+        NativeFunc {
+            name: "format".to_string(),
+            arity: 1..=u8::MAX,
+            func: format,
+        },
+        NativeFunc {
+            name: "println".to_string(),
+            arity: 1..=u8::MAX,
+            func: println,
+        },
+        NativeFunc {
+            name: "push".to_string(),
+            arity: 2..=2,
+            func: push,
+        },
+        NativeFunc {
+            name: "pop".to_string(),
+            arity: 1..=1,
+            func: pop,
+        },
+        NativeFunc {
+            name: "len".to_string(),
+            arity: 1..=1,
+            func: len,
+        },
+        NativeFunc {
+            name: "char_at".to_string(),
+            arity: 2..=2,
+            func: char_at,
+        },
+        NativeFunc {
+            name: "char_code".to_string(),
+            arity: 2..=2,
+            func: char_code,
+        },
+        NativeFunc {
+            name: "split".to_string(),
+            arity: 2..=2,
+            func: split,
+        },
+        NativeFunc {
+            name: "join".to_string(),
+            arity: 2..=2,
+            func: join,
+        },
+        NativeFunc {
+            name: "parse_int".to_string(),
+            arity: 1..=2,
+            func: parse_int,
+        },
+        NativeFunc {
+            name: "parse_float".to_string(),
+            arity: 1..=1,
+            func: parse_float,
+        },
+        NativeFunc {
+            name: "contains".to_string(),
+            arity: 2..=2,
+            func: contains,
+        },
+        NativeFunc {
+            name: "weak".to_string(),
+            arity: 1..=1,
+            func: weak,
+        },
+        NativeFunc {
+            name: "deref_weak".to_string(),
+            arity: 1..=1,
+            func: deref_weak,
+        },
+        NativeFunc {
+            name: "random".to_string(),
+            arity: 0..=0,
+            func: random,
+        },
+        NativeFunc {
+            name: "random_int".to_string(),
+            arity: 2..=2,
+            func: random_int,
+        },
+        NativeFunc {
+            name: "seed".to_string(),
+            arity: 1..=1,
+            func: seed,
+        },
+        NativeFunc {
+            name: "fields".to_string(),
+            arity: 1..=1,
+            func: fields,
+        },
+        NativeFunc {
+            name: "get_field".to_string(),
+            arity: 2..=2,
+            func: get_field,
+        },
+        NativeFunc {
+            name: "set_field".to_string(),
+            arity: 3..=3,
+            func: set_field,
+        },
+    ]
 }
@@ -8,13 +8,14 @@ use crate::token::{Token, TokenKind};
 
 /// A lexical analyzer that converts Holo source code into tokens.
 ///
-/// The scanner uses a two-character lookahead buffer to efficiently handle
-/// multi-character operators and comments.
+/// The scanner reads the source byte by byte, looking up to two characters
+/// ahead to handle multi-character operators and comments. ASCII characters are
+/// read straight from their byte, only other characters are decoded from UTF-8.
+#[derive(Clone)]
 pub struct Scanner<'a> {
     source: &'a str,
-    iter: std::str::CharIndices<'a>,
-    lookahead: [Option<(usize, char)>; 2],
     start_offset: usize,
+    /// Byte offset of the next character to be consumed.
     curr_offset: usize,
     curr_line: usize,
     /// 1-based column of the next character to be consumed.
@@ -27,21 +28,14 @@ pub struct Scanner<'a> {
     /// number of unmatched `{` seen inside the embedded expression so that the `}`
     /// closing the interpolation can be told apart from braces nested within it.
     interp_stack: Vec<u32>,
+    /// Whether the [`Iterator`] implementation has yielded the `Eof` token.
+    reached_eof: bool,
 }
 
 impl<'a> Scanner<'a> {
     pub fn new(source: &'a str) -> Self {
-        let mut iter = source.char_indices();
-        let mut lookahead = [None; 2];
-
-        for slot in &mut lookahead {
-            *slot = iter.next();
-        }
-
         Scanner {
             source,
-            iter,
-            lookahead,
             start_offset: 0,
             curr_offset: 0,
             curr_line: 1,
@@ -49,6 +43,7 @@ impl<'a> Scanner<'a> {
             start_column: 1,
             last_error: None,
             interp_stack: Vec::new(),
+            reached_eof: false,
         }
     }
 
@@ -99,10 +94,28 @@ impl<'a> Scanner<'a> {
                 None => self.make_token(TokenKind::RightBrace),
             },
             ';' => self.make_token(TokenKind::Semicolon),
-            '?' => self.make_token(TokenKind::Question),
+            '?' => {
+                self.scan_compound_operator([('.', TokenKind::QuestionDot)], TokenKind::Question)
+            }
             ':' => self.make_token(TokenKind::Colon),
             ',' => self.make_token(TokenKind::Comma),
-            '.' => self.make_token(TokenKind::Dot),
+            '.' => {
+                if self.peek() == Some('.') && self.peek_next() == Some('.') {
+                    self.advance();
+                    self.advance();
+                    self.make_token(TokenKind::Ellipsis)
+                } else if self.peek().is_some_and(|c| c.is_ascii_digit()) {
+                    // A number without an integer part, e.g. `.5`
+                    self.consume_digits();
+                    self.make_token(TokenKind::Number)
+                } else {
+                    self.make_token(TokenKind::Dot)
+                }
+            }
+            '~' => self.make_token(TokenKind::Tilde),
+            '&' => self.make_token(TokenKind::Ampersand),
+            '|' => self.make_token(TokenKind::Pipe),
+            '^' => self.make_token(TokenKind::Caret),
 
             // Multi-character operators
             '-' => self.scan_compound_operator(
@@ -117,10 +130,17 @@ impl<'a> Scanner<'a> {
             '*' => self.scan_compound_operator([('=', TokenKind::StarEqual)], TokenKind::Star),
             '!' => self.scan_compound_operator([('=', TokenKind::BangEqual)], TokenKind::Bang),
             '=' => self.scan_compound_operator([('=', TokenKind::EqualEqual)], TokenKind::Equal),
-            '>' => {
-                self.scan_compound_operator([('=', TokenKind::GreaterEqual)], TokenKind::Greater)
-            }
-            '<' => self.scan_compound_operator([('=', TokenKind::LessEqual)], TokenKind::Less),
+            '>' => self.scan_compound_operator(
+                [
+                    ('=', TokenKind::GreaterEqual),
+                    ('>', TokenKind::GreaterGreater),
+                ],
+                TokenKind::Greater,
+            ),
+            '<' => self.scan_compound_operator(
+                [('=', TokenKind::LessEqual), ('<', TokenKind::LessLess)],
+                TokenKind::Less,
+            ),
 
             // Literals
             '"' => self.scan_string(),
@@ -132,31 +152,37 @@ impl<'a> Scanner<'a> {
     }
 
     fn advance(&mut self) -> Option<char> {
-        let head = self.lookahead[0];
+        let c = self.peek()?;
 
-        self.lookahead[0] = self.lookahead[1];
-        self.lookahead[1] = self.iter.next();
-
-        if let Some((idx, c)) = head {
-            self.curr_offset = idx + c.len_utf8();
-            if c == '\n' {
-                self.curr_line += 1;
-                self.curr_column = 1;
-            } else {
-                self.curr_column += 1;
-            }
-            Some(c)
+        self.curr_offset += c.len_utf8();
+        if c == '\n' {
+            self.curr_line += 1;
+            self.curr_column = 1;
         } else {
-            None
+            self.curr_column += 1;
         }
+        Some(c)
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.char_at(self.curr_offset)
     }
 
-    fn peek(&mut self) -> Option<char> {
-        self.lookahead[0].map(|(_, c)| c)
+    fn peek_next(&self) -> Option<char> {
+        let c = self.peek()?;
+        self.char_at(self.curr_offset + c.len_utf8())
     }
 
-    fn peek_next(&mut self) -> Option<char> {
-        self.lookahead[1].map(|(_, c)| c)
+    /// Returns the character starting at the byte `offset`, decoding UTF-8 only
+    /// for characters outside of ASCII
+    fn char_at(&self, offset: usize) -> Option<char> {
+        let byte = *self.source.as_bytes().get(offset)?;
+
+        if byte.is_ascii() {
+            Some(byte as char)
+        } else {
+            self.source[offset..].chars().next()
+        }
     }
 
     fn scan_compound_operator<const N: usize>(
@@ -188,6 +214,8 @@ impl<'a> Scanner<'a> {
     /// `StringInterpEnd` token) or is interrupted by a `{` (yielding a
     /// `StringInterp` or `StringInterpCont` token), in which case the embedded
     /// expression is scanned as ordinary tokens and resumed at the matching `}`.
+    /// A doubled `{{` and an empty `{}` are literal text and do not start an
+    /// interpolation.
     fn scan_string_body(&mut self, continuation: bool) -> Token<'a> {
         loop {
             match self.peek() {
@@ -200,6 +228,43 @@ impl<'a> Scanner<'a> {
                     };
                     return self.make_token(kind);
                 }
+                Some('{') if matches!(self.peek_next(), Some('{' | '}')) => {
+                    self.advance();
+                    self.advance();
+                }
+                Some('\\') => {
+                    // An escaped quote doesn't end the string, and neither an
+                    // escaped brace nor the braces of a `\u{XXXX}` escape start
+                    // an interpolation. The compiler resolves escapes and checks
+                    // the digits, so a malformed escape only has to stop at the
+                    // closing quote.
+                    self.advance();
+
+                    match self.peek() {
+                        Some('\\' | '"' | '{') => {
+                            self.advance();
+                        }
+                        Some('u') => {
+                            self.advance();
+
+                            if self.peek() == Some('{') {
+                                self.advance();
+
+                                while let Some(c) = self.peek() {
+                                    if c == '"' {
+                                        break;
+                                    }
+
+                                    self.advance();
+                                    if c == '}' {
+                                        break;
+                                    }
+                                }
+                            }
+                        }
+                        _ => {}
+                    }
+                }
                 Some('{') => {
                     self.advance(); // Consume the opening brace
                     self.interp_stack.push(0);
@@ -227,11 +292,13 @@ impl<'a> Scanner<'a> {
         if let Some('.') = self.peek() {
             self.advance();
 
-            // Optionally consume digits after '.'
+            // Optionally consume digits after '.', `5.` is the float 5
             self.consume_digits();
+
+            return self.make_token(TokenKind::Number);
         }
 
-        self.make_token(TokenKind::Number)
+        self.make_token(TokenKind::Integer)
     }
 
     fn scan_identifier(&mut self) -> Token<'a> {
@@ -243,7 +310,33 @@ impl<'a> Scanner<'a> {
             }
         }
 
-        self.make_token(self.resolve_identifier_kind())
+        let kind = match self.resolve_identifier_kind() {
+            TokenKind::Identifier if self.is_followed_by_loop_label() => TokenKind::Label,
+            kind => kind,
+        };
+
+        self.make_token(kind)
+    }
+
+    /// Returns true if the identifier just scanned is followed by `:` and a loop
+    /// keyword. This tells a loop label (`outer: while ...`) apart from an
+    /// identifier in the `then` branch of a ternary (`a ? b : c`).
+    fn is_followed_by_loop_label(&self) -> bool {
+        let rest = self.source[self.curr_offset..].trim_start();
+
+        let Some(rest) = rest.strip_prefix(':') else {
+            return false;
+        };
+        let rest = rest.trim_start();
+
+        ["while", "for", "loop"].iter().any(|keyword| {
+            rest.strip_prefix(keyword).is_some_and(|after| {
+                !after
+                    .chars()
+                    .next()
+                    .is_some_and(Self::is_identifier_continue)
+            })
+        })
     }
 
     fn resolve_identifier_kind(&self) -> TokenKind {
@@ -253,16 +346,20 @@ impl<'a> Scanner<'a> {
             "and" => TokenKind::And,
             "break" => TokenKind::Break,
             "class" => TokenKind::Class,
+            "const" => TokenKind::Const,
             "continue" => TokenKind::Continue,
             "else" => TokenKind::Else,
             "false" => TokenKind::False,
             "for" => TokenKind::For,
             "fun" => TokenKind::Fun,
             "if" => TokenKind::If,
+            "import" => TokenKind::Import,
+            "loop" => TokenKind::LoopKw,
             "nil" => TokenKind::Nil,
             "or" => TokenKind::Or,
             "print" => TokenKind::Print,
             "return" => TokenKind::Return,
+            "static" => TokenKind::Static,
             "super" => TokenKind::Super,
             "this" => TokenKind::This,
             "true" => TokenKind::True,
@@ -355,6 +452,7 @@ impl<'a> Scanner<'a> {
             lexeme: &self.source[self.start_offset..self.curr_offset],
             line: self.curr_line,
             column: self.start_column,
+            span: self.start_offset..self.curr_offset,
         }
     }
 
@@ -365,6 +463,7 @@ impl<'a> Scanner<'a> {
             lexeme: &self.source[self.start_offset..self.curr_offset],
             line: self.curr_line,
             column: self.start_column,
+            span: self.start_offset..self.curr_offset,
         }
     }
 
@@ -377,11 +476,94 @@ impl<'a> Scanner<'a> {
     }
 }
 
+/// Scans the whole source and returns its tokens, ending with the `Eof` token.
+///
+/// Lexical errors do not stop the scan: each one appears in the list as a
+/// [`TokenKind::Error`] token, so all of them can be reported at once.
+pub fn tokenize(source: &str) -> Vec<Token<'_>> {
+    Scanner::new(source).collect()
+}
+
+/// Yields every token of the source, ending with a single `Eof` token.
+impl<'a> Iterator for Scanner<'a> {
+    type Item = Token<'a>;
+
+    fn next(&mut self) -> Option<Token<'a>> {
+        if self.reached_eof {
+            return None;
+        }
+
+        let token = self.scan_token();
+        self.reached_eof = token.kind == TokenKind::Eof;
+        Some(token)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use std::path::PathBuf;
 
+    #[test]
+    fn iterator_ends_after_eof() {
+        let kinds: Vec<_> = Scanner::new("var x = 1;").map(|token| token.kind).collect();
+
+        assert_eq!(
+            kinds,
+            [
+                TokenKind::Var,
+                TokenKind::Identifier,
+                TokenKind::Equal,
+                TokenKind::Integer,
+                TokenKind::Semicolon,
+                TokenKind::Eof,
+            ]
+        );
+    }
+
+    #[test]
+    fn tokenize_keeps_going_after_errors() {
+        let kinds: Vec<_> = tokenize("1 @ 2 #").iter().map(|token| token.kind).collect();
+
+        assert_eq!(
+            kinds,
+            [
+                TokenKind::Integer,
+                TokenKind::Error,
+                TokenKind::Integer,
+                TokenKind::Error,
+                TokenKind::Eof,
+            ]
+        );
+    }
+
+    #[test]
+    fn multi_byte_characters() {
+        let source = "var café = \"π ≈ 3.14 🥧\";\n🥧";
+        let tokens = tokenize(source);
+
+        let summary: Vec<_> = tokens
+            .iter()
+            .map(|token| (token.kind, token.lexeme, token.line, token.column))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (TokenKind::Var, "var", 1, 1),
+                (TokenKind::Identifier, "café", 1, 5),
+                (TokenKind::Equal, "=", 1, 10),
+                (TokenKind::String, "\"π ≈ 3.14 🥧\"", 1, 12),
+                (TokenKind::Semicolon, ";", 1, 24),
+                (TokenKind::Error, "🥧", 2, 1),
+                (TokenKind::Eof, "", 2, 2),
+            ]
+        );
+
+        // Spans are byte offsets into the source
+        assert_eq!(&source[tokens[1].span.clone()], "café");
+        assert_eq!(tokens[4].span, 30..31);
+    }
+
     #[test]
     fn scanner_tests() {
         let base_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
@@ -406,23 +588,7 @@ mod tests {
             let expected = std::fs::read_to_string(expected_file).unwrap();
             let source = std::fs::read_to_string(path).unwrap();
 
-            let mut scanner = Scanner::new(&source);
-            let mut tokens = Vec::new();
-
-            loop {
-                match scanner.scan_token() {
-                    token @ Token {
-                        kind: TokenKind::Eof,
-                        lexeme: _,
-                        line: _,
-                        column: _,
-                    } => {
-                        tokens.push(token);
-                        break;
-                    }
-                    token => tokens.push(token),
-                }
-            }
+            let tokens = tokenize(&source);
 
             let normalized_expected = expected.trim().replace("\r\n", "\n");
             let normalized_output = format!("{tokens:#?}\n").trim().replace("\r\n", "\n");
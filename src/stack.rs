@@ -0,0 +1,117 @@
+use crate::value::Value;
+use std::ops::{Deref, DerefMut, Range};
+
+/// The value stack of the virtual machine.
+///
+/// The stack is backed by a single allocation made up front that never grows: pushing onto a
+/// full stack panics instead of reallocating. Callers check [`Stack::is_full`] first to report
+/// a stack overflow.
+#[derive(Debug)]
+pub struct Stack {
+    slots: Box<[Value]>,
+    len: usize,
+}
+
+impl Stack {
+    /// Creates an empty stack that can hold up to `capacity` values
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            slots: vec![Value::default(); capacity].into_boxed_slice(),
+            len: 0,
+        }
+    }
+
+    /// Returns true if no more values can be pushed
+    pub fn is_full(&self) -> bool {
+        self.len == self.slots.len()
+    }
+
+    /// Pushes a value onto the stack
+    ///
+    /// # Panics
+    /// Panics if the stack is full
+    pub fn push(&mut self, value: Value) {
+        assert!(!self.is_full(), "pushed onto a full stack");
+
+        self.slots[self.len] = value;
+        self.len += 1;
+    }
+
+    /// Removes the top value from the stack and returns it, or `None` if it is empty
+    pub fn pop(&mut self) -> Option<Value> {
+        if self.len == 0 {
+            return None;
+        }
+
+        self.len -= 1;
+        Some(self.slots[self.len])
+    }
+
+    /// Shortens the stack to `len` values, does nothing if it is not longer than that
+    pub fn truncate(&mut self, len: usize) {
+        self.len = self.len.min(len);
+    }
+
+    /// Removes the values in `range`, moving the values above it down to fill the gap
+    ///
+    /// # Panics
+    /// Panics if `range` is out of bounds
+    pub fn remove_range(&mut self, range: Range<usize>) {
+        assert!(range.start <= range.end && range.end <= self.len);
+
+        self.slots.copy_within(range.end..self.len, range.start);
+        self.len -= range.len();
+    }
+}
+
+impl Deref for Stack {
+    type Target = [Value];
+
+    fn deref(&self) -> &[Value] {
+        &self.slots[..self.len]
+    }
+}
+
+impl DerefMut for Stack {
+    fn deref_mut(&mut self) -> &mut [Value] {
+        &mut self.slots[..self.len]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slots_never_move() {
+        let mut stack = Stack::with_capacity(4);
+        stack.push(Value::Int(1));
+        let slot = &stack[0] as *const Value;
+
+        while !stack.is_full() {
+            stack.push(Value::Nil);
+        }
+
+        assert_eq!(&stack[0] as *const Value, slot);
+    }
+
+    #[test]
+    #[should_panic(expected = "pushed onto a full stack")]
+    fn push_onto_full_stack_panics() {
+        let mut stack = Stack::with_capacity(1);
+        stack.push(Value::Nil);
+        stack.push(Value::Nil);
+    }
+
+    #[test]
+    fn remove_range_moves_values_down() {
+        let mut stack = Stack::with_capacity(8);
+        for i in 0..5 {
+            stack.push(Value::Int(i));
+        }
+
+        stack.remove_range(1..3);
+
+        assert_eq!(&*stack, &[Value::Int(0), Value::Int(3), Value::Int(4)]);
+    }
+}
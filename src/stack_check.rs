@@ -0,0 +1,274 @@
+//! Debug-build check that compiled bytecode keeps the value stack balanced.
+//!
+//! The compiler never tracks how deep the stack is while it emits code, so a construct that
+//! leaves a temporary behind, or pops one too many, goes unnoticed until some later instruction
+//! reads the wrong slot. This module walks every path through a compiled function and computes
+//! the stack depth before each instruction. Every path that reaches an instruction must agree on
+//! its depth, which is exactly what `break` and `continue` rely on: a `break` lands at the loop's
+//! exit with the depth the loop was entered with, and a `continue` jumps back to the top of the
+//! loop with the depth of the loop's first iteration.
+
+use crate::chunk::{Chunk, OpCode};
+use crate::value::Function;
+
+/// Checks the stack balance of `function` and every function nested in it, returning a
+/// description of the first imbalance found
+pub fn check_function(function: &Function) -> Result<(), String> {
+    check_chunk(function)?;
+
+    for constant in &function.chunk.constants {
+        if let Some(nested) = constant.as_function() {
+            check_function(nested)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Where control goes after an instruction, and the stack depth it arrives with
+enum Flow {
+    /// Falls through to the next instruction
+    Next(isize),
+    /// Continues at the given offset only
+    Jump(usize, isize),
+    /// Either falls through with the first depth or jumps to the offset with the second
+    Branch(isize, usize, isize),
+    /// Leaves the function
+    Exit,
+}
+
+fn check_chunk(function: &Function) -> Result<(), String> {
+    let chunk = &function.chunk;
+    let mut depths: Vec<Option<isize>> = vec![None; chunk.code.len()];
+    let mut pending: Vec<(usize, isize)> = Vec::new();
+
+    // The callee and the arguments a call starts with. A call that leaves out defaulted
+    // parameters starts at a later entry point with fewer arguments
+    let required = 1 + function.required_arity() as isize;
+    if function.entry_points.is_empty() {
+        pending.push((0, required));
+    } else {
+        for (index, &entry) in function.entry_points.iter().enumerate() {
+            pending.push((entry, required + index as isize));
+        }
+    }
+
+    while let Some((mut offset, mut depth)) = pending.pop() {
+        while offset < chunk.code.len() {
+            match depths[offset] {
+                Some(seen) if seen == depth => break,
+                Some(seen) => {
+                    return Err(format!(
+                        "stack depth mismatch in '{}' at offset {}: {} on one path, {} on another",
+                        function.name, offset, seen, depth
+                    ))
+                }
+                None => depths[offset] = Some(depth),
+            }
+
+            let (flow, len) = step(chunk, offset, depth);
+
+            let after = match flow {
+                Flow::Next(after) => after,
+                Flow::Jump(target, after) => {
+                    offset = target;
+                    depth = after;
+                    check_depth(function, target, after)?;
+                    continue;
+                }
+                Flow::Branch(after, target, taken) => {
+                    check_depth(function, target, taken)?;
+                    pending.push((target, taken));
+                    after
+                }
+                Flow::Exit => break,
+            };
+
+            check_depth(function, offset, after)?;
+            offset += len;
+            depth = after;
+        }
+    }
+
+    Ok(())
+}
+
+fn check_depth(function: &Function, offset: usize, depth: isize) -> Result<(), String> {
+    if depth < 0 {
+        Err(format!(
+            "stack underflow in '{}' at offset {}",
+            function.name, offset
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// Returns where control goes after the instruction at `offset`, entered with `depth` values on
+/// the stack, and the length of the instruction
+fn step(chunk: &Chunk, offset: usize, depth: isize) -> (Flow, usize) {
+    let code = &chunk.code;
+    let byte = |at: usize| code[offset + at] as isize;
+    let int16 = |at: usize| Chunk::read_int16(&code[offset + at..offset + at + 2]);
+
+    let opcode = OpCode::try_from(code[offset]).expect("compiled code has only valid opcodes");
+
+    // Net change of the stack depth and instruction length of straight-line instructions
+    let (change, len) = match opcode {
+        OpCode::Return => return (Flow::Exit, 1),
+
+        OpCode::Nil | OpCode::True | OpCode::False => (1, 1),
+        OpCode::Constant
+        | OpCode::PoolConstant
+        | OpCode::GetGlobal
+        | OpCode::GetDefinedGlobal
+        | OpCode::GetLocal
+        | OpCode::GetUpvalue
+        | OpCode::Class => (1, 2),
+        OpCode::ConstantLong
+        | OpCode::PoolConstantLong
+        | OpCode::GetGlobalLong
+        | OpCode::GetDefinedGlobalLong
+        | OpCode::GetLocalLong
+        | OpCode::GetUpvalueLong => (1, 4),
+
+        OpCode::Negate | OpCode::Not | OpCode::BitNot => (0, 1),
+        OpCode::SetGlobal
+        | OpCode::SetDefinedGlobal
+        | OpCode::SetLocal
+        | OpCode::SetUpvalue
+        | OpCode::IncrementLocal => (0, 2),
+        OpCode::SetGlobalLong
+        | OpCode::SetDefinedGlobalLong
+        | OpCode::SetLocalLong
+        | OpCode::SetUpvalueLong => (0, 4),
+        OpCode::GetProperty | OpCode::GetPropertyOrNil => (0, 3),
+
+        OpCode::Add
+        | OpCode::Sub
+        | OpCode::Mult
+        | OpCode::Divide
+        | OpCode::Equal
+        | OpCode::NotEqual
+        | OpCode::Greater
+        | OpCode::GreaterEqual
+        | OpCode::Less
+        | OpCode::LessEqual
+        | OpCode::ShiftLeft
+        | OpCode::ShiftRight
+        | OpCode::BitAnd
+        | OpCode::BitOr
+        | OpCode::BitXor
+        | OpCode::Print
+        | OpCode::Pop
+        | OpCode::CloseUpvalue
+        | OpCode::Inherit
+        | OpCode::Len => (-1, 1),
+        OpCode::DefineGlobal
+        | OpCode::Method
+        | OpCode::Getter
+        | OpCode::StaticMethod
+        | OpCode::GetSuper => (-1, 2),
+        OpCode::DefineGlobalLong => (-1, 4),
+        OpCode::SetProperty => (-1, 3),
+
+        OpCode::PopN => (-byte(1), 2),
+        OpCode::PopNLong => (
+            -(Chunk::read_int24(&code[offset + 1..offset + 4]) as isize),
+            4,
+        ),
+        OpCode::PrintN => (-byte(1), 3),
+        // The callee and its arguments are replaced by the result
+        OpCode::Call | OpCode::TailCall => (-byte(1), 2),
+        OpCode::Invoke => (-byte(2), 3),
+        // The superclass above the arguments is consumed as well
+        OpCode::SuperInvoke => (-byte(2) - 1, 3),
+        OpCode::Concat | OpCode::BuildList => (1 - byte(1), 2),
+        OpCode::Unpack => (byte(1) - 1, 2),
+        // Leaves the callee, the parameters before the rest parameter and the list of the rest
+        OpCode::CollectRest => (1 + byte(1) + 1 - depth, 2),
+
+        OpCode::Closure => {
+            let index = code[offset + 1] as usize;
+            (1, 2 + 2 * upvalue_count(chunk, index))
+        }
+        OpCode::ClosureLong => {
+            let index = Chunk::read_int24(&code[offset + 1..offset + 4]);
+            (1, 4 + 2 * upvalue_count(chunk, index))
+        }
+
+        // The condition stays on the stack either way
+        OpCode::JumpIfFalse | OpCode::JumpIfTrue | OpCode::JumpIfNil => {
+            return (Flow::Branch(depth, offset + 3 + int16(1), depth), 3)
+        }
+        OpCode::Jump => return (Flow::Jump(offset + 3 + int16(1), depth), 3),
+        OpCode::Loop => return (Flow::Jump(offset + 3 - int16(1), depth), 3),
+        OpCode::ForNum => return (Flow::Branch(depth, offset + 5 + int16(3), depth), 5),
+        // Pushes the next item, or jumps out once there is none
+        OpCode::ForIn => return (Flow::Branch(depth + 1, offset + 5 + int16(3), depth), 5),
+    };
+
+    (Flow::Next(depth + change), len)
+}
+
+fn upvalue_count(chunk: &Chunk, index: usize) -> usize {
+    chunk.constants[index]
+        .as_function()
+        .expect("CLOSURE refers to a function")
+        .upvalue_count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn function(chunk: Chunk) -> Function {
+        Function {
+            name: String::from("test"),
+            chunk,
+            ..Function::default()
+        }
+    }
+
+    #[test]
+    fn balanced_branches_pass() {
+        let mut chunk = Chunk::new();
+
+        // if (true) print nil;
+        chunk.write_opcode(OpCode::True, 1);
+        chunk.write_opcode(OpCode::JumpIfFalse, 1);
+        chunk.write_int16(5, 1);
+        chunk.write_opcode(OpCode::Pop, 1);
+        chunk.write_opcode(OpCode::Nil, 1);
+        chunk.write_opcode(OpCode::Print, 1);
+        chunk.write_opcode(OpCode::Jump, 1);
+        chunk.write_int16(1, 1);
+        chunk.write_opcode(OpCode::Pop, 1);
+        chunk.write_opcode(OpCode::Nil, 1);
+        chunk.write_opcode(OpCode::Return, 1);
+
+        assert_eq!(check_function(&function(chunk)), Ok(()));
+    }
+
+    #[test]
+    fn break_leaving_a_temporary_is_caught() {
+        let mut chunk = Chunk::new();
+
+        // while (true) { nil; break; } with the `nil` never popped before the `break` jump
+        chunk.write_opcode(OpCode::True, 1);
+        chunk.write_opcode(OpCode::JumpIfFalse, 1);
+        chunk.write_int16(9, 1);
+        chunk.write_opcode(OpCode::Nil, 1);
+        chunk.write_opcode(OpCode::Jump, 1);
+        chunk.write_int16(5, 1);
+        chunk.write_opcode(OpCode::Pop, 1);
+        chunk.write_opcode(OpCode::Pop, 1);
+        chunk.write_opcode(OpCode::Loop, 1);
+        chunk.write_int16(13, 1);
+        chunk.write_opcode(OpCode::Pop, 1);
+        chunk.write_opcode(OpCode::Return, 1);
+
+        let err = check_function(&function(chunk)).unwrap_err();
+        assert!(err.contains("stack depth mismatch"), "{err}");
+    }
+}
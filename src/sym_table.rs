@@ -6,36 +6,48 @@ use std::collections::HashMap;
 /// and their indices, which are used for efficient variable access in
 /// the compiled bytecode.
 #[derive(Debug)]
-pub struct SymbolTable<'a> {
-    symbols: HashMap<&'a str, usize>,
+pub struct SymbolTable {
+    symbols: HashMap<String, usize>,
     /// Owned names in insertion order, index → name
     names: Vec<String>,
+    /// Whether each symbol has been declared, rather than only referenced so far
+    declared: Vec<bool>,
 }
 
-impl<'a> SymbolTable<'a> {
+impl SymbolTable {
     pub fn new() -> Self {
         Self {
             symbols: HashMap::new(),
             names: Vec::new(),
+            declared: Vec::new(),
         }
     }
 
     /// Declares a new symbol or returns the existing index if already declared.
-    pub fn declare(&mut self, name: &'a str) -> usize {
+    pub fn declare(&mut self, name: &str) -> usize {
+        let idx = self.resolve(name);
+        self.declared[idx] = true;
+        idx
+    }
+
+    /// Resolve a variable name to its index. This will add a slot for the variable
+    /// if it does not exist, without declaring it
+    pub fn resolve(&mut self, name: &str) -> usize {
         if let Some(&idx) = self.symbols.get(name) {
             idx
         } else {
             let idx = self.names.len();
             self.names.push(name.to_owned());
-            self.symbols.insert(name, idx);
+            self.declared.push(false);
+            self.symbols.insert(name.to_owned(), idx);
             idx
         }
     }
 
-    /// Resolve a variable name to its index. This will declare the variable if
-    /// it does not exist
-    pub fn resolve(&mut self, name: &'a str) -> usize {
-        self.declare(name)
+    /// Returns true if the symbol at the given index has been declared, as opposed
+    /// to only referenced.
+    pub fn is_declared(&self, idx: usize) -> bool {
+        self.declared[idx]
     }
 
     /// Returns the number of symbols in the table.
@@ -54,7 +66,7 @@ impl<'a> SymbolTable<'a> {
     }
 }
 
-impl Default for SymbolTable<'_> {
+impl Default for SymbolTable {
     fn default() -> Self {
         Self::new()
     }
@@ -1,3 +1,16 @@
+//! String interning for the compiler and the virtual machine.
+//!
+//! This is the only intern table in the crate. It hands out the same `*mut String` handles
+//! that [`Value::String`](crate::value::Value::String) holds, and the garbage collector
+//! prunes it of unmarked strings after each collection.
+//!
+//! The table itself is not a GC object. It is owned by whoever runs the program, first the
+//! compiler and then the virtual machine, and only borrows the strings it points to. The
+//! collector schedules collections by the number of live objects, and every entry is an interned
+//! string the collector already counts, so the table grows and shrinks with the count it is
+//! scheduled by. Pruning also gives back the buckets a burst of short-lived strings left behind,
+//! so the table never holds much more memory than its live entries need.
+
 use crate::gc::GC;
 use std::{collections::HashMap, fmt::Debug};
 use std::{
@@ -86,14 +99,25 @@ impl StringInternTable {
         }
     }
 
-    /// Clears all unmarked interned strings
+    /// Clears all unmarked interned strings, shrinking the table once it is mostly empty
     pub fn clear_unmarked(&mut self, gc: &mut GC) {
         self.0.retain(|_, &mut handle| gc.is_string_marked(handle));
+
+        if self.0.len() < self.0.capacity() / 4 {
+            self.0.shrink_to(self.0.len() * 2);
+        }
     }
 
-    pub fn contains(&self, value: &str) -> bool {
+    /// Returns the interned copy of `value` without allocating, or `None` if it has not
+    /// been interned
+    pub fn get(&self, value: &str) -> Option<*mut String> {
+        // Only uses the `value` for comparison purposes
         let key = unsafe { StrKey::new(NonNull::from(value)) };
-        self.0.contains_key(&key)
+        self.0.get(&key).copied()
+    }
+
+    pub fn contains(&self, value: &str) -> bool {
+        self.get(value).is_some()
     }
 }
 
@@ -169,4 +193,37 @@ mod tests {
         assert!(table.contains("keep"));
         assert!(!table.contains("remove"));
     }
+
+    #[test]
+    fn test_clear_unmarked_shrinks() {
+        let mut gc = GC::new();
+        let mut table = StringInternTable::new();
+
+        let kept = table.intern_slice("keep", &mut gc);
+        for i in 0..1000 {
+            table.intern_owned(format!("temporary {i}"), &mut gc);
+        }
+        let grown = table.0.capacity();
+
+        gc.mark_string(kept);
+        table.clear_unmarked(&mut gc);
+
+        assert_eq!(table.0.len(), 1);
+        assert!(table.0.capacity() < grown / 4);
+        assert_eq!(table.get("keep"), Some(kept));
+    }
+
+    #[test]
+    fn test_get() {
+        let mut gc = GC::new();
+        let mut table = StringInternTable::new();
+
+        let s1 = table.intern_slice("hello", &mut gc);
+
+        assert_eq!(table.get("hello"), Some(s1));
+        assert_eq!(table.get(&String::from("hello")), Some(s1));
+        assert_eq!(table.get("world"), None);
+        // Looking up does not intern
+        assert!(!table.contains("world"));
+    }
 }
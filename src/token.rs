@@ -1,3 +1,5 @@
+use std::ops::Range;
+
 /// Represents a token extracted from the source code.
 #[repr(u8)]
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -33,9 +35,20 @@ pub enum TokenKind {
     MinusEqual,
     StarEqual,
     SlashEqual,
+    Tilde,
+    LessLess,
+    GreaterGreater,
+    Ampersand,
+    Pipe,
+    Caret,
+    QuestionDot,
+    Ellipsis,
 
     // Literals
     Identifier,
+    /// An identifier naming a loop, e.g. the `outer` in `outer: while (...)`.
+    /// Only produced when the identifier is followed by `:` and a loop keyword.
+    Label,
     String,
     /// The leading chunk of an interpolated string: the text from the opening
     /// `"` up to and including the first `{`.
@@ -47,6 +60,8 @@ pub enum TokenKind {
     /// including the closing `"`.
     StringInterpEnd,
     Number,
+    /// A number literal without a fractional part, e.g. `42`.
+    Integer,
 
     // Keywords,
     And,
@@ -67,6 +82,11 @@ pub enum TokenKind {
     While,
     Break,
     Continue,
+    /// The `loop` keyword, named so it does not read as the `Loop` opcode.
+    LoopKw,
+    Import,
+    Const,
+    Static,
 
     Error,
     Eof,
@@ -80,6 +100,8 @@ pub struct Token<'a> {
     pub line: usize,
     /// 1-based column of the first character of the lexeme.
     pub column: usize,
+    /// Byte range of the lexeme in the source.
+    pub span: Range<usize>,
 }
 
 impl TokenKind {
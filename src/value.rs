@@ -7,10 +7,36 @@ use std::fmt::Debug;
 #[derive(Debug, Default)]
 pub struct Function {
     pub name: String,
+    /// The file or other source the function was compiled from, shown in stack traces. Empty if
+    /// the source is unnamed.
+    pub source_name: String,
     pub arity: u8,
     pub upvalue_count: usize,
     /// The compiled bytecode and constants for this function.
     pub chunk: Chunk,
+    /// The constants of `chunk` that live on the heap, the only ones the GC has to mark.
+    pub object_constants: Vec<Value>,
+    /// Where a call with `required_arity() + i` arguments starts running. The code that computes
+    /// the missing default arguments comes first and falls through to the body. Empty if no
+    /// parameter has a default, in which case calls start at the beginning of the chunk.
+    pub entry_points: Vec<usize>,
+    /// Whether the last parameter is a `...rest` parameter. It is not counted in `arity`.
+    pub variadic: bool,
+}
+
+impl Function {
+    /// Returns the number of parameters without a default value
+    pub fn required_arity(&self) -> u8 {
+        self.arity - self.entry_points.len().saturating_sub(1) as u8
+    }
+
+    /// Returns the offset a call with `arg_count` arguments starts running at. `arg_count` must
+    /// be at least `required_arity()`, and at most `arity` unless the function is variadic
+    pub fn entry_point(&self, arg_count: u8) -> usize {
+        let index = (arg_count.min(self.arity) - self.required_arity()) as usize;
+
+        self.entry_points.get(index).copied().unwrap_or(0)
+    }
 }
 
 /// Represents an upvalue - a variable captured by a closure from an enclosing scope.
@@ -18,22 +44,23 @@ pub struct Function {
 /// Upvalues allow closures to access variables from their defining environment
 /// even after that environment's stack frame has been popped. An upvalue can be
 /// in two states:
-/// - Open: Points to a location on the stack
+/// - Open: Refers to a slot on the stack by its index
 /// - Closed: Contains the value directly in the `closed` field
 #[derive(Debug)]
 pub struct Upvalue {
-    pub location: *mut Value,
+    /// The index of the captured stack slot. `None` once the upvalue is closed.
+    pub stack_index: Option<usize>,
     /// The closed-over value. Used when the upvalue is closed.
     pub closed: Value,
 }
 
 impl Upvalue {
-    /// Creates a new upvalue pointing to the given location.
-    ///
-    /// # Safety
-    /// The caller must ensure `location` remains valid for the lifetime of this upvalue.
-    pub fn new(location: *mut Value, closed: Value) -> Self {
-        Self { location, closed }
+    /// Creates a new open upvalue for the given stack slot.
+    pub fn new(stack_index: usize) -> Self {
+        Self {
+            stack_index: Some(stack_index),
+            closed: Value::default(),
+        }
     }
 }
 
@@ -45,6 +72,9 @@ pub struct Closure {
 }
 
 impl Closure {
+    /// Creates a closure with room for all of the function's upvalues, so capturing them never
+    /// reallocates the list. The collector counts objects rather than bytes, so a closure weighs
+    /// the same however many upvalues it captures
     pub fn new(function: *mut Function, upvalue_count: usize) -> Self {
         Self {
             function,
@@ -78,6 +108,14 @@ pub struct Class {
     pub name: String,
     // FIXME: Might want to make it a hashmap over `NonNull<str>`
     pub methods: HashMap<String, *mut Closure>,
+    /// Methods without parameters that run when the property of their name is read. A name is
+    /// either a method or a getter, defining one removes the other
+    pub getters: HashMap<String, *mut Closure>,
+    /// Methods called on the class itself rather than on its instances, without a `this`
+    pub static_methods: HashMap<String, *mut Closure>,
+    /// The slot of each field in the instances of this class. A field gets the next slot the
+    /// first time it is set on any instance, so every instance keeps it in the same place
+    pub field_slots: HashMap<String, usize>,
 }
 
 impl Class {
@@ -85,7 +123,21 @@ impl Class {
         Self {
             name,
             methods: HashMap::new(),
+            getters: HashMap::new(),
+            static_methods: HashMap::new(),
+            field_slots: HashMap::new(),
+        }
+    }
+
+    /// Returns the slot of the field `name`, assigning it the next free slot if it has none
+    pub fn field_slot(&mut self, name: &str) -> usize {
+        if let Some(slot) = self.field_slots.get(name) {
+            return *slot;
         }
+
+        let slot = self.field_slots.len();
+        self.field_slots.insert(name.to_string(), slot);
+        slot
     }
 }
 
@@ -93,20 +145,42 @@ impl Class {
 #[derive(Debug)]
 pub struct ClassInstance {
     pub class: *mut Class,
-    // FIXME: Might want to make it a hashmap over `NonNull<str>`
-    pub fields: HashMap<String, Value>,
+    /// The fields by their slot in [`Class::field_slots`], `None` for the slots of fields only
+    /// other instances of the class have set. Walking the slots in order visits the fields in
+    /// the order the class first saw them, which does not depend on hashing
+    pub fields: Vec<Option<Value>>,
+    pub finalized: bool, // Set once queued for finalization, so `finalize` runs at most once
 }
 
 impl ClassInstance {
     pub fn new(class: *mut Class) -> Self {
         Self {
             class,
-            fields: HashMap::new(),
+            fields: Vec::new(),
+            finalized: false,
         }
     }
 
     pub fn get_field(&self, name: &str) -> Option<&Value> {
-        self.fields.get(name)
+        let slot = unsafe {
+            // SAFETY: GC guarantees that all pointers are valid
+            (*self.class).field_slots.get(name)?
+        };
+
+        self.field(*slot)
+    }
+
+    /// Returns the field in `slot`, if this instance has set it
+    pub fn field(&self, slot: usize) -> Option<&Value> {
+        self.fields.get(slot)?.as_ref()
+    }
+
+    pub fn set_field(&mut self, slot: usize, value: Value) {
+        if slot >= self.fields.len() {
+            self.fields.resize(slot + 1, None);
+        }
+
+        self.fields[slot] = Some(value);
     }
 }
 
@@ -127,12 +201,40 @@ impl BoundMethod {
     }
 }
 
+/// Represents a growable list of values.
+#[derive(Debug, Default)]
+pub struct List {
+    pub items: Vec<Value>,
+}
+
+impl List {
+    pub fn new(items: Vec<Value>) -> Self {
+        Self { items }
+    }
+}
+
+/// Represents a weak reference to an object.
+///
+/// The garbage collector does not trace through `target`. When the target is collected, the
+/// collector replaces it with `nil` before freeing it, so `target` never dangles.
+#[derive(Debug)]
+pub struct WeakRef {
+    pub target: Value,
+}
+
+impl WeakRef {
+    pub fn new(target: Value) -> Self {
+        Self { target }
+    }
+}
+
 /// Represents any value in the language.
-#[derive(Default, Clone, Copy, PartialEq, PartialOrd)]
+#[derive(Default, Clone, Copy)]
 pub enum Value {
     #[default]
     Nil,
     Bool(bool),
+    Int(i64),
     Number(f64),
     String(*mut String),
     Function(*mut Function),
@@ -142,9 +244,43 @@ pub enum Value {
     Class(*mut Class),
     ClassInstance(*mut ClassInstance),
     BoundMethod(*mut BoundMethod),
+    List(*mut List),
+    Weak(*mut WeakRef),
 }
 
 impl Value {
+    /// Returns the name of this value's type as seen by programs
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Self::Nil => "nil",
+            Self::Bool(_) => "bool",
+            Self::Int(_) => "int",
+            Self::Number(_) => "number",
+            Self::String(_) => "string",
+            Self::Function(_) | Self::Closure(_) | Self::BoundMethod(_) => "function",
+            Self::NativeFunc(_) => "native function",
+            Self::Upvalue(_) => "upvalue",
+            Self::Class(_) => "class",
+            Self::ClassInstance(_) => "instance",
+            Self::List(_) => "list",
+            Self::Weak(_) => "weak",
+        }
+    }
+
+    /// Returns the value of an int or a float as a float
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Self::Int(value) => Some(*value as f64),
+            Self::Number(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// Returns false for `nil` and `false`, true for every other value
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Self::Nil | Self::Bool(false))
+    }
+
     // SAFETY: GC guarantees that all pointers are valid
     pub fn as_string(&self) -> Option<&str> {
         match self {
@@ -229,6 +365,73 @@ impl Value {
             _ => None,
         }
     }
+
+    pub fn as_list(&self) -> Option<&List> {
+        match self {
+            Self::List(ptr) => unsafe { Some(&**ptr) },
+            _ => None,
+        }
+    }
+
+    pub fn as_list_ptr(&self) -> Option<*mut List> {
+        match self {
+            Self::List(ptr) => Some(*ptr),
+            _ => None,
+        }
+    }
+
+    pub fn as_weak(&self) -> Option<&WeakRef> {
+        match self {
+            Self::Weak(ptr) => unsafe { Some(&**ptr) },
+            _ => None,
+        }
+    }
+
+    /// Returns true if the value lives on the heap and is managed by the GC
+    pub fn is_object(&self) -> bool {
+        !matches!(
+            self,
+            Self::Nil | Self::Bool(_) | Self::Int(_) | Self::Number(_)
+        )
+    }
+}
+
+/// The semantics of `==` and `!=`: nil, bools and numbers compare by value, with ints and floats
+/// compared numerically and `NaN` unequal to everything, strings by contents, and every other
+/// object by identity. Values of different types are unequal rather than an error. `Value` has no
+/// ordering on purpose: `<` and friends only accept numbers, see `VM::compare_numbers`
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Nil, Self::Nil) => true,
+            (Self::Bool(a), Self::Bool(b)) => a == b,
+            (Self::Int(a), Self::Int(b)) => a == b,
+            (Self::Number(a), Self::Number(b)) => a == b,
+            // Ints and floats compare by their exact numeric value. Converting the int to a
+            // float would round it above 2^53, so the float is converted instead, which only
+            // works for a whole float in the range of an int
+            (Self::Int(a), Self::Number(b)) | (Self::Number(b), Self::Int(a)) => {
+                b.fract() == 0.0 && *b >= i64::MIN as f64 && *b < i64::MAX as f64 && *b as i64 == *a
+            }
+            // Interning usually makes equal strings share a pointer, but compare the contents so
+            // equality never depends on it
+            (Self::String(a), Self::String(b)) => unsafe {
+                // SAFETY: GC guarantees that all pointers are valid
+                a == b || **a == **b
+            },
+            // Everything else is compared by identity
+            (Self::Function(a), Self::Function(b)) => a == b,
+            (Self::Closure(a), Self::Closure(b)) => a == b,
+            (Self::NativeFunc(a), Self::NativeFunc(b)) => a == b,
+            (Self::Upvalue(a), Self::Upvalue(b)) => a == b,
+            (Self::Class(a), Self::Class(b)) => a == b,
+            (Self::ClassInstance(a), Self::ClassInstance(b)) => a == b,
+            (Self::BoundMethod(a), Self::BoundMethod(b)) => a == b,
+            (Self::List(a), Self::List(b)) => a == b,
+            (Self::Weak(a), Self::Weak(b)) => a == b,
+            _ => false,
+        }
+    }
 }
 
 impl Debug for Value {
@@ -238,6 +441,7 @@ impl Debug for Value {
             match self {
                 Self::Nil => f.write_str("nil"),
                 Self::Bool(value) => f.write_str(&format!("{}", value)),
+                Self::Int(value) => f.write_str(&format!("{}", value)),
                 Self::Number(value) => f.write_str(&format!("{}", value)),
                 Self::String(ptr) => {
                     write!(f, "\"{}\"", (**ptr))
@@ -252,7 +456,7 @@ impl Debug for Value {
                     write!(f, "<native fn {}>", (**ptr).name)
                 }
                 Self::Upvalue(ptr) => {
-                    write!(f, "<upvalue {:p}>", (**ptr).location)
+                    write!(f, "<upvalue {:p}>", *ptr)
                 }
                 Self::Class(ptr) => {
                     write!(f, "<class {}>", (**ptr).name)
@@ -261,9 +465,53 @@ impl Debug for Value {
                     write!(f, "<instance of {}>", (*(**ptr).class).name)
                 }
                 Self::BoundMethod(ptr) => {
-                    write!(f, "<fn {}>", (*(**ptr).method).name())
+                    // Also name the class of the receiver, which `Display` leaves out
+                    let class = &*(*(**ptr).receiver).class;
+                    write!(
+                        f,
+                        "<bound method {}.{}>",
+                        class.name,
+                        (*(**ptr).method).name()
+                    )
+                }
+                Self::List(_) | Self::Weak(_) => write_nested(f, self, &mut Vec::new()),
+            }
+        }
+    }
+}
+
+/// Writes a list or weak reference as `Debug` does, with `[...]` in place of a list that contains
+/// itself. `printing` holds the lists being written further out
+fn write_nested(
+    f: &mut std::fmt::Formatter<'_>,
+    value: &Value,
+    printing: &mut Vec<*mut List>,
+) -> std::fmt::Result {
+    unsafe {
+        // SAFETY: GC guarantees that all pointers are valid
+        match value {
+            Value::List(ptr) => {
+                if printing.contains(ptr) {
+                    return f.write_str("[...]");
+                }
+
+                printing.push(*ptr);
+                f.write_str("[")?;
+                for (i, item) in (**ptr).items.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write_nested(f, item, printing)?;
                 }
+                printing.pop();
+                f.write_str("]")
+            }
+            Value::Weak(ptr) => {
+                f.write_str("<weak ")?;
+                write_nested(f, &(**ptr).target, printing)?;
+                f.write_str(">")
             }
+            _ => write!(f, "{:?}", value),
         }
     }
 }
@@ -275,6 +523,8 @@ impl std::fmt::Display for Value {
             match self {
                 Self::Nil => f.write_str("nil"),
                 Self::Bool(value) => f.write_str(&format!("{}", value)),
+                Self::Int(value) => f.write_str(&format!("{}", value)),
+                // Prints whole floats without a `.0` and never switches to an exponent
                 Self::Number(value) => f.write_str(&format!("{}", value)),
                 Self::String(ptr) => {
                     write!(f, "{}", &**ptr)
@@ -289,7 +539,7 @@ impl std::fmt::Display for Value {
                     write!(f, "<native fn {}>", (**ptr).name)
                 }
                 Self::Upvalue(ptr) => {
-                    write!(f, "<upvalue {:p}>", (**ptr).location)
+                    write!(f, "<upvalue {:p}>", *ptr)
                 }
                 Self::Class(ptr) => {
                     write!(f, "<class {}>", (**ptr).name)
@@ -300,7 +550,89 @@ impl std::fmt::Display for Value {
                 Self::BoundMethod(ptr) => {
                     write!(f, "<fn {}>", (*(**ptr).method).name())
                 }
+                Self::List(_) | Self::Weak(_) => write_nested(f, self, &mut Vec::new()),
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strings_compare_by_contents() {
+        let mut a = String::from("holo");
+        let mut b = String::from("holo");
+        let mut c = String::from("lox");
+
+        // Separate allocations, as if interning had missed
+        let a = Value::String(&mut a);
+        let b = Value::String(&mut b);
+        let c = Value::String(&mut c);
+
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+        assert_eq!(a, a);
+    }
+
+    #[test]
+    fn numbers_compare_by_value() {
+        assert_eq!(Value::Int(1), Value::Number(1.0));
+        assert_eq!(Value::Number(0.0), Value::Number(-0.0));
+        assert_ne!(Value::Int(1), Value::Number(1.5));
+        assert_ne!(Value::Number(f64::NAN), Value::Number(f64::NAN));
+    }
+
+    #[test]
+    fn objects_compare_by_identity() {
+        let mut a = Class::new(String::from("Foo"));
+        let mut b = Class::new(String::from("Foo"));
+
+        assert_eq!(Value::Class(&mut a), Value::Class(&mut a));
+        assert_ne!(Value::Class(&mut a), Value::Class(&mut b));
+    }
+
+    #[test]
+    fn floats_display_shortest_round_trip() {
+        let cases = [
+            (0.1 + 0.2, "0.30000000000000004"),
+            (1.0 / 3.0, "0.3333333333333333"),
+            (2.5, "2.5"),
+            (5.0, "5"),
+            (-0.0, "-0"),
+            (1e21, "1000000000000000000000"),
+            (1e-7, "0.0000001"),
+        ];
+
+        for (value, shown) in cases {
+            let displayed = Value::Number(value).to_string();
+
+            assert_eq!(displayed, shown);
+            assert_eq!(displayed.parse::<f64>().unwrap().to_bits(), value.to_bits());
+        }
+
+        // Written out in full rather than in scientific notation
+        let huge = Value::Number(1e300).to_string();
+        assert_eq!(huge.len(), 301);
+        assert!(huge.starts_with('1') && huge[1..].bytes().all(|b| b == b'0'));
+    }
+
+    #[test]
+    fn field_slots_follow_first_assignment() {
+        let mut class = Class::new(String::from("Foo"));
+        let names = ["z", "a", "m", "b", "y"];
+
+        for name in names {
+            class.field_slot(name);
+        }
+        // Setting a field again keeps its slot
+        class.field_slot("a");
+
+        let mut by_slot: Vec<_> = class.field_slots.iter().collect();
+        by_slot.sort_by_key(|(_, slot)| **slot);
+        let by_slot: Vec<_> = by_slot.into_iter().map(|(name, _)| name.as_str()).collect();
+
+        assert_eq!(by_slot, names);
+    }
+}
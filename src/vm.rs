@@ -1,19 +1,55 @@
-use crate::value::{BoundMethod, Class, ClassInstance};
+use crate::value::{BoundMethod, Class, ClassInstance, List};
 
 use super::{
-    chunk::{Chunk, OpCode},
-    error::RuntimeError,
+    chunk::{Chunk, OpCode, PropertyCache},
+    error::{write_json_string, RuntimeError},
     gc,
+    native::{self, NativeContext, Rng},
+    stack::Stack,
     table::StringInternTable,
-    value::{Closure, Upvalue, Value},
+    value::{Closure, Function, Upvalue, Value},
 };
+use std::cmp::Ordering;
+use std::fmt::Write as _;
 use std::io::Write;
 
 #[derive(Clone, Copy)]
 struct CallFrame {
     closure: *mut Closure, // Current closure being executed
+    chunk: *mut Chunk,     // Chunk of the closure's function, read on every instruction
     ip: usize,             // Instruction pointer
     stack_start: usize,    // Index of the first element of the stack for this frame
+    on_return: OnReturn,   // What the caller does with the value this frame returns
+}
+
+/// What the calling frame does with the value a call returns, once it is back on the stack
+#[derive(Clone, Copy, Default)]
+enum OnReturn {
+    /// Leave it on the stack as the result of the call
+    #[default]
+    Keep,
+    /// Call it with the given number of arguments, which lie below it on the stack. Invoking a
+    /// getter calls the value it produces this way
+    Call(u8),
+    /// Check that it is a bool, as `equals` must return, and negate it for `!=`
+    Equals { negate: bool },
+}
+
+impl CallFrame {
+    fn new(closure: *mut Closure, ip: usize, stack_start: usize) -> Self {
+        let chunk = unsafe {
+            // SAFETY: GC guarantees that all pointers are valid
+            &mut (*(*closure).function).chunk as *mut Chunk
+        };
+
+        CallFrame {
+            closure,
+            chunk,
+            ip,
+            stack_start,
+            on_return: OnReturn::Keep,
+        }
+    }
 }
 
 struct OpenUpvalue {
@@ -21,57 +57,169 @@ struct OpenUpvalue {
     upvalue: *mut Upvalue,
 }
 
-static VEC_SIZE: usize = 1024; // Default vec size for `VM::stack` and `VM::open_upvalues`
+static VEC_SIZE: usize = 1024; // Capacity of `VM::stack`
 static STACK_TRACE_SIZE: usize = 10; // Number of frames to print in a stack trace
+static MAX_TO_STRING_DEPTH: usize = 64; // Each nested `to_string` call recurses into `run`
+
+/// What dividing by zero does
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DivByZero {
+    /// Follow IEEE 754: `1 / 0` is `inf` and `0 / 0` is `nan`
+    #[default]
+    Ieee,
+    /// Report a runtime error
+    Error,
+}
 
 pub struct VM<'a, T: Write, U: Write> {
     call_stack: Vec<CallFrame>,
     current_frame: CallFrame,
-    stack: Vec<Value>,
+    stack: Stack,
     open_upvalues: Vec<OpenUpvalue>,
     gc: gc::GC,
     str_intern_table: StringInternTable,
     globals: Vec<Option<Value>>, // None means the variable is undefined
     global_var_names: Vec<String>,
+    constants: Vec<Value>, // The program's constant pool
     output_stream: &'a mut T,
     err_stream: &'a mut U,
+    exit_depth: usize, // `run` returns once the call stack shrinks to this depth
+    last_error: Option<RuntimeError>,
+    to_string_depth: usize, // Number of `to_string` calls in progress
+    running_finalizers: bool,
+    div_by_zero: DivByZero,
+    json_errors: bool, // Whether runtime errors are written as JSON
+    rng: Rng,          // Shared by the random number natives
 }
 
 impl<'a, T: Write, U: Write> VM<'a, T, U> {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         main_closure: *mut Closure,
         gc: gc::GC,
         str_intern_table: StringInternTable,
         global_var_names: Vec<String>,
         globals: Vec<Option<Value>>,
+        constants: Vec<Value>,
         output_stream: &'a mut T,
         err_stream: &'a mut U,
     ) -> Self {
         VM {
-            call_stack: vec![CallFrame {
-                closure: main_closure,
-                ip: 0,
-                stack_start: 0,
-            }],
-            current_frame: CallFrame {
-                closure: main_closure,
-                ip: 0,
-                stack_start: 0,
-            },
-            stack: Vec::with_capacity(VEC_SIZE),
-            open_upvalues: Vec::with_capacity(VEC_SIZE),
+            call_stack: vec![CallFrame::new(main_closure, 0, 0)],
+            current_frame: CallFrame::new(main_closure, 0, 0),
+            stack: Stack::with_capacity(VEC_SIZE),
+            open_upvalues: Vec::new(), // Rarely holds more than a few, so grows on demand
             gc,
             str_intern_table,
             globals,
             global_var_names,
+            constants,
             output_stream,
             err_stream,
+            exit_depth: 0,
+            last_error: None,
+            to_string_depth: 0,
+            running_finalizers: false,
+            div_by_zero: DivByZero::default(),
+            json_errors: false,
+            rng: Rng::default(),
+        }
+    }
+
+    /// Returns the function compiled from the top level of the program, until it returns
+    pub fn main_function(&self) -> Option<&Function> {
+        let frame = self.call_stack.first()?;
+
+        unsafe {
+            // SAFETY: GC guarantees that all pointers are valid
+            Some((*frame.closure).function())
+        }
+    }
+
+    /// Sets what dividing by zero does, [`DivByZero::Ieee`] by default
+    pub fn set_div_by_zero(&mut self, policy: DivByZero) {
+        self.div_by_zero = policy;
+    }
+
+    /// Writes runtime errors as one line of JSON each, with the message and the frames of the
+    /// stack trace, instead of for people to read
+    pub fn set_json_errors(&mut self, enabled: bool) {
+        self.json_errors = enabled;
+    }
+
+    /// Returns the program's constant pool, shared by the chunks of all its functions
+    pub fn constant_pool(&self) -> &[Value] {
+        &self.constants
+    }
+
+    /// Returns the value of the global variable with the given name, if it is defined
+    pub fn global(&self, name: &str) -> Option<Value> {
+        let index = self.global_var_names.iter().position(|n| n == name)?;
+
+        self.globals[index]
+    }
+
+    /// Calls `callee` with the given arguments from the host and runs it to completion, without
+    /// running the rest of the program. Usually called after `run` to invoke functions the
+    /// program defined, e.g. as callbacks. Runtime errors, including passing more than 255
+    /// arguments, are reported to the error stream as usual and returned
+    pub fn call_function(&mut self, callee: Value, args: &[Value]) -> Result<Value, RuntimeError> {
+        let base_depth = self.call_stack.len();
+        let base_stack = self.stack.len();
+        let saved_exit_depth = std::mem::replace(&mut self.exit_depth, base_depth);
+        self.last_error = None;
+
+        let result = self.run_call(callee, args);
+        self.exit_depth = saved_exit_depth;
+
+        match result {
+            Some(value) => Ok(value),
+            None => {
+                // Unwind whatever the failed call left behind
+                self.close_upvalues(base_stack);
+                self.stack.truncate(base_stack);
+                self.call_stack.truncate(base_depth);
+
+                if let Some(frame) = self.call_stack.last() {
+                    self.current_frame = *frame;
+                }
+
+                Err(self.last_error.take().unwrap_or(RuntimeError::CallFailed))
+            }
         }
     }
 
+    /// Pushes the callee and its arguments, then runs the call until it returns its result
+    fn run_call(&mut self, callee: Value, args: &[Value]) -> Option<Value> {
+        if args.len() > u8::MAX as usize {
+            self.runtime_error(RuntimeError::TooManyArguments(args.len()));
+            return None;
+        }
+
+        self.push(callee)?;
+
+        for arg in args {
+            self.push(*arg)?;
+        }
+
+        self.call_value(args.len() as u8)?;
+
+        // Natives and classes without an initializer return right away
+        if self.call_stack.len() > self.exit_depth {
+            self.run()?;
+        }
+
+        self.stack.pop()
+    }
+
     pub fn run(&mut self) -> Option<()> {
         loop {
-            match self.read_opcode() {
+            // Finalizers run between instructions, never in the middle of one
+            if self.gc.has_pending_finalizers() && !self.running_finalizers {
+                self.run_finalizers()?;
+            }
+
+            match self.read_opcode()? {
                 OpCode::Constant => {
                     let constant = self.read_constant();
                     self.push(constant)?;
@@ -80,6 +228,16 @@ impl<'a, T: Write, U: Write> VM<'a, T, U> {
                     let constant = self.read_constant_long();
                     self.push(constant)?;
                 }
+                OpCode::PoolConstant => {
+                    let index = self.read_int8();
+                    let constant = self.constants[index];
+                    self.push(constant)?;
+                }
+                OpCode::PoolConstantLong => {
+                    let index = self.read_int24();
+                    let constant = self.constants[index];
+                    self.push(constant)?;
+                }
                 OpCode::Nil => {
                     self.push(Value::Nil)?;
                 }
@@ -96,22 +254,53 @@ impl<'a, T: Write, U: Write> VM<'a, T, U> {
                     // Pop off the current frame
                     self.call_stack.pop();
 
-                    // If the call stack is empty, we're done
-                    // (we added an implicit return for the main function)
-                    if self.call_stack.is_empty() {
-                        return Some(());
-                    }
-
                     // Close upvalues for the current frame
                     self.close_upvalues(self.current_frame.stack_start);
 
-                    // Otherwise, pop off the arguments and the callee from the stack,
-                    // push the return value and set the current frame to the top of the call stack
+                    // Pop off the arguments and the callee from the stack and push the return
+                    // value
                     self.stack.truncate(self.current_frame.stack_start);
                     self.push(ret)?;
+
+                    // We're done once the call that started this run returns
+                    // (we added an implicit return for the main function)
+                    if self.call_stack.len() == self.exit_depth {
+                        return Some(());
+                    }
+
+                    // Otherwise, set the current frame to the top of the call stack
+                    let on_return = self.current_frame.on_return;
                     self.current_frame = *self.call_stack.last().unwrap();
+
+                    match on_return {
+                        OnReturn::Keep => {}
+                        OnReturn::Call(arg_count) => {
+                            // The callee goes where the receiver was, below the arguments
+                            let callee = self.stack.pop().unwrap();
+                            let len = self.stack.len();
+                            self.stack[len - (arg_count as usize) - 1] = callee;
+                            self.call_value(arg_count)?;
+                        }
+                        OnReturn::Equals { negate } => match ret {
+                            Value::Bool(equal) => {
+                                *self.stack.last_mut().unwrap() = Value::Bool(equal != negate);
+                            }
+                            _ => {
+                                self.runtime_error(RuntimeError::EqualsNotBool(ret.type_name()));
+                                return None;
+                            }
+                        },
+                    }
                 }
                 OpCode::Negate => match self.stack.last_mut() {
+                    Some(Value::Int(value)) => match value.checked_neg() {
+                        Some(negated) => *value = negated,
+                        // The smallest int has no positive counterpart
+                        None => {
+                            let negated = -(*value as f64);
+                            *self.stack.last_mut().unwrap() = Value::Number(negated);
+                        }
+                    },
                     Some(Value::Number(value)) => *value = -*value,
                     Some(_) => {
                         self.runtime_error(RuntimeError::NegateOperandNotNumber);
@@ -121,118 +310,141 @@ impl<'a, T: Write, U: Write> VM<'a, T, U> {
                         return None;
                     }
                 },
-                OpCode::Not => match self.stack.last_mut() {
-                    Some(Value::Bool(value)) => *value = !*value,
+                OpCode::BitNot => match self.stack.last_mut() {
+                    Some(Value::Int(value)) => *value = !*value,
                     Some(_) => {
-                        self.runtime_error(RuntimeError::NotOperandNotBool);
+                        self.runtime_error(RuntimeError::BitNotOperandNotInt);
                         return None;
                     }
                     _ => {
                         return None;
                     }
                 },
+                OpCode::Not => {
+                    let value = self.stack.last_mut()?;
+                    *value = Value::Bool(!value.is_truthy());
+                }
                 OpCode::Add => self.binary_add()?,
                 OpCode::Sub => {
                     self.binary_number_op(
-                        |l, r| *l -= r,
+                        i64::checked_sub,
+                        |l, r| l - r,
                         RuntimeError::BinaryOperandsNotNumbers("-"),
                     )?;
                 }
                 OpCode::Mult => {
                     self.binary_number_op(
-                        |l, r| *l *= r,
+                        i64::checked_mul,
+                        |l, r| l * r,
                         RuntimeError::BinaryOperandsNotNumbers("*"),
                     )?;
                 }
-                OpCode::Divide => self.binary_divide()?,
-                OpCode::Stringify => {
-                    if self.stack.is_empty() {
+                OpCode::Divide => {
+                    if self.div_by_zero == DivByZero::Error && self.is_division_by_zero() {
+                        self.runtime_error(RuntimeError::DivisionByZero);
                         return None;
                     }
 
-                    let value = self.stack.pop().unwrap();
-                    let rendered = format!("{value}");
-                    let str_ptr = self
-                        .str_intern_table
-                        .intern_owned(rendered, &mut self.gc);
-                    self.stack.push(Value::String(str_ptr));
-
-                    // Attempt to trigger a garbage collection cycle
-                    self.attempt_gc();
+                    // Division always produces a float
+                    self.binary_number_op(
+                        |_, _| None,
+                        |l, r| l / r,
+                        RuntimeError::BinaryOperandsNotNumbers("/"),
+                    )?;
+                }
+                OpCode::ShiftLeft => {
+                    self.binary_int_op(
+                        |l, r| l.wrapping_shl(r as u32),
+                        RuntimeError::BinaryOperandsNotInts("<<"),
+                    )?;
                 }
-                OpCode::Equal => {
-                    if self.stack.len() < 2 {
+                OpCode::ShiftRight => {
+                    self.binary_int_op(
+                        |l, r| l.wrapping_shr(r as u32),
+                        RuntimeError::BinaryOperandsNotInts(">>"),
+                    )?;
+                }
+                OpCode::BitAnd => {
+                    self.binary_int_op(|l, r| l & r, RuntimeError::BinaryOperandsNotInts("&"))?;
+                }
+                OpCode::BitOr => {
+                    self.binary_int_op(|l, r| l | r, RuntimeError::BinaryOperandsNotInts("|"))?;
+                }
+                OpCode::BitXor => {
+                    self.binary_int_op(|l, r| l ^ r, RuntimeError::BinaryOperandsNotInts("^"))?;
+                }
+                OpCode::Concat => {
+                    let count = self.read_int8();
+
+                    if self.stack.len() < count {
                         return None;
                     }
 
-                    let right = self.stack.pop().unwrap();
-                    let left = self.stack.last_mut().unwrap();
+                    let first = self.stack.len() - count;
+                    let mut concatenated = String::new();
 
-                    *left = Value::Bool(*left == right);
-                }
-                OpCode::NotEqual => {
-                    if self.stack.len() < 2 {
-                        return None;
+                    for i in first..first + count {
+                        concatenated.push_str(&self.stringify(self.stack[i])?);
                     }
 
-                    let right = self.stack.pop().unwrap();
-                    let left = self.stack.last_mut().unwrap();
+                    self.stack.truncate(first);
 
-                    *left = Value::Bool(*left != right);
+                    let str_ptr = self
+                        .str_intern_table
+                        .intern_owned(concatenated, &mut self.gc);
+                    self.stack.push(Value::String(str_ptr));
+
+                    // Attempt to trigger a garbage collection cycle
+                    self.attempt_gc();
                 }
+                OpCode::Equal => self.binary_equal(false)?,
+                OpCode::NotEqual => self.binary_equal(true)?,
                 OpCode::Greater => {
-                    self.binary_number_ordering_op(
-                        |l, r| l > r,
-                        RuntimeError::BinaryOperandsNotNumbers(">"),
-                    )?;
+                    self.binary_number_ordering_op(Ordering::is_gt, ">")?;
                 }
                 OpCode::GreaterEqual => {
-                    self.binary_number_ordering_op(
-                        |l, r| l >= r,
-                        RuntimeError::BinaryOperandsNotNumbers(">="),
-                    )?;
+                    self.binary_number_ordering_op(Ordering::is_ge, ">=")?;
                 }
                 OpCode::Less => {
-                    self.binary_number_ordering_op(
-                        |l, r| l < r,
-                        RuntimeError::BinaryOperandsNotNumbers("<"),
-                    )?;
+                    self.binary_number_ordering_op(Ordering::is_lt, "<")?;
                 }
                 OpCode::LessEqual => {
-                    self.binary_number_ordering_op(
-                        |l, r| l <= r,
-                        RuntimeError::BinaryOperandsNotNumbers("<="),
-                    )?;
+                    self.binary_number_ordering_op(Ordering::is_le, "<=")?;
                 }
-                OpCode::Ternary => {
-                    if self.stack.len() < 3 {
+                OpCode::Print => {
+                    if self.stack.is_empty() {
                         return None;
                     }
 
-                    let else_value = self.stack.pop().unwrap();
-                    let then_value = self.stack.pop().unwrap();
-                    let predicate = self.stack.last_mut().unwrap();
+                    // The value stays on the stack, rooted, while `to_string` runs
+                    let text = self.stringify(*self.stack.last().unwrap())?;
 
-                    match predicate {
-                        Value::Bool(value) => {
-                            if *value {
-                                *predicate = then_value;
-                            } else {
-                                *predicate = else_value;
-                            }
-                        }
-                        _ => {
-                            self.runtime_error(RuntimeError::TernaryPredicateNotBool);
-                            return None;
-                        }
-                    }
+                    self.stack.pop();
+                    let _ = writeln!(self.output_stream, "{text}");
                 }
-                OpCode::Print => {
-                    if self.stack.is_empty() {
+                OpCode::PrintN => {
+                    let count = self.read_int8();
+                    let newline = self.read_int8() != 0;
+
+                    if self.stack.len() < count {
                         return None;
                     }
 
-                    let _ = writeln!(self.output_stream, "{}", self.stack.pop().unwrap());
+                    let first = self.stack.len() - count;
+                    let mut line = String::new();
+
+                    for i in first..first + count {
+                        let separator = if i > first { " " } else { "" };
+                        let text = self.stringify(self.stack[i])?;
+                        let _ = write!(line, "{separator}{text}");
+                    }
+
+                    self.stack.truncate(first);
+                    let _ = write!(self.output_stream, "{line}");
+
+                    if newline {
+                        let _ = writeln!(self.output_stream);
+                    }
                 }
                 OpCode::Pop => {
                     if self.stack.is_empty() {
@@ -256,22 +468,46 @@ impl<'a, T: Write, U: Write> VM<'a, T, U> {
                 OpCode::GetGlobal => {
                     let index = self.read_int8();
 
-                    self.get_global(index)?
+                    self.get_global(index)?;
+                    self.rewrite_instruction(OpCode::GetDefinedGlobal, 1);
                 }
                 OpCode::GetGlobalLong => {
                     let index = self.read_int24();
 
-                    self.get_global(index)?
+                    self.get_global(index)?;
+                    self.rewrite_instruction(OpCode::GetDefinedGlobalLong, 3);
                 }
                 OpCode::SetGlobal => {
                     let index = self.read_int8();
 
-                    self.set_global(index)?
+                    self.set_global(index)?;
+                    self.rewrite_instruction(OpCode::SetDefinedGlobal, 1);
                 }
                 OpCode::SetGlobalLong => {
                     let index = self.read_int24();
 
-                    self.set_global(index)?
+                    self.set_global(index)?;
+                    self.rewrite_instruction(OpCode::SetDefinedGlobalLong, 3);
+                }
+                OpCode::GetDefinedGlobal => {
+                    let index = self.read_int8();
+
+                    self.get_defined_global(index)?
+                }
+                OpCode::GetDefinedGlobalLong => {
+                    let index = self.read_int24();
+
+                    self.get_defined_global(index)?
+                }
+                OpCode::SetDefinedGlobal => {
+                    let index = self.read_int8();
+
+                    self.set_defined_global(index)
+                }
+                OpCode::SetDefinedGlobalLong => {
+                    let index = self.read_int24();
+
+                    self.set_defined_global(index)
                 }
                 OpCode::GetLocal => {
                     let index = self.read_int8();
@@ -306,33 +542,79 @@ impl<'a, T: Write, U: Write> VM<'a, T, U> {
                 OpCode::JumpIfFalse => {
                     let jump_offset = self.read_int16();
 
-                    match self.stack.last() {
-                        Some(Value::Bool(value)) => {
-                            if !*value {
-                                *self.ip_as_mut() += jump_offset;
-                            }
-                        }
-                        Some(_) => {
-                            self.runtime_error(RuntimeError::ConditionNotBool);
-                            return None;
-                        }
-                        _ => unreachable!("No value in the stack"),
+                    if !self
+                        .stack
+                        .last()
+                        .expect("No value in the stack")
+                        .is_truthy()
+                    {
+                        *self.ip_as_mut() += jump_offset;
                     }
                 }
                 OpCode::JumpIfTrue => {
                     let jump_offset = self.read_int16();
 
-                    match self.stack.last() {
-                        Some(Value::Bool(value)) => {
-                            if *value {
-                                *self.ip_as_mut() += jump_offset;
-                            }
-                        }
-                        Some(_) => {
-                            self.runtime_error(RuntimeError::ConditionNotBool);
-                            return None;
-                        }
-                        _ => unreachable!("No value in the stack"),
+                    if self
+                        .stack
+                        .last()
+                        .expect("No value in the stack")
+                        .is_truthy()
+                    {
+                        *self.ip_as_mut() += jump_offset;
+                    }
+                }
+                OpCode::BuildList => {
+                    let count = self.read_int8();
+                    let start = self.stack.len() - count;
+                    let items = self.stack[start..].to_vec();
+
+                    let list = self.gc.alloc_list(List::new(items));
+                    self.stack.truncate(start);
+                    self.stack.push(list);
+
+                    // Attempt to trigger a garbage collection cycle
+                    self.attempt_gc();
+                }
+                OpCode::CollectRest => {
+                    let count = self.read_int8();
+                    let start = self.current_frame.stack_start + 1 + count;
+                    let items = self.stack[start..].to_vec();
+
+                    let list = self.gc.alloc_list(List::new(items));
+                    self.stack.truncate(start);
+                    self.push(list)?;
+
+                    // Attempt to trigger a garbage collection cycle
+                    self.attempt_gc();
+                }
+                OpCode::Unpack => {
+                    let count = self.read_int8();
+                    let value = self.stack.pop().unwrap();
+
+                    // Any other value is a single one, as returned by `return value;`
+                    let items = match value.as_list() {
+                        Some(list) => &list.items[..],
+                        None => std::slice::from_ref(&value),
+                    };
+
+                    if items.len() > count {
+                        self.runtime_error(RuntimeError::UnpackTooManyValues {
+                            expected: count as u8,
+                            got: items.len(),
+                        });
+                        return None;
+                    }
+
+                    // Missing values are bound to `nil`
+                    for i in 0..count {
+                        self.push(items.get(i).copied().unwrap_or_default())?;
+                    }
+                }
+                OpCode::JumpIfNil => {
+                    let jump_offset = self.read_int16();
+
+                    if let Some(Value::Nil) = self.stack.last() {
+                        *self.ip_as_mut() += jump_offset;
                     }
                 }
                 OpCode::Jump => {
@@ -345,11 +627,102 @@ impl<'a, T: Write, U: Write> VM<'a, T, U> {
 
                     *self.ip_as_mut() -= jump_offset;
                 }
+                OpCode::ForNum => {
+                    let counter = self.read_int8();
+                    let bound = self.read_int8();
+                    let jump_offset = self.read_int16();
+
+                    // Indices are relative to the current frame
+                    let stack_start = self.current_frame.stack_start;
+
+                    let counter = self.stack[stack_start + counter];
+                    let bound = self.stack[stack_start + bound];
+
+                    match Self::compare_numbers(counter, bound, Ordering::is_lt) {
+                        Some(true) => {}
+                        Some(false) => *self.ip_as_mut() += jump_offset,
+                        None => {
+                            self.runtime_error(RuntimeError::ComparisonOperandsNotNumbers {
+                                op: "<",
+                                left: counter.type_name(),
+                                right: bound.type_name(),
+                            });
+                            return None;
+                        }
+                    }
+                }
+                OpCode::IncrementLocal => {
+                    let index = self.read_int8();
+
+                    // Index is relative to the current frame
+                    let abs_index = self.current_frame.stack_start + index;
+
+                    match &mut self.stack[abs_index] {
+                        Value::Int(value) => {
+                            self.stack[abs_index] = value
+                                .checked_add(1)
+                                .map_or(Value::Number(*value as f64 + 1.0), Value::Int)
+                        }
+                        Value::Number(value) => *value += 1.0,
+                        _ => {
+                            self.runtime_error(RuntimeError::AddOperandsInvalid);
+                            return None;
+                        }
+                    }
+                }
                 OpCode::Call => {
                     let arg_count = self.read_int8() as u8;
 
                     self.call_value(arg_count)?
                 }
+                OpCode::Len => {
+                    let callee = self.stack[self.stack.len() - 2];
+
+                    match callee.as_native_func() {
+                        Some(native) if native.name == "len" => {
+                            let arg = self.stack.pop().unwrap();
+
+                            match native::length(arg) {
+                                Ok(len) => *self.stack.last_mut().unwrap() = len,
+                                Err(err) => {
+                                    self.runtime_error(RuntimeError::Native(err));
+                                    return None;
+                                }
+                            }
+                        }
+                        // `len` was redefined, call whatever it holds instead
+                        _ => self.call_value(1)?,
+                    }
+                }
+                OpCode::ForIn => {
+                    let iterable = self.read_int8();
+                    let index = self.read_int8();
+                    let jump_offset = self.read_int16();
+                    let stack_start = self.current_frame.stack_start;
+
+                    let Some(list) = self.stack[stack_start + iterable].as_list() else {
+                        let type_name = self.stack[stack_start + iterable].type_name();
+                        self.runtime_error(RuntimeError::NotIterable(type_name));
+                        return None;
+                    };
+
+                    let Value::Int(position) = self.stack[stack_start + index] else {
+                        unreachable!("The position of a `for-in` loop is always an int");
+                    };
+
+                    match list.items.get(position as usize).copied() {
+                        Some(item) => {
+                            self.stack[stack_start + index] = Value::Int(position + 1);
+                            self.push(item)?;
+                        }
+                        None => *self.ip_as_mut() += jump_offset,
+                    }
+                }
+                OpCode::TailCall => {
+                    let arg_count = self.read_int8() as u8;
+
+                    self.tail_call_value(arg_count)?
+                }
                 OpCode::Closure => {
                     let func = self.read_constant();
 
@@ -397,29 +770,15 @@ impl<'a, T: Write, U: Write> VM<'a, T, U> {
                     self.attempt_gc();
                 }
                 OpCode::GetProperty => {
-                    let name = self.read_constant();
-                    let name = name.as_string().expect("Property name must be a string");
-
-                    // Get the field from the instance
-                    let instance = self.stack.last().unwrap().as_class_instance();
-                    if instance.is_none() {
-                        self.runtime_error(RuntimeError::PropertyOnNonInstance);
-                        return None;
-                    }
-
-                    let instance = instance.unwrap();
-                    let field = instance.fields.get(name);
-
-                    if let Some(field) = field {
-                        *self.stack.last_mut().unwrap() = *field;
-                    } else {
-                        // Bind the method to the instance
-                        self.bind_method(instance.class, name)?;
-                    }
+                    self.get_property(false)?;
+                }
+                OpCode::GetPropertyOrNil => {
+                    self.get_property(true)?;
                 }
                 OpCode::SetProperty => {
                     let name = self.read_constant();
                     let name = name.as_string().expect("Property name must be a string");
+                    let cache = self.read_int8();
 
                     // Set the field on the instance
                     let value = self.stack.pop().unwrap();
@@ -434,11 +793,21 @@ impl<'a, T: Write, U: Write> VM<'a, T, U> {
                     // stack is not aliased elsewhere while we mutate it here.
                     let instance = unsafe { &mut *instance.unwrap() };
 
-                    instance.fields.insert(name.to_string(), value);
+                    let cached = self.chunk().property_caches[cache];
+                    let slot = if std::ptr::eq(instance.class, cached.class) {
+                        cached.slot
+                    } else {
+                        // SAFETY: GC guarantees that all pointers are valid
+                        let slot = unsafe { (*instance.class).field_slot(name) };
+                        self.update_property_cache(cache, instance.class, slot);
+                        slot
+                    };
+
+                    instance.set_field(slot, value);
                     *self.stack.last_mut().unwrap() = value;
                 }
-                OpCode::Method => {
-                    self.define_method()?;
+                opcode @ (OpCode::Method | OpCode::Getter | OpCode::StaticMethod) => {
+                    self.define_method(opcode)?;
                 }
                 OpCode::Invoke => {
                     self.invoke_method()?;
@@ -459,10 +828,16 @@ impl<'a, T: Write, U: Write> VM<'a, T, U> {
                     // popped from the stack so it is not aliased while we mutate it here.
                     let subclass = unsafe { &mut *subclass.unwrap() };
 
-                    // Copy all methods from the superclass to the subclass
+                    // Copy all methods and getters from the superclass to the subclass
                     for (name, method) in superclass.methods.iter() {
                         subclass.methods.insert(name.clone(), *method);
                     }
+                    for (name, getter) in superclass.getters.iter() {
+                        subclass.getters.insert(name.clone(), *getter);
+                    }
+                    for (name, method) in superclass.static_methods.iter() {
+                        subclass.static_methods.insert(name.clone(), *method);
+                    }
                 }
                 OpCode::GetSuper => {
                     let superclass = self.stack.pop().unwrap();
@@ -472,8 +847,17 @@ impl<'a, T: Write, U: Write> VM<'a, T, U> {
                         .expect("Method name must be a string");
 
                     // `this` is at the top of the stack and will be the receiver for
-                    // the bound method
-                    self.bind_method(superclass.as_class_ptr().unwrap(), method_name)?;
+                    // the bound method or the getter
+                    let superclass = superclass.as_class_ptr().unwrap();
+                    let getter = unsafe {
+                        // SAFETY: GC guarantees that all pointers are valid
+                        (*superclass).getters.get(method_name).copied()
+                    };
+
+                    match getter {
+                        Some(getter) => self.call(getter, 0)?,
+                        None => self.bind_method(superclass, method_name)?,
+                    }
                 }
                 OpCode::SuperInvoke => {
                     self.invoke_super_method()?;
@@ -492,20 +876,28 @@ impl<'a, T: Write, U: Write> VM<'a, T, U> {
         unsafe {
             // SAFETY: GC guarantees that all pointers are valid
             match callee {
-                Value::Closure(closure) => {
-                    let arity = (*closure).arity();
-
-                    self.call(closure, arity, arg_count)
-                }
+                Value::Closure(closure) => self.call(closure, arg_count),
                 Value::NativeFunc(native) => {
+                    let line = self.chunk().get_line_of(self.ip() - 1);
                     let args = &self.stack[self.stack.len() - (arg_count as usize)..];
-                    let ret = (*native).call(args);
+                    let mut ctx = NativeContext {
+                        gc: &mut self.gc,
+                        str_intern_table: &mut self.str_intern_table,
+                        out_stream: &mut *self.output_stream,
+                        err_stream: &mut *self.err_stream,
+                        line,
+                        rng: &mut self.rng,
+                    };
+                    let ret = (*native).call(&mut ctx, args);
 
                     match ret {
                         Ok(value) => {
                             self.stack
                                 .truncate(self.stack.len() - (arg_count as usize) - 1);
                             self.push(value)?;
+
+                            // Attempt to trigger a garbage collection cycle
+                            self.attempt_gc();
                             Some(())
                         }
                         Err(err) => {
@@ -526,10 +918,8 @@ impl<'a, T: Write, U: Write> VM<'a, T, U> {
                     let initializer = (*class).methods.get("init");
 
                     if let Some(init) = initializer {
-                        let arity = (**init).arity();
-
                         // Call the initializer with the instance as the receiver
-                        self.call(*init, arity, arg_count)?;
+                        self.call(*init, arg_count)?;
                     } else if arg_count != 0 {
                         self.runtime_error(RuntimeError::InitializerArgCount(arg_count));
                         return None;
@@ -538,14 +928,13 @@ impl<'a, T: Write, U: Write> VM<'a, T, U> {
                     Some(())
                 }
                 Value::BoundMethod(bound_method) => {
-                    let arity = (*(*bound_method).method).arity();
                     let len = self.stack.len();
 
                     // We reserved the first slot of the locals for the receiver. To utilize that we'll overwrite
                     // the callee with the receiver
                     self.stack[len - (arg_count as usize) - 1] =
                         Value::ClassInstance((*bound_method).receiver);
-                    self.call((*bound_method).method, arity, arg_count)
+                    self.call((*bound_method).method, arg_count)
                 }
                 _ => {
                     self.runtime_error(RuntimeError::NotCallable);
@@ -555,29 +944,218 @@ impl<'a, T: Write, U: Write> VM<'a, T, U> {
         }
     }
 
-    fn call(&mut self, closure: *mut Closure, arity: u8, arg_count: u8) -> Option<()> {
-        if arity != arg_count {
-            self.runtime_error(RuntimeError::ArgCountMismatch {
-                expected: arity,
-                got: arg_count,
-            });
+    /// Calls the value below the arguments on the stack in place of the current function. A
+    /// closure reuses the current call frame, so tail recursion runs in constant space. Any other
+    /// callee is called normally and its result returned by the `Return` that follows
+    fn tail_call_value(&mut self, arg_count: u8) -> Option<()> {
+        if self.stack.len() < (arg_count as usize) + 1 {
             return None;
         }
 
-        // Before setting the current frame to the new call frame we need to
-        // write back the current ip to the current frame on the call stack
-        self.call_stack.last_mut().unwrap().ip = self.current_frame.ip;
-        self.call_stack.push(CallFrame {
-            closure,
-            ip: 0,
-            stack_start: self.stack.len() - (arg_count as usize) - 1,
+        let callee_index = self.stack.len() - (arg_count as usize) - 1;
+
+        let Value::Closure(closure) = self.stack[callee_index] else {
+            return self.call_value(arg_count);
+        };
+
+        let ip = self.entry_point(closure, arg_count)?;
+
+        // The current function is done, its captured locals must outlive its frame
+        let stack_start = self.current_frame.stack_start;
+        self.close_upvalues(stack_start);
+
+        // Move the callee and the arguments down over the current frame
+        self.stack.remove_range(stack_start..callee_index);
+
+        // The caller still expects the value of the call it made
+        let on_return = self.current_frame.on_return;
+        self.current_frame = CallFrame::new(closure, ip, stack_start);
+        self.current_frame.on_return = on_return;
+        *self.call_stack.last_mut().unwrap() = self.current_frame;
+        Some(())
+    }
+
+    /// Converts `value` to the text `print` and string interpolation show. Instances of a class
+    /// with a `to_string` method are converted by calling it, which runs a nested `run` loop.
+    /// The caller must keep `value` rooted, e.g. on the stack, until this returns
+    fn stringify(&mut self, value: Value) -> Option<String> {
+        let method = value.as_class_instance().and_then(|instance| unsafe {
+            // SAFETY: GC guarantees that all pointers are valid
+            (*instance.class).methods.get("to_string").copied()
         });
 
+        let Some(method) = method else {
+            return Some(value.to_string());
+        };
+
+        if self.to_string_depth == MAX_TO_STRING_DEPTH {
+            self.runtime_error(RuntimeError::ToStringTooDeep(MAX_TO_STRING_DEPTH));
+            return None;
+        }
+
+        // Run the method until it returns to the current frame
+        let saved_exit_depth = std::mem::replace(&mut self.exit_depth, self.call_stack.len());
+        self.to_string_depth += 1;
+
+        let result = self
+            .push(value)
+            .and_then(|_| self.call(method, 0))
+            .and_then(|_| self.run())
+            .and_then(|_| self.stack.pop());
+
+        self.to_string_depth -= 1;
+        self.exit_depth = saved_exit_depth;
+        let result = result?;
+
+        // Resume the frame that is printing
+        self.current_frame = *self.call_stack.last().unwrap();
+
+        match result.as_string() {
+            // Copy the text out, the string may be collected once it is off the stack
+            Some(text) => Some(text.to_owned()),
+            None => {
+                self.runtime_error(RuntimeError::ToStringNotString(result.type_name()));
+                None
+            }
+        }
+    }
+
+    /// Replaces the top two values on the stack with whether they are equal, or unequal if
+    /// `negate` is set. When both are instances whose classes share an `equals` method, it is
+    /// called with the left one as `this` and decides once it returns, so `equals` may recurse as
+    /// deeply as any method. Any other values are equal if they are the same object or, for the
+    /// rest, the same value
+    fn binary_equal(&mut self, negate: bool) -> Option<()> {
+        if self.stack.len() < 2 {
+            return None;
+        }
+
+        let left = self.stack[self.stack.len() - 2];
+        let right = self.stack[self.stack.len() - 1];
+
+        // Requiring the same method makes `a == b` and `b == a` agree
+        let method = match (left.as_class_instance(), right.as_class_instance()) {
+            (Some(left), Some(right)) => unsafe {
+                // SAFETY: GC guarantees that all pointers are valid
+                let method = (*left.class).methods.get("equals").copied();
+                method
+                    .filter(|&method| (*right.class).methods.get("equals").copied() == Some(method))
+            },
+            _ => None,
+        };
+
+        let Some(method) = method else {
+            let equal = left == right;
+            self.stack.pop();
+            *self.stack.last_mut().unwrap() = Value::Bool(equal != negate);
+            return Some(());
+        };
+
+        // The operands are the receiver and the argument of the call
+        self.call_then(method, 1, OnReturn::Equals { negate })
+    }
+
+    /// Calls the `finalize` method of every instance the collector queued, oldest first, each
+    /// one until it returns. Finalizers run outside of the collector, so one that allocates may
+    /// trigger an ordinary collection. That collection keeps the instance being finalized alive,
+    /// as it is on the stack, and queues any instances it finds unreachable behind the rest
+    fn run_finalizers(&mut self) -> Option<()> {
+        self.running_finalizers = true;
+        let saved_exit_depth = std::mem::replace(&mut self.exit_depth, self.call_stack.len());
+
+        let mut result = Some(());
+        while let Some(instance) = self.gc.next_finalizer() {
+            let method = unsafe {
+                // SAFETY: GC guarantees that all pointers are valid, and only instances whose
+                // class defines `finalize` are queued
+                *(*(*instance).class).methods.get("finalize").unwrap()
+            };
+
+            result = self
+                .push(Value::ClassInstance(instance))
+                .and_then(|_| self.call(method, 0))
+                .and_then(|_| self.run())
+                .and_then(|_| self.stack.pop())
+                .map(|_| ());
+            if result.is_none() {
+                break;
+            }
+
+            // Resume the interrupted frame
+            self.current_frame = *self.call_stack.last().unwrap();
+        }
+
+        self.exit_depth = saved_exit_depth;
+        self.running_finalizers = false;
+        result
+    }
+
+    fn call(&mut self, closure: *mut Closure, arg_count: u8) -> Option<()> {
+        let ip = self.entry_point(closure, arg_count)?;
+
+        // Before setting the current frame to the new call frame we need to
+        // write back the current ip to the current frame on the call stack, if any
+        if let Some(frame) = self.call_stack.last_mut() {
+            frame.ip = self.current_frame.ip;
+        }
+
+        let stack_start = self.stack.len() - (arg_count as usize) - 1;
+        self.call_stack
+            .push(CallFrame::new(closure, ip, stack_start));
+
         // Set the current frame to the top of the call stack
         self.current_frame = *self.call_stack.last().unwrap();
         Some(())
     }
 
+    /// Calls `closure` like [`Self::call`], and has the caller handle the value it returns as
+    /// `on_return` says
+    fn call_then(
+        &mut self,
+        closure: *mut Closure,
+        arg_count: u8,
+        on_return: OnReturn,
+    ) -> Option<()> {
+        self.call(closure, arg_count)?;
+
+        self.current_frame.on_return = on_return;
+        *self.call_stack.last_mut().unwrap() = self.current_frame;
+        Some(())
+    }
+
+    /// Returns the offset a call to `closure` with `arg_count` arguments starts at, skipping the
+    /// defaults of the parameters that were passed. Reports an error if the count is wrong
+    fn entry_point(&mut self, closure: *mut Closure, arg_count: u8) -> Option<usize> {
+        let function = unsafe {
+            // SAFETY: GC guarantees that all pointers are valid
+            (*closure).function()
+        };
+        let (min, max) = (function.required_arity(), function.arity);
+
+        if arg_count >= min && (arg_count <= max || function.variadic) {
+            return Some(function.entry_point(arg_count));
+        }
+
+        if function.variadic {
+            self.runtime_error(RuntimeError::ArgCountBelowMinimum {
+                min,
+                got: arg_count,
+            });
+        } else if min == max {
+            self.runtime_error(RuntimeError::ArgCountMismatch {
+                expected: max,
+                got: arg_count,
+            });
+        } else {
+            self.runtime_error(RuntimeError::ArgCountOutOfRange {
+                min,
+                max,
+                got: arg_count,
+            });
+        }
+        None
+    }
+
     fn define_global(&mut self, index: usize) -> Option<()> {
         if self.stack.is_empty() {
             return None;
@@ -632,6 +1210,35 @@ impl<'a, T: Write, U: Write> VM<'a, T, U> {
         }
     }
 
+    /// Reads a global without checking that it is defined, see [`OpCode::GetDefinedGlobal`]
+    fn get_defined_global(&mut self, index: usize) -> Option<()> {
+        match self.globals[index] {
+            Some(value) => self.push(value),
+            None => unreachable!("global `{}` is not defined", self.global_var_names[index]),
+        }
+    }
+
+    /// Assigns a global without checking that it is defined, see [`OpCode::SetDefinedGlobal`]
+    fn set_defined_global(&mut self, index: usize) {
+        debug_assert!(self.globals[index].is_some());
+
+        // The assigned value stays on the stack as the value of the assignment
+        self.globals[index] = self.stack.last().copied();
+    }
+
+    /// Replaces the opcode of the instruction that just ran, which had `operand_len` bytes of
+    /// operands, with `opcode`. Lets an instruction skip checks whose outcome can no longer
+    /// change the next time it runs
+    fn rewrite_instruction(&mut self, opcode: OpCode, operand_len: usize) {
+        let at = self.ip() - operand_len - 1;
+
+        unsafe {
+            // SAFETY: GC guarantees that all pointers are valid, and nothing else borrows the
+            // chunk while the instruction runs
+            (&mut (*self.current_frame.chunk).code)[at] = opcode.into();
+        }
+    }
+
     fn get_local(&mut self, index: usize) -> Option<()> {
         // Index is relative to the current frame
         let abs_index = self.current_frame.stack_start + index;
@@ -691,24 +1298,35 @@ impl<'a, T: Write, U: Write> VM<'a, T, U> {
     fn get_upvalue(&mut self, index: usize) -> Option<()> {
         let upvalue = self.upvalues()[index];
 
-        unsafe {
+        let value = unsafe {
             // SAFETY: the upvalue is reachable from the current closure (a GC root),
-            // so it stays alive and `location` points to live storage.
-            self.push(*(*upvalue).location)
-        }
+            // so it stays alive
+            match (*upvalue).stack_index {
+                Some(stack_index) => self.stack[stack_index],
+                None => (*upvalue).closed,
+            }
+        };
+
+        self.push(value)
     }
 
     fn set_upvalue(&mut self, index: usize) {
         let upvalue = self.upvalues()[index];
+        let value = *self.stack.last().unwrap();
 
         unsafe {
             // SAFETY: the upvalue is reachable from the current closure (a GC root),
-            // so it stays alive and `location` points to live storage.
-            *(*upvalue).location = *self.stack.last().unwrap()
+            // so it stays alive
+            match (*upvalue).stack_index {
+                Some(stack_index) => self.stack[stack_index] = value,
+                None => (*upvalue).closed = value,
+            }
         }
     }
 
-    fn define_method(&mut self) -> Option<()> {
+    /// Defines the closure at the top of the stack as a method, getter or static method of the
+    /// class below it, depending on the `opcode` that defines it
+    fn define_method(&mut self, opcode: OpCode) -> Option<()> {
         let method_name = self.read_constant();
         let method_name = method_name
             .as_string()
@@ -733,10 +1351,17 @@ impl<'a, T: Write, U: Write> VM<'a, T, U> {
         // aliased elsewhere while we mutate it here.
         let class = unsafe { &mut *class };
 
-        // Add the method to the class
-        class
-            .methods
-            .insert(method_name.to_string(), method_closure);
+        // Add the method to the class, replacing an inherited method or getter of the same name.
+        // Static methods live apart, as they are looked up on the class instead
+        let (methods, others) = match opcode {
+            OpCode::Getter => (&mut class.getters, Some(&mut class.methods)),
+            OpCode::StaticMethod => (&mut class.static_methods, None),
+            _ => (&mut class.methods, Some(&mut class.getters)),
+        };
+        if let Some(others) = others {
+            others.remove(method_name);
+        }
+        methods.insert(method_name.to_string(), method_closure);
 
         Some(())
     }
@@ -748,11 +1373,29 @@ impl<'a, T: Write, U: Write> VM<'a, T, U> {
             .expect("Method name must be a string");
         let arg_count = self.read_int8() as u8;
         let len = self.stack.len();
-        let instance = self.stack[len - (arg_count as usize) - 1].as_class_instance_ptr();
+        let receiver = self.stack[len - (arg_count as usize) - 1];
+
+        // A class only has its static methods, which are called without binding `this`
+        if let Some(class) = receiver.as_class_ptr() {
+            let method = unsafe {
+                // SAFETY: GC guarantees that all pointers are valid
+                (*class).static_methods.get(method_name).copied()
+            };
+
+            return match method {
+                Some(method) => self.call(method, arg_count),
+                None => {
+                    self.runtime_error(RuntimeError::UndefinedMethod(method_name.to_string()));
+                    None
+                }
+            };
+        }
+
+        let instance = receiver.as_class_instance_ptr();
 
         if let Some(instance) = instance {
             // First check if this is a field access
-            let field = unsafe { (*instance).fields.get(method_name) };
+            let field = unsafe { (*instance).get_field(method_name) };
 
             if let Some(field) = field {
                 self.stack[len - (arg_count as usize) - 1] = *field;
@@ -788,7 +1431,15 @@ impl<'a, T: Write, U: Write> VM<'a, T, U> {
             let method = (*class).methods.get(method_name);
 
             if let Some(method) = method {
-                return self.call(*method, (**method).arity(), arg_count);
+                return self.call(*method, arg_count);
+            }
+
+            // Calling a getter calls the value it produces, once it returns. It runs on a copy of
+            // the receiver above the arguments, and its value then replaces the receiver
+            if let Some(getter) = (*class).getters.get(method_name).copied() {
+                let receiver = self.stack[self.stack.len() - arg_count as usize - 1];
+                self.push(receiver)?;
+                return self.call_then(getter, 0, OnReturn::Call(arg_count));
             }
 
             self.runtime_error(RuntimeError::UndefinedMethod(method_name.to_string()));
@@ -796,6 +1447,96 @@ impl<'a, T: Write, U: Write> VM<'a, T, U> {
         }
     }
 
+    /// Replaces the instance at the top of the stack with its property whose name is the next
+    /// constant. A missing property is an error, unless `or_nil` is set, in which case it
+    /// produces `nil`
+    fn get_property(&mut self, or_nil: bool) -> Option<()> {
+        let name = self.read_constant();
+        let name = name.as_string().expect("Property name must be a string");
+        let cache = self.read_int8();
+
+        // A class only has its static methods, produced as they are since they have no `this`
+        if let Some(class) = self.stack.last().unwrap().as_class_ptr() {
+            let method = unsafe {
+                // SAFETY: GC guarantees that all pointers are valid
+                (*class).static_methods.get(name).copied()
+            };
+
+            *self.stack.last_mut().unwrap() = match method {
+                Some(method) => Value::Closure(method),
+                None if or_nil => Value::Nil,
+                None => {
+                    self.runtime_error(RuntimeError::UndefinedProperty(name.to_string()));
+                    return None;
+                }
+            };
+            return Some(());
+        }
+
+        // Get the field from the instance
+        let instance = self.stack.last().unwrap().as_class_instance_ptr();
+        if instance.is_none() {
+            self.runtime_error(RuntimeError::PropertyOnNonInstance);
+            return None;
+        }
+
+        // SAFETY: GC guarantees that all pointers are valid
+        let instance = unsafe { &*instance.unwrap() };
+        let cached = self.chunk().property_caches[cache];
+        let field = if std::ptr::eq(instance.class, cached.class) {
+            instance.field(cached.slot)
+        } else {
+            let slot = unsafe {
+                // SAFETY: GC guarantees that all pointers are valid
+                (*instance.class).field_slots.get(name).copied()
+            };
+
+            slot.and_then(|slot| {
+                self.update_property_cache(cache, instance.class, slot);
+                instance.field(slot)
+            })
+        };
+
+        if let Some(field) = field {
+            *self.stack.last_mut().unwrap() = *field;
+            return Some(());
+        }
+
+        let (has_method, getter) = unsafe {
+            // SAFETY: GC guarantees that all pointers are valid
+            let class = &*instance.class;
+            (
+                class.methods.contains_key(name),
+                class.getters.get(name).copied(),
+            )
+        };
+
+        // A getter runs only when no field of its name is set. Its value replaces the instance,
+        // which is `this` for the call
+        if let Some(getter) = getter {
+            return self.call(getter, 0);
+        }
+
+        if or_nil && !has_method {
+            *self.stack.last_mut().unwrap() = Value::Nil;
+            Some(())
+        } else {
+            // Bind the method to the instance
+            self.bind_method(instance.class, name)
+        }
+    }
+
+    /// Remembers that instances of `class` keep the property of the property instruction owning
+    /// `cache` in `slot`
+    fn update_property_cache(&mut self, cache: usize, class: *const Class, slot: usize) {
+        unsafe {
+            // SAFETY: GC guarantees that all pointers are valid, and nothing else borrows the
+            // chunk while the instruction runs
+            (&mut (*self.current_frame.chunk).property_caches)[cache] =
+                PropertyCache { class, slot };
+        }
+    }
+
     fn bind_method(&mut self, class: *mut Class, method_name: &str) -> Option<()> {
         let method = unsafe { (*class).methods.get(method_name) };
 
@@ -817,10 +1558,15 @@ impl<'a, T: Write, U: Write> VM<'a, T, U> {
         Some(())
     }
 
-    fn binary_number_op<F>(&mut self, op: F, err: RuntimeError) -> Option<()>
-    where
-        F: FnOnce(&mut f64, f64),
-    {
+    /// Applies an arithmetic operator to the top two values on the stack. Two ints produce an int
+    /// through `int_op`, which returns `None` on overflow to fall back to `float_op`. Any other pair
+    /// of numbers is promoted to floats
+    fn binary_number_op(
+        &mut self,
+        int_op: impl Fn(i64, i64) -> Option<i64>,
+        float_op: impl Fn(f64, f64) -> f64,
+        err: RuntimeError,
+    ) -> Option<()> {
         if self.stack.len() < 2 {
             return None;
         }
@@ -828,22 +1574,30 @@ impl<'a, T: Write, U: Write> VM<'a, T, U> {
         let right = self.stack.pop().unwrap();
         let left = self.stack.last_mut().unwrap();
 
-        match (left, right) {
-            (Value::Number(left), Value::Number(right)) => {
-                op(left, right);
+        match Self::number_op(*left, right, int_op, float_op) {
+            Some(result) => {
+                *left = result;
                 Some(())
             }
-            _ => {
+            None => {
                 self.runtime_error(err);
                 None
             }
         }
     }
 
-    fn binary_number_ordering_op<F>(&mut self, op: F, err: RuntimeError) -> Option<()>
-    where
-        F: FnOnce(f64, f64) -> bool,
-    {
+    /// Returns true if the top two values on the stack are numbers and the top one is zero
+    fn is_division_by_zero(&self) -> bool {
+        match self.stack.len().checked_sub(2) {
+            Some(left) => {
+                self.stack[left].as_f64().is_some() && self.stack[left + 1].as_f64() == Some(0.0)
+            }
+            None => false,
+        }
+    }
+
+    /// Applies a bitwise operator to the top two values on the stack, both of which must be ints
+    fn binary_int_op(&mut self, op: impl Fn(i64, i64) -> i64, err: RuntimeError) -> Option<()> {
         if self.stack.len() < 2 {
             return None;
         }
@@ -851,9 +1605,9 @@ impl<'a, T: Write, U: Write> VM<'a, T, U> {
         let right = self.stack.pop().unwrap();
         let left = self.stack.last_mut().unwrap();
 
-        match (&left, right) {
-            (Value::Number(l), Value::Number(r)) => {
-                *left = Value::Bool(op(*l, r));
+        match (*left, right) {
+            (Value::Int(l), Value::Int(r)) => {
+                *left = Value::Int(op(l, r));
                 Some(())
             }
             _ => {
@@ -863,7 +1617,30 @@ impl<'a, T: Write, U: Write> VM<'a, T, U> {
         }
     }
 
-    fn binary_add(&mut self) -> Option<()> {
+    /// Applies an arithmetic operator to two values, see [`Self::binary_number_op`]. Returns
+    /// `None` if either value is not a number
+    fn number_op(
+        left: Value,
+        right: Value,
+        int_op: impl Fn(i64, i64) -> Option<i64>,
+        float_op: impl Fn(f64, f64) -> f64,
+    ) -> Option<Value> {
+        match (left, right) {
+            (Value::Int(l), Value::Int(r)) => Some(
+                int_op(l, r)
+                    .map_or_else(|| Value::Number(float_op(l as f64, r as f64)), Value::Int),
+            ),
+            _ => Some(Value::Number(float_op(left.as_f64()?, right.as_f64()?))),
+        }
+    }
+
+    /// Replaces the top two values on the stack with the result of comparing them. `lexeme` is the
+    /// operator, for the error if either one is not a number
+    fn binary_number_ordering_op(
+        &mut self,
+        op: impl Fn(Ordering) -> bool,
+        lexeme: &'static str,
+    ) -> Option<()> {
         if self.stack.len() < 2 {
             return None;
         }
@@ -871,11 +1648,43 @@ impl<'a, T: Write, U: Write> VM<'a, T, U> {
         let right = self.stack.pop().unwrap();
         let left = self.stack.last_mut().unwrap();
 
-        match (left, right) {
-            (Value::Number(left), Value::Number(right)) => {
-                *left += right;
+        match Self::compare_numbers(*left, right, op) {
+            Some(result) => {
+                *left = Value::Bool(result);
                 Some(())
             }
+            None => {
+                let err = RuntimeError::ComparisonOperandsNotNumbers {
+                    op: lexeme,
+                    left: left.type_name(),
+                    right: right.type_name(),
+                };
+                self.runtime_error(err);
+                None
+            }
+        }
+    }
+
+    /// Compares two numbers with `op`, ints exactly and anything else as floats. Unordered floats
+    /// (NaN) compare false. Returns `None` if either value is not a number
+    fn compare_numbers(left: Value, right: Value, op: impl Fn(Ordering) -> bool) -> Option<bool> {
+        let ordering = match (left, right) {
+            (Value::Int(l), Value::Int(r)) => Some(l.cmp(&r)),
+            _ => left.as_f64()?.partial_cmp(&right.as_f64()?),
+        };
+
+        Some(ordering.is_some_and(op))
+    }
+
+    fn binary_add(&mut self) -> Option<()> {
+        if self.stack.len() < 2 {
+            return None;
+        }
+
+        let right = self.stack.pop().unwrap();
+        let left = self.stack.last_mut().unwrap();
+
+        match (left, right) {
             (Value::String(left), Value::String(right)) => unsafe {
                 // SAFETY: GC guarantees that all pointers are valid
                 let mut concatenated_str: String =
@@ -892,37 +1701,22 @@ impl<'a, T: Write, U: Write> VM<'a, T, U> {
 
                 Some(())
             },
-            _ => {
-                self.runtime_error(RuntimeError::AddOperandsInvalid);
-                None
-            }
-        }
-    }
-
-    fn binary_divide(&mut self) -> Option<()> {
-        if self.stack.len() < 2 {
-            return None;
-        }
-
-        let right = self.stack.pop().unwrap();
-        let left = self.stack.last_mut().unwrap();
-
-        match (left, right) {
-            (Value::Number(left), Value::Number(right)) => {
-                *left /= right;
-                Some(())
-            }
-            _ => {
-                self.runtime_error(RuntimeError::BinaryOperandsNotNumbers("/"));
-                None
-            }
+            (left, right) => match Self::number_op(*left, right, i64::checked_add, |l, r| l + r) {
+                Some(result) => {
+                    *left = result;
+                    Some(())
+                }
+                None => {
+                    self.runtime_error(RuntimeError::AddOperandsInvalid);
+                    None
+                }
+            },
         }
     }
 
     /// Captures the local at the given index for the current frame
     fn capture_local(&mut self, index: usize) -> *mut Upvalue {
         let abs_index = self.current_frame.stack_start + index;
-        let location = &mut self.stack[abs_index] as *mut Value;
 
         // Search for an existing upvalue for this local, our `open_upvalues` array
         // is sorted by stack index, so we can use binary search
@@ -932,9 +1726,7 @@ impl<'a, T: Write, U: Write> VM<'a, T, U> {
         {
             Ok(index) => self.open_upvalues[index].upvalue,
             Err(index) => {
-                let upvalue = self
-                    .gc
-                    .alloc_upvalue_ptr(Upvalue::new(location, Value::default()));
+                let upvalue = self.gc.alloc_upvalue_ptr(Upvalue::new(abs_index));
 
                 self.open_upvalues.insert(
                     index,
@@ -965,11 +1757,10 @@ impl<'a, T: Write, U: Write> VM<'a, T, U> {
                 // SAFETY: GC guarantees that all pointers are valid
 
                 // Move the stack value to the upvalue's closed field
-                // and set the upvalue's location to the closed field
-                let upvalue = &mut *upvalue.upvalue;
+                let captured = &mut *upvalue.upvalue;
 
-                upvalue.closed = *upvalue.location;
-                upvalue.location = &mut upvalue.closed as *mut Value;
+                captured.closed = self.stack[upvalue.stack_index];
+                captured.stack_index = None;
             }
         }
     }
@@ -997,7 +1788,7 @@ impl<'a, T: Write, U: Write> VM<'a, T, U> {
         }
 
         // Mark all values that are reachable from the stack
-        for value in &self.stack {
+        for value in self.stack.iter() {
             self.gc.mark_value(*value);
         }
 
@@ -1009,6 +1800,9 @@ impl<'a, T: Write, U: Write> VM<'a, T, U> {
         // Mark all values that are reachable from the roots
         self.gc.trace_references();
 
+        // Keep unreachable instances with a finalizer alive until it has run
+        self.gc.queue_finalizers();
+
         // Clear all interned strings that are not marked
         self.str_intern_table.clear_unmarked(&mut self.gc);
 
@@ -1016,11 +1810,21 @@ impl<'a, T: Write, U: Write> VM<'a, T, U> {
         self.gc.sweep();
     }
 
-    // TRY: Stash the current frame's chunk in a local variable
+    /// Returns the chunk being executed. The current frame caches a pointer to it, so reading an
+    /// instruction does not go through the closure and its function. Every frame is built by
+    /// [`CallFrame::new`] and frame changes copy whole frames, so the pointer cannot go stale
     fn chunk(&self) -> &Chunk {
         unsafe {
-            // SAFETY: GC guarantees that all pointers are valid
-            (*self.current_frame.closure).chunk()
+            // SAFETY: GC guarantees that all pointers are valid, a function's chunk never moves
+            debug_assert!(
+                std::ptr::eq(
+                    self.current_frame.chunk,
+                    (*self.current_frame.closure).chunk()
+                ),
+                "the current frame's cached chunk does not belong to its closure"
+            );
+
+            &*self.current_frame.chunk
         }
     }
 
@@ -1040,7 +1844,7 @@ impl<'a, T: Write, U: Write> VM<'a, T, U> {
     }
 
     fn push(&mut self, value: Value) -> Option<()> {
-        if self.stack.len() >= VEC_SIZE {
+        if self.stack.is_full() {
             self.runtime_error(RuntimeError::StackOverflow(VEC_SIZE));
             return None;
         }
@@ -1049,8 +1853,16 @@ impl<'a, T: Write, U: Write> VM<'a, T, U> {
         Some(())
     }
 
-    fn read_opcode(&mut self) -> OpCode {
-        OpCode::from(self.read_byte())
+    fn read_opcode(&mut self) -> Option<OpCode> {
+        let byte = self.read_byte();
+
+        match OpCode::try_from(byte) {
+            Ok(opcode) => Some(opcode),
+            Err(_) => {
+                self.runtime_error(RuntimeError::InvalidOpcode(byte));
+                None
+            }
+        }
     }
 
     fn read_byte(&mut self) -> u8 {
@@ -1094,9 +1906,19 @@ impl<'a, T: Write, U: Write> VM<'a, T, U> {
         ret
     }
 
+    /// Reports a runtime error followed by a trace of the call stack. Frames replaced by tail
+    /// calls are gone, so the trace only shows the last function called in a chain of tail calls
     fn runtime_error(&mut self, err: RuntimeError) {
         // We have to write back the current ip to the current call frame on the call stack
-        self.call_stack.last_mut().unwrap().ip = self.current_frame.ip;
+        if let Some(frame) = self.call_stack.last_mut() {
+            frame.ip = self.current_frame.ip;
+        }
+
+        if self.json_errors {
+            self.runtime_error_json(&err);
+            self.last_error = Some(err);
+            return;
+        }
 
         let _ = writeln!(self.err_stream, "Runtime error: {err}");
         let rev_frame_iter = self.call_stack.iter().rev();
@@ -1115,7 +1937,53 @@ impl<'a, T: Write, U: Write> VM<'a, T, U> {
                 format!("{}()", function.name)
             };
 
-            let _ = writeln!(self.err_stream, "[line {}] in {}", line, function_name);
+            let _ = if function.source_name.is_empty() {
+                writeln!(self.err_stream, "[line {}] in {}", line, function_name)
+            } else {
+                writeln!(
+                    self.err_stream,
+                    "[{}:{}] in {}",
+                    function.source_name, line, function_name
+                )
+            };
+        }
+
+        self.last_error = Some(err);
+    }
+
+    /// Writes `err` and its stack trace as a JSON object on a single line, each frame with the
+    /// function, the line and, for named sources, the file
+    fn runtime_error_json(&mut self, err: &RuntimeError) {
+        let mut json = String::from("{\"message\":");
+        write_json_string(&err.to_string(), &mut json);
+        json.push_str(",\"severity\":\"error\",\"frames\":[");
+
+        for (i, frame) in self
+            .call_stack
+            .iter()
+            .rev()
+            .take(STACK_TRACE_SIZE)
+            .enumerate()
+        {
+            let function = unsafe {
+                // SAFETY: GC guarantees that all pointers are valid
+                (*frame.closure).function()
+            };
+
+            if i > 0 {
+                json.push(',');
+            }
+            json.push_str("{\"function\":");
+            write_json_string(&function.name, &mut json);
+            if !function.source_name.is_empty() {
+                json.push_str(",\"file\":");
+                write_json_string(&function.source_name, &mut json);
+            }
+            let line = function.chunk.get_line_of(frame.ip - 1);
+            let _ = write!(json, ",\"line\":{line}}}");
         }
+
+        json.push_str("]}");
+        let _ = writeln!(self.err_stream, "{json}");
     }
 }
@@ -0,0 +1,256 @@
+use std::env;
+use std::path::PathBuf;
+use std::process::Command;
+
+fn holo() -> Command {
+    Command::new(env!("CARGO_BIN_EXE_holo"))
+}
+
+#[test]
+fn gc_flags_with_tiny_threshold() {
+    let script = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("test_files")
+        .join("cli")
+        .join("gc_stress.holo");
+
+    // Collect on nearly every allocation
+    let output = holo()
+        .args(["--gc-threshold", "1", "--gc-growth", "1.01"])
+        .arg(&script)
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stderr).unwrap(), "");
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "125250\nnode 499\nitem 499\n499\n"
+    );
+}
+
+#[test]
+fn gc_flags_reject_invalid_values() {
+    let invalid_args = [
+        ["--gc-threshold", "0"],
+        ["--gc-threshold", "-5"],
+        ["--gc-threshold", "lots"],
+        ["--gc-growth", "1"],
+        ["--gc-growth", "NaN"],
+        ["--gc-growth", "inf"],
+        ["--max-errors", "0"],
+        ["--max-errors", "few"],
+    ];
+
+    for args in invalid_args {
+        let output = holo().args(args).arg("script.holo").output().unwrap();
+        let errors = String::from_utf8(output.stderr).unwrap();
+
+        assert_eq!(output.status.code(), Some(2), "{:?} was accepted", args);
+        assert!(
+            errors.starts_with(&format!("error: '{}' must be", args[0])),
+            "Unexpected error for {:?}:\n{}",
+            args,
+            errors
+        );
+    }
+}
+
+#[test]
+fn max_errors_counts_the_rest() {
+    let script = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("test_files")
+        .join("cli")
+        .join("many_errors.holo");
+
+    let output = holo()
+        .args(["--max-errors", "2"])
+        .arg(&script)
+        .output()
+        .unwrap();
+    let errors = String::from_utf8(output.stderr).unwrap();
+
+    assert_eq!(errors.matches("error: expected variable name").count(), 2);
+    assert!(errors.contains("2 | var 2;"));
+    assert!(!errors.contains("3 | var 3;"));
+    assert!(errors.ends_with("... 3 more errors\n"), "{errors}");
+
+    // Every error is written in full when there are fewer than the default limit
+    let output = holo().arg(&script).output().unwrap();
+    let errors = String::from_utf8(output.stderr).unwrap();
+
+    assert_eq!(errors.matches("error: expected variable name").count(), 5);
+    assert!(!errors.contains("more error"));
+
+    // JSON output always has every error
+    let output = holo()
+        .args(["--json-errors", "--max-errors", "2"])
+        .arg(&script)
+        .output()
+        .unwrap();
+
+    let errors = String::from_utf8(output.stderr).unwrap();
+
+    assert_eq!(output.status.code(), Some(2));
+    assert!(
+        errors.starts_with("error: '--max-errors' cannot be used with '--json-errors'\n"),
+        "{errors}"
+    );
+}
+
+#[test]
+fn strict_warns_about_empty_bodies() {
+    let script = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("test_files")
+        .join("cli")
+        .join("empty_body.holo");
+
+    let output = holo().arg("--strict").arg(&script).output().unwrap();
+    let warnings = String::from_utf8(output.stderr).unwrap();
+
+    assert!(output.status.success(), "{warnings}");
+    assert!(warnings.contains("empty body of 'while'"), "{warnings}");
+    assert!(warnings.contains("2 | while (i > 0);"), "{warnings}");
+    assert!(warnings.contains("empty body of 'if'"), "{warnings}");
+    assert!(!warnings.contains("empty body of 'else'"), "{warnings}");
+
+    // Without the flag an empty body is fine
+    let output = holo().arg(&script).output().unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stderr).unwrap(), "");
+}
+
+#[test]
+fn json_errors() {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("test_files")
+        .join("cli");
+
+    let output = holo()
+        .arg("--json-errors")
+        .arg(dir.join("compile_errors.holo"))
+        .output()
+        .unwrap();
+
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        concat!(
+            r#"{"line":1,"col":11,"message":"unexpected character '\\'","severity":"error"}"#,
+            "\n",
+            r#"{"line":3,"col":1,"message":"expected ';' after statement","severity":"error"}"#,
+            "\n",
+        )
+    );
+
+    let script = dir.join("runtime_error.holo");
+    let output = holo().arg("--json-errors").arg(&script).output().unwrap();
+    let script = script.to_str().unwrap();
+
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        format!(
+            concat!(
+                r#"{{"line":6,"col":7,"message":"unused variable 'unused'","severity":"warning"}}"#,
+                "\n",
+                r#"{{"message":"Operands to '+' must be two numbers or strings","severity":"error","#,
+                r#""frames":[{{"function":"fail","file":"{0}","line":2}},"#,
+                r#"{{"function":"<main>","file":"{0}","line":9}}]}}"#,
+                "\n",
+            ),
+            script
+        )
+    );
+}
+
+#[test]
+fn disasm_prints_every_function() {
+    let script = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("test_files")
+        .join("cli")
+        .join("disasm.holo");
+
+    let output = holo().arg("disasm").arg(&script).output().unwrap();
+    let disassembly = String::from_utf8(output.stdout).unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stderr).unwrap(), "");
+
+    // Nested functions and methods follow the chunk that defines them
+    let headers: Vec<&str> = disassembly
+        .lines()
+        .filter(|line| line.starts_with("=="))
+        .collect();
+    assert_eq!(
+        headers,
+        ["== <main> ==", "== outer ==", "== inner ==", "== greet =="]
+    );
+    assert!(disassembly.contains("METHOD \"greet\""));
+}
+
+#[test]
+fn disasm_imports_relative_to_the_script() {
+    let tests_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests");
+
+    // Run from another directory than the script's, which imports "modules/math.holo"
+    let output = holo()
+        .current_dir(&tests_dir)
+        .args(["disasm", "test_files/import/basic.holo"])
+        .output()
+        .unwrap();
+    let disassembly = String::from_utf8(output.stdout).unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stderr).unwrap(), "");
+    assert!(disassembly.contains("== square =="), "{disassembly}");
+
+    // The parse trace compiles the imports the same way
+    let output = holo()
+        .current_dir(&tests_dir)
+        .args(["parse-trace", "test_files/import/basic.holo"])
+        .output()
+        .unwrap();
+    let trace = String::from_utf8(output.stderr).unwrap();
+
+    assert!(output.status.success());
+    assert!(!trace.contains("error"), "{trace}");
+}
+
+#[test]
+fn parse_trace_nests_productions() {
+    let script = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("test_files")
+        .join("cli")
+        .join("parse_trace.holo");
+
+    let output = holo().arg("parse-trace").arg(&script).output().unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "");
+
+    // `*` binds tighter, so it is parsed inside the right operand of `+`
+    let expected = "\
+declaration `print` line 1
+  statement `print` line 1
+    expression `1` line 1
+      prefix Integer `1` line 1
+      end prefix Integer
+      infix Plus (Term) `+` line 1
+        prefix Integer `2` line 1
+        end prefix Integer
+        infix Star (Factor) `*` line 1
+          prefix Integer `3` line 1
+          end prefix Integer
+        end infix Star (Factor)
+      end infix Plus (Term)
+    end expression
+  end statement
+end declaration
+";
+    assert_eq!(String::from_utf8(output.stderr).unwrap(), expected);
+}
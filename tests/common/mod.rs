@@ -1,57 +1,15 @@
 use holo::*;
+use std::env;
 use std::fs;
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
+/// Runs the program at `path` through [`holo::compile`], like an embedder would. Unlike the
+/// `holo` binary, it compiles the source without its path, so stack traces don't name the file
 pub fn interpret<T: Write, U: Write>(path: PathBuf, output_stream: &mut T, err_stream: &mut U) {
     match fs::read_to_string(path) {
         Ok(source) => {
-            let mut gc = gc::GC::new();
-            let mut str_intern_table = table::StringInternTable::new();
-            let mut globals: Vec<Option<value::Value>> = Vec::new();
-
-            let (global_var_names, compiled_function) = {
-                let mut sym_table = sym_table::SymbolTable::new();
-                let native_funcs = native::get_native_funcs();
-
-                // Define native functions as global variables
-                for native_func in &native_funcs {
-                    sym_table.declare(&native_func.name);
-                    globals.push(Some(gc.alloc_native(native_func.clone())));
-                }
-
-                let compiler = compiler::Compiler::new(
-                    &source,
-                    "<main>",
-                    &mut gc,
-                    &mut str_intern_table,
-                    &mut sym_table,
-                    err_stream,
-                );
-                let compiled_function = compiler.compile();
-                let global_var_names = sym_table.into_names();
-
-                // We need to push `None` for each global variable that is not a native function
-                for _ in &global_var_names[native_funcs.len()..] {
-                    globals.push(None);
-                }
-
-                (global_var_names, compiled_function)
-            };
-
-            if let Some(function) = compiled_function {
-                let main_closure = gc.alloc_function_ptr(function);
-                let main_closure = gc.alloc_closure_ptr(value::Closure::new(main_closure, 0));
-
-                let mut vm = vm::VM::new(
-                    main_closure,
-                    gc,
-                    str_intern_table,
-                    global_var_names,
-                    globals,
-                    output_stream,
-                    err_stream,
-                );
+            if let Some(mut vm) = compile(&source, gc::GC::new(), output_stream, err_stream) {
                 let _res = vm.run();
             }
         }
@@ -60,3 +18,94 @@ pub fn interpret<T: Write, U: Write>(path: PathBuf, output_stream: &mut T, err_s
         }
     }
 }
+
+/// Runs every `.holo` file in `tests/test_files/<dir>` and compares its errors followed by its
+/// output against the file of the same name in the `expected` subdirectory
+#[allow(dead_code)]
+pub fn run_expected_output_tests(dir: &str) {
+    check_dir(dir, false);
+}
+
+/// Like [`run_expected_output_tests`], but runs each file under its path, so that it can import
+/// files relative to it. Paths in the errors are shown relative to the test directory
+#[allow(dead_code)]
+pub fn run_named_expected_output_tests(dir: &str) {
+    check_dir(dir, true);
+}
+
+/// Runs `tests/test_files/<dir>/<test_name>.holo` and compares its errors followed by its output
+/// against `tests/test_files/<dir>/expected/<test_name>`
+#[allow(dead_code)]
+pub fn run_expected_output_test(dir: &str, test_name: &str) {
+    check_expected_output(
+        &test_files_dir(dir).join(format!("{test_name}.holo")),
+        false,
+    );
+}
+
+fn test_files_dir(dir: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("test_files")
+        .join(dir)
+}
+
+fn check_dir(dir: &str, named: bool) {
+    for entry in fs::read_dir(test_files_dir(dir)).unwrap() {
+        let path = entry.unwrap().path();
+
+        // Skip the `expected` subdirectory and any other directory, such as the modules the
+        // import tests load
+        if path.is_dir() {
+            continue;
+        }
+
+        check_expected_output(&path, named);
+    }
+}
+
+fn check_expected_output(path: &Path, named: bool) {
+    println!("Running test: {}", path.display());
+
+    let mut output_stream: Vec<u8> = Vec::new();
+    let mut err_stream: Vec<u8> = Vec::new();
+
+    // Run the interpreter
+    if named {
+        holo::interpret(path.to_str().unwrap(), &mut output_stream, &mut err_stream);
+    } else {
+        interpret(path.to_path_buf(), &mut output_stream, &mut err_stream);
+    }
+
+    let mut errors = String::from_utf8(err_stream).unwrap();
+    if named {
+        let dir = format!("{}/", path.parent().unwrap().display());
+        errors = errors.replace(&dir, "");
+    }
+    let output = String::from_utf8(output_stream).unwrap();
+
+    // Load the expected output
+    let expected_path = path
+        .parent()
+        .unwrap()
+        .join("expected")
+        .join(path.file_stem().unwrap());
+    let expected = fs::read_to_string(&expected_path).unwrap_or_else(|e| {
+        panic!(
+            "Could not read expected output file for `{}`: {}",
+            path.display(),
+            e
+        )
+    });
+
+    let full_output = errors.trim_end().to_owned() + "\n" + &output;
+    let normalized_output = full_output.trim().replace("\r\n", "\n");
+    let normalized_expected = expected.trim().replace("\r\n", "\n");
+
+    assert_eq!(
+        normalized_output,
+        normalized_expected,
+        "Output mismatch for test `{}`",
+        path.display(),
+    );
+}
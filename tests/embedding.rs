@@ -0,0 +1,394 @@
+use holo::compiler::Compiler;
+use holo::const_pool::ConstantPool;
+use holo::error::RuntimeError;
+use holo::gc::GC;
+use holo::sym_table::SymbolTable;
+use holo::table::StringInternTable;
+use holo::value::{Closure, Function, Value};
+use holo::vm::DivByZero;
+use std::io::{self, Write};
+
+const SCRIPT: &str = r#"
+var calls = 0;
+
+fun add(a, b) {
+  calls = calls + 1;
+  return a + b;
+}
+
+fun fail() {
+  return nil + 1;
+}
+
+print "loaded";
+"#;
+
+#[test]
+fn call_function() {
+    let mut output_stream: Vec<u8> = Vec::new();
+    let mut err_stream: Vec<u8> = Vec::new();
+
+    let mut vm = holo::compile(SCRIPT, GC::new(), &mut output_stream, &mut err_stream)
+        .expect("script should compile");
+    vm.run().expect("script should run");
+
+    // Call a function the script defined without running the script again
+    let add = vm.global("add").expect("'add' should be defined");
+    let sum = vm.call_function(add, &[Value::Int(2), Value::Number(0.5)]);
+    assert_eq!(sum, Ok(Value::Number(2.5)));
+
+    let sum = vm.call_function(add, &[Value::Int(40), Value::Int(2)]);
+    assert_eq!(sum, Ok(Value::Int(42)));
+    assert_eq!(vm.global("calls"), Some(Value::Int(2)));
+
+    // Natives can be called too
+    let clock = vm.global("clock").unwrap();
+    assert!(matches!(vm.call_function(clock, &[]), Ok(Value::Number(_))));
+
+    // Errors are returned and leave the VM usable
+    let fail = vm.global("fail").unwrap();
+    assert_eq!(
+        vm.call_function(fail, &[]),
+        Err(RuntimeError::AddOperandsInvalid)
+    );
+    assert_eq!(
+        vm.call_function(add, &[Value::Int(1)]),
+        Err(RuntimeError::ArgCountMismatch {
+            expected: 2,
+            got: 1
+        })
+    );
+    assert_eq!(
+        vm.call_function(add, &[Value::Int(0); 256]),
+        Err(RuntimeError::TooManyArguments(256))
+    );
+    assert_eq!(
+        vm.call_function(add, &[Value::Int(1), Value::Int(1)]),
+        Ok(Value::Int(2))
+    );
+
+    drop(vm);
+    assert_eq!(String::from_utf8(output_stream).unwrap(), "loaded\n");
+
+    let errors = String::from_utf8(err_stream).unwrap();
+    assert!(errors.contains("Operands to '+' must be two numbers or strings\n[line 10] in fail()"));
+    assert!(errors.contains("Runtime error: Can't pass more than 255 arguments, got 256\n"));
+}
+
+#[test]
+fn div_by_zero_policy() {
+    const DIVIDE: &str = r#"
+fun divide(a, b) {
+  return a / b;
+}
+"#;
+
+    let mut output_stream: Vec<u8> = Vec::new();
+    let mut err_stream: Vec<u8> = Vec::new();
+
+    let mut vm = holo::compile(DIVIDE, GC::new(), &mut output_stream, &mut err_stream)
+        .expect("script should compile");
+    vm.run().expect("script should run");
+    let divide = vm.global("divide").unwrap();
+
+    // IEEE 754 by default
+    assert_eq!(
+        vm.call_function(divide, &[Value::Int(1), Value::Int(0)]),
+        Ok(Value::Number(f64::INFINITY))
+    );
+    assert!(matches!(
+        vm.call_function(divide, &[Value::Number(0.0), Value::Number(0.0)]),
+        Ok(Value::Number(value)) if value.is_nan()
+    ));
+
+    vm.set_div_by_zero(DivByZero::Error);
+    assert_eq!(
+        vm.call_function(divide, &[Value::Int(1), Value::Int(0)]),
+        Err(RuntimeError::DivisionByZero)
+    );
+    assert_eq!(
+        vm.call_function(divide, &[Value::Int(1), Value::Number(-0.0)]),
+        Err(RuntimeError::DivisionByZero)
+    );
+    assert_eq!(
+        vm.call_function(divide, &[Value::Int(1), Value::Int(4)]),
+        Ok(Value::Number(0.25))
+    );
+
+    // Non-numbers are still reported as such
+    assert_eq!(
+        vm.call_function(divide, &[Value::Nil, Value::Int(0)]),
+        Err(RuntimeError::BinaryOperandsNotNumbers("/"))
+    );
+
+    drop(vm);
+    let errors = String::from_utf8(err_stream).unwrap();
+    assert!(errors.starts_with("Runtime error: Division by zero\n[line 3] in divide()"));
+}
+
+#[test]
+fn stack_trace_names_source() {
+    let source = "fun fail() {\n  return nil + 1;\n}\nfail();\n";
+    let mut output_stream: Vec<u8> = Vec::new();
+    let mut err_stream: Vec<u8> = Vec::new();
+
+    let mut vm = holo::compile_named(
+        source,
+        "snippet.holo",
+        GC::new(),
+        &mut output_stream,
+        &mut err_stream,
+    )
+    .expect("source should compile");
+    assert_eq!(vm.run(), None);
+
+    drop(vm);
+    let errors = String::from_utf8(err_stream).unwrap();
+    assert!(
+        errors.ends_with("[snippet.holo:2] in fail()\n[snippet.holo:4] in <main>\n"),
+        "{errors}"
+    );
+}
+
+#[test]
+fn invalid_opcode() {
+    let mut output_stream: Vec<u8> = Vec::new();
+    let mut err_stream: Vec<u8> = Vec::new();
+    let mut gc = GC::new();
+
+    let mut function = Function::default();
+    function.chunk.write_byte(u8::MAX, 1);
+    let function = gc.alloc_function_ptr(function);
+    let closure = gc.alloc_closure_ptr(Closure::new(function, 0));
+
+    let mut vm = holo::vm::VM::new(
+        closure,
+        gc,
+        StringInternTable::new(),
+        Vec::new(),
+        Vec::new(),
+        Vec::new(),
+        &mut output_stream,
+        &mut err_stream,
+    );
+
+    // Corrupt bytecode is reported instead of crashing the interpreter
+    assert_eq!(vm.run(), None);
+
+    drop(vm);
+    let errors = String::from_utf8(err_stream).unwrap();
+    assert!(errors.starts_with("Runtime error: Invalid opcode 255\n[line 1] in"));
+}
+
+#[test]
+fn closure_upvalues_fit_their_allocation() {
+    let mut output_stream: Vec<u8> = Vec::new();
+    let mut err_stream: Vec<u8> = Vec::new();
+
+    let source = r#"
+fun make(a, b, c) {
+  fun sum() {
+    return a + b + c;
+  }
+  return sum;
+}
+
+var sum = make(1, 2, 3);
+"#;
+
+    let mut vm = holo::compile(source, GC::new(), &mut output_stream, &mut err_stream)
+        .expect("script should compile");
+    vm.run().expect("script should run");
+
+    let sum = vm.global("sum").unwrap();
+    let closure = sum.as_closure().expect("'sum' should be a closure");
+    assert_eq!(closure.function().upvalue_count, 3);
+    assert_eq!(closure.upvalues.len(), 3);
+    assert_eq!(closure.upvalues.capacity(), 3);
+    assert_eq!(vm.call_function(sum, &[]), Ok(Value::Int(6)));
+}
+
+#[test]
+fn compile_errors_as_data() {
+    let source = "print 1 +;\nvar = 2;\nprint \"ok\";\n";
+    let mut err_stream: Vec<u8> = Vec::new();
+    let mut gc = GC::new();
+    let mut str_intern_table = StringInternTable::new();
+    let mut sym_table = SymbolTable::new();
+    let mut const_pool = ConstantPool::new();
+
+    let compiler = Compiler::new(
+        source,
+        "",
+        "<main>",
+        &mut gc,
+        &mut str_intern_table,
+        &mut sym_table,
+        &mut const_pool,
+        &mut err_stream,
+    );
+
+    // Every error is returned, and none of them is printed
+    let errors = compiler.compile().expect_err("source should not compile");
+    let found: Vec<(usize, &str, String)> = errors
+        .iter()
+        .map(|err| (err.token.line, err.token.lexeme, err.kind.to_string()))
+        .collect();
+
+    assert_eq!(
+        found,
+        [
+            (1, ";", "expected expression".to_string()),
+            (2, "=", "expected variable name".to_string()),
+        ]
+    );
+    assert!(err_stream.is_empty());
+}
+
+/// An error stream whose writes all fail, like a closed stderr
+struct ClosedStream;
+
+impl Write for ClosedStream {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        Err(io::ErrorKind::BrokenPipe.into())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn compile_diagnostics_ignore_write_errors() {
+    // An unused variable warning, errors past the limit and the parse trace all get written
+    let source = "fun f() { var unused; }\nvar 1;\nvar 2;\nvar 3;\n";
+
+    for json_errors in [false, true] {
+        let mut err_stream = ClosedStream;
+        let mut gc = GC::new();
+        let mut str_intern_table = StringInternTable::new();
+        let mut sym_table = SymbolTable::new();
+        let mut const_pool = ConstantPool::new();
+
+        let mut compiler = Compiler::new(
+            source,
+            "",
+            "<main>",
+            &mut gc,
+            &mut str_intern_table,
+            &mut sym_table,
+            &mut const_pool,
+            &mut err_stream,
+        );
+        compiler.set_max_errors(1);
+        compiler.set_json_errors(json_errors);
+        compiler.set_parse_trace(true);
+
+        assert!(compiler.compile_and_report().is_none());
+    }
+}
+
+#[test]
+fn compile_error_spans_cover_token() {
+    let source = "var x = 1;\nclass Foo : Foo {}\n";
+    let mut err_stream: Vec<u8> = Vec::new();
+    let mut gc = GC::new();
+    let mut str_intern_table = StringInternTable::new();
+    let mut sym_table = SymbolTable::new();
+    let mut const_pool = ConstantPool::new();
+
+    let compiler = Compiler::new(
+        source,
+        "",
+        "<main>",
+        &mut gc,
+        &mut str_intern_table,
+        &mut sym_table,
+        &mut const_pool,
+        &mut err_stream,
+    );
+
+    let errors = compiler.compile().expect_err("source should not compile");
+
+    // The span is a byte range into the source, past the first line
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].span, 23..26);
+    assert_eq!(&source[errors[0].span.clone()], "Foo");
+}
+
+#[test]
+fn every_byte_has_a_line() {
+    let source = r#"
+class Counter {
+  init() { this.count = 0; }
+
+  bump(by) {
+    if (by > 0) {
+      this.count = this.count + by;
+    } else {
+      print "skipped";
+    }
+    return this;
+  }
+}
+
+fun run(n) {
+  var counter = Counter();
+  for (var i = 0; i < n; i = i + 1) {
+    if (i == 3) continue;
+    counter.bump(i);
+  }
+  while (counter.count > 10) counter.count = counter.count - 1;
+  return counter.count > 5 ? "big" : "small";
+}
+
+print run(6);
+"#;
+    let line_count = source.lines().count();
+    let mut err_stream: Vec<u8> = Vec::new();
+    let mut gc = GC::new();
+    let mut str_intern_table = StringInternTable::new();
+    let mut sym_table = SymbolTable::new();
+    let mut const_pool = ConstantPool::new();
+
+    let compiler = Compiler::new(
+        source,
+        "",
+        "<main>",
+        &mut gc,
+        &mut str_intern_table,
+        &mut sym_table,
+        &mut const_pool,
+        &mut err_stream,
+    );
+    let main = compiler.compile().expect("source should compile");
+
+    // Walk the main function and every function nested in it
+    let mut functions: Vec<&Function> = vec![&main];
+    let mut checked = 0;
+
+    while let Some(function) = functions.pop() {
+        let chunk = &function.chunk;
+
+        // Jumps are patched in place, so every byte is still covered by an entry
+        for offset in 0..chunk.code.len() {
+            let line = chunk.get_line_of(offset);
+            assert!(
+                (1..=line_count).contains(&line),
+                "byte {offset} of {} is on line {line}",
+                function.name
+            );
+        }
+
+        functions.extend(
+            function
+                .object_constants
+                .iter()
+                .filter_map(Value::as_function),
+        );
+        checked += 1;
+    }
+
+    // <main>, init, bump and run
+    assert_eq!(checked, 4);
+}
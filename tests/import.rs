@@ -0,0 +1,6 @@
+mod common;
+
+#[test]
+fn import() {
+    common::run_named_expected_output_tests("import");
+}
@@ -0,0 +1,6 @@
+mod common;
+
+#[test]
+fn loop_stmt() {
+    common::run_expected_output_tests("loop");
+}
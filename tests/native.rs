@@ -1,12 +1,11 @@
 mod common;
 
 use std::env;
-use std::fs;
 use std::path::PathBuf;
 
 #[test]
 fn clock() {
-    // Base directory containing the test inputs and expected outputs
+    // Base directory containing the test inputs
     let base_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
         .join("tests")
         .join("test_files")
@@ -31,44 +30,58 @@ fn clock() {
 
 #[test]
 fn clock_error() {
-    // Base directory containing the test inputs and expected outputs
-    let base_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
-        .join("tests")
-        .join("test_files")
-        .join("native");
-    let expected_dir = base_dir.join("expected");
-    let path = base_dir.join("clock_error.holo");
+    common::run_expected_output_test("native", "clock_error");
+}
 
-    let mut output_stream: Vec<u8> = Vec::new();
-    let mut err_stream: Vec<u8> = Vec::new();
+#[test]
+fn range() {
+    common::run_expected_output_test("native", "range");
+    common::run_expected_output_test("native", "range_zero_step");
+}
 
-    // Run the interpreter
-    common::interpret(path.clone(), &mut output_stream, &mut err_stream);
+#[test]
+fn assert_eq() {
+    common::run_expected_output_test("native", "assert_eq");
+}
 
-    let errors = String::from_utf8(err_stream).unwrap();
-    let output = String::from_utf8(output_stream).unwrap();
+#[test]
+fn debug() {
+    common::run_expected_output_test("native", "debug");
+}
 
-    // There should be no output
-    assert_eq!(output.trim(), "");
-
-    // Load the expected output
-    let test_name = path.file_name().unwrap().to_string_lossy().to_string();
-    let expected_path = expected_dir.join(path.file_stem().unwrap());
-    let expected = fs::read_to_string(&expected_path).unwrap_or_else(|e| {
-        panic!(
-            "Could not read expected output file for `{}`: {}",
-            test_name, e
-        )
-    });
-
-    let full_output = errors.trim_end().to_owned() + "\n" + &output;
-    let normalized_output = full_output.trim().replace("\r\n", "\n");
-    let normalized_expected = expected.trim().replace("\r\n", "\n");
-
-    assert_eq!(
-        normalized_output,
-        normalized_expected,
-        "Output mismatch for test `{}`",
-        path.as_os_str().to_str().unwrap(),
-    );
+#[test]
+fn checked_int() {
+    common::run_expected_output_test("native", "checked_int");
+    common::run_expected_output_test("native", "checked_int_non_integer");
+}
+
+#[test]
+fn format() {
+    common::run_expected_output_test("native", "format");
+    common::run_expected_output_test("native", "format_unmatched");
+}
+
+#[test]
+fn len() {
+    common::run_expected_output_test("native", "len");
+    common::run_expected_output_test("native", "len_invalid");
+}
+
+#[test]
+fn list() {
+    common::run_expected_output_test("native", "list");
+    common::run_expected_output_test("native", "list_not_a_list");
+}
+
+#[test]
+fn sleep() {
+    common::run_expected_output_test("native", "sleep");
+    common::run_expected_output_test("native", "sleep_not_a_number");
+    common::run_expected_output_test("native", "time_millis_error");
+}
+
+#[test]
+fn weak() {
+    common::run_expected_output_test("native", "weak");
+    common::run_expected_output_test("native", "weak_not_an_object");
 }